@@ -0,0 +1,78 @@
+// compares building the quadtree by inserting bodies one by one with building it from morton codes.
+// run with `cargo bench --bench quadtree_build`
+
+use std::hint::black_box;
+use std::time::{
+    Duration,
+    Instant,
+};
+
+use cgmath::Point2;
+use graviton::simulation::SimFloat;
+use graviton::simulation::quadtree::{
+    Positioned,
+    Quadtree,
+};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand_distr::{
+    Distribution,
+    Normal,
+};
+
+const BODY_COUNTS: [usize; 3] = [10_000, 100_000, 1_000_000];
+const RUNS: u32 = 5;
+
+#[derive(Debug, Clone, Copy)]
+struct Element(Point2<SimFloat>);
+
+impl Positioned for Element {
+    fn position(&self) -> Point2<SimFloat> {
+        self.0
+    }
+}
+
+// the fastest of several runs, reusing the tree like the simulation does
+fn measure(mut build: impl FnMut()) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            build();
+            Instant::now() - start
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let normal = Normal::new(0.0, 0.25).unwrap();
+
+    for num_bodies in BODY_COUNTS {
+        let elements: Vec<_> = (0..num_bodies)
+            .map(|_| Element(Point2::new(normal.sample(&mut rng), normal.sample(&mut rng))))
+            .collect();
+        let mut quadtree = Quadtree::<Element, ()>::new(2.0);
+
+        let insert = measure(|| {
+            quadtree.clear();
+            for &element in &elements {
+                // positions beyond the extent are simply skipped
+                let _ = quadtree.insert(element);
+            }
+            black_box(&quadtree);
+        });
+
+        let build_from = measure(|| {
+            black_box(quadtree.build_from(elements.iter().copied()));
+        });
+
+        println!(
+            "{:>9} bodies: insert {:>10.2?}, build_from {:>10.2?} ({:.1}x)",
+            num_bodies,
+            insert,
+            build_from,
+            insert.as_secs_f64() / build_from.as_secs_f64()
+        );
+    }
+}
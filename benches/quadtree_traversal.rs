@@ -0,0 +1,167 @@
+// compares the Barnes-Hut force walk over the quadtree's nodes by key with the walk over its flattened copy.
+// run with `cargo bench --bench quadtree_traversal`
+
+use std::hint::black_box;
+use std::time::{
+    Duration,
+    Instant,
+};
+
+use cgmath::{
+    InnerSpace,
+    Point2,
+    Vector2,
+};
+use graviton::simulation::quadtree::{
+    ContinueTraverse,
+    FlatQuadtree,
+    Positioned,
+    Quadtree,
+    QuadtreeChild,
+};
+use graviton::simulation::{
+    Pseudobody,
+    SimFloat,
+};
+use graviton::utility::should_approximate;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand_distr::{
+    Distribution,
+    Normal,
+};
+
+const BODY_COUNTS: [usize; 3] = [10_000, 100_000, 1_000_000];
+// forces are only calculated for this many bodies, which is enough to compare the walks
+const QUERY_COUNT: usize = 10_000;
+const THETA: SimFloat = 0.5;
+const SOFTENING: SimFloat = 0.005;
+const RUNS: u32 = 5;
+
+#[derive(Debug, Clone, Copy)]
+struct Element(Point2<SimFloat>);
+
+impl Positioned for Element {
+    fn position(&self) -> Point2<SimFloat> {
+        self.0
+    }
+}
+
+// the fastest of several runs
+fn measure(mut walk: impl FnMut()) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            walk();
+            Instant::now() - start
+        })
+        .min()
+        .unwrap()
+}
+
+// softened attraction without the gravitational constant, which doesn't matter for the timing
+fn attraction(
+    origin: Point2<SimFloat>,
+    position: Point2<SimFloat>,
+    mass: SimFloat,
+) -> Vector2<SimFloat> {
+    let direction = position - origin;
+    let distance_squared = direction.magnitude2() + SOFTENING * SOFTENING;
+    direction * (mass / (distance_squared * distance_squared.sqrt()))
+}
+
+fn keyed_force(
+    quadtree: &Quadtree<Element, Pseudobody>,
+    point: Point2<SimFloat>,
+) -> Vector2<SimFloat> {
+    let mut acceleration = Vector2::new(0.0, 0.0);
+    quadtree.traverse(|node, _| match &node.child_key {
+        QuadtreeChild::Elements(elements) => {
+            for &element_key in elements {
+                acceleration += attraction(point, quadtree.element(element_key).0, 1.0);
+            }
+            ContinueTraverse::Stop
+        }
+        QuadtreeChild::Node(_) => {
+            let distance = (node.data.position() - point).magnitude();
+            if should_approximate(node.width(), distance, THETA) {
+                acceleration += attraction(point, node.data.position(), node.data.mass());
+                ContinueTraverse::Stop
+            } else {
+                ContinueTraverse::Continue
+            }
+        }
+    });
+    acceleration
+}
+
+fn flat_force(
+    flat_quadtree: &FlatQuadtree<Element, Pseudobody>,
+    point: Point2<SimFloat>,
+) -> Vector2<SimFloat> {
+    let mut acceleration = Vector2::new(0.0, 0.0);
+    flat_quadtree.traverse(|node| {
+        if node.is_leaf() {
+            for element in flat_quadtree.leaf_elements(node) {
+                acceleration += attraction(point, element.0, 1.0);
+            }
+            return ContinueTraverse::Stop;
+        }
+
+        let distance = (node.data.position() - point).magnitude();
+        if should_approximate(node.width, distance, THETA) {
+            acceleration += attraction(point, node.data.position(), node.data.mass());
+            ContinueTraverse::Stop
+        } else {
+            ContinueTraverse::Continue
+        }
+    });
+    acceleration
+}
+
+fn main() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let normal = Normal::new(0.0, 0.25).unwrap();
+
+    for num_bodies in BODY_COUNTS {
+        let elements: Vec<_> = (0..num_bodies)
+            .map(|_| Element(Point2::new(normal.sample(&mut rng), normal.sample(&mut rng))))
+            .collect();
+        let mut quadtree = Quadtree::<Element, Pseudobody>::new(2.0);
+        quadtree.build_from(elements.iter().copied());
+        quadtree.aggregate(
+            |pseudobody, element| pseudobody.merge(Pseudobody::new(element.0, 1.0)),
+            Pseudobody::merge,
+        );
+
+        let mut flat_quadtree = FlatQuadtree::default();
+        let flatten = measure(|| {
+            quadtree.flatten_into(&mut flat_quadtree);
+            black_box(&flat_quadtree);
+        });
+
+        // every nth body, so the queries are spread over the whole distribution
+        let queries = elements.iter().step_by((num_bodies / QUERY_COUNT).max(1)).map(|element| element.0);
+
+        let keyed = measure(|| {
+            for point in queries.clone() {
+                black_box(keyed_force(&quadtree, point));
+            }
+        });
+
+        let flat = measure(|| {
+            for point in queries.clone() {
+                black_box(flat_force(&flat_quadtree, point));
+            }
+        });
+
+        println!(
+            "{:>9} bodies: keyed walk {:>10.2?}, flat walk {:>10.2?} ({:.1}x), flatten {:>10.2?}",
+            num_bodies,
+            keyed,
+            flat,
+            keyed.as_secs_f64() / flat.as_secs_f64(),
+            flatten
+        );
+    }
+}
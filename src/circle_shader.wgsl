@@ -1,3 +1,10 @@
+struct CameraUniform {
+    view: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
 struct VertexInput {
     @location(0) v_pos: vec2<f32>,
     @location(1) i_pos: vec2<f32>,
@@ -19,15 +26,19 @@ fn vs_main(
     // [-0.5, 0.5] for xy axes
     out.local_pos = model.v_pos;
     let world_pos = model.i_pos + model.v_pos * model.i_rad;
-    out.position = vec4<f32>(world_pos, 0.0, 1.0);
+    // larger radii map to larger depths below 1, which are drawn in front if depth testing is enabled
+    let depth = model.i_rad / (model.i_rad + 1.0);
+    out.position = camera.view * vec4<f32>(world_pos, depth, 1.0);
     out.color = model.i_col;
     return out;
 }
 
 @fragment
 fn fs_main(@location(0) local_pos: vec2<f32>, @location(1) color: vec4<f32>) -> @location(0) vec4<f32> {
+    // fade out over about one pixel at the edge, independent of how large the circle is on screen
     let dist = length(local_pos);
-    let alpha = smoothstep(0.5, 0.48, dist);
+    let edge = max(fwidth(dist), 1e-4);
+    let alpha = 1.0 - smoothstep(0.5 - edge, 0.5, dist);
     if (alpha < 0.01) {
         discard;
     }
@@ -0,0 +1,269 @@
+use std::time::Duration;
+
+use thiserror::Error;
+use wgpu::{
+    Color,
+    PresentMode,
+};
+
+use crate::rendering::rgb_hex;
+use crate::simulation::{
+    BlockTimesteps,
+    BoundaryMode,
+    ContactForce,
+    DEFAULT_SOFTENING,
+    ForceMode,
+    InvalidBoundaryError,
+    InvalidContactForceError,
+    MAX_TIMESTEP_LEVEL,
+    SimFloat,
+};
+use crate::simulation::export::CsvTarget;
+use crate::simulation::presets::{
+    InvalidMassDistributionError,
+    MassDistribution,
+    Preset,
+    UnknownPresetError,
+};
+
+pub const USAGE: &str = "\
+Usage: graviton [bench] [OPTIONS]
+
+Running with bench advances the simulation without opening a window and prints timings.
+
+Options:
+  --bodies <N>        number of bodies to generate [default: 1000000]
+  --theta <THETA>     Barnes-Hut approximation threshold [default: 0.5]
+  --softening <LEN>   softening length of the force between close bodies [default: 0.005]
+  --exact             calculate exact forces between all bodies instead of approximating them, which is slow
+  --boundary <MODE>   unbounded, or open, reflective or periodic with a half-width like periodic:2.0
+                      [default: unbounded]
+  --contact <STIFFNESS>[:<DAMPING>]
+                      push overlapping bodies apart like soft spheres so they pile up [default: damping 0]
+  --timestep <MS>     fixed timestep in milliseconds instead of the measured real time
+  --step-rate <HZ>    how often the simulation steps per second at most, slower steps run back to back
+                      [default: 100]
+  --warm-start        keep the quadtree between steps and only move bodies that left their leaf
+  --gpu-forces        calculate Barnes-Hut forces in a compute shader, needs the gpu-forces feature
+  --block-timesteps <LEVELS>
+                      split the timestep into up to 2^LEVELS steps for bodies with large accelerations,
+                      LEVELS is at most 20
+  --seed <SEED>       seed for generating bodies, also read from GRAVITON_SEED [default: random]
+  --preset <NAME>     initial conditions, one of blob, two-body, plummer, disk or star [default: blob]
+  --masses <DIST>     masses of the generated bodies, equal or power-law:<min>:<max>:<exponent> like
+                      power-law:0.1:10:2.35 [default: equal]
+  --msaa <SAMPLES>    multisampling sample count, 1 disables it [default: 4]
+  --background <HEX>  background color as a hex code like 1a1a2e or #ffffff [default: near black]
+  --depth             draw larger bodies in front of smaller ones using a depth buffer
+  --present-mode <MODE>
+                      fifo (vsync), mailbox or immediate (no vsync) [default: fifo]
+  --steps <N>         number of steps to run with bench [default: 10]
+  --csv <FILE>        append the bodies of every step to a CSV file
+  --csv-dir <DIR>     write the bodies of every step to a separate CSV file in a directory
+  --help              print this message";
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Unknown argument {0:?}.")]
+    UnknownArgument(String),
+    #[error("Missing value for {0}.")]
+    MissingValue(&'static str),
+    #[error("Invalid value {value:?} for {name}.")]
+    InvalidValue { name: &'static str, value: String },
+    #[error(transparent)]
+    UnknownPreset(#[from] UnknownPresetError),
+    #[error(transparent)]
+    InvalidMassDistribution(#[from] InvalidMassDistributionError),
+    #[error(transparent)]
+    InvalidBoundary(#[from] InvalidBoundaryError),
+    #[error(transparent)]
+    InvalidContactForce(#[from] InvalidContactForceError),
+    #[error("Help requested.")]
+    HelpRequested,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub num_bodies: usize,
+    pub pseudobody_threshold: SimFloat,
+    pub softening: SimFloat,
+    pub boundary_mode: BoundaryMode,
+    // overlapping bodies pass through each other if not given
+    pub contact_force: Option<ContactForce>,
+    pub force_mode: ForceMode,
+    pub warm_start: bool,
+    // ignored without the gpu-forces feature or compute shader support
+    pub gpu_forces: bool,
+
+    // the measured time between steps is used if no timestep is given
+    pub timestep: Option<Duration>,
+    // least time between the starts of two steps, the simulation thread sleeps for what is left after a step
+    pub step_interval: Duration,
+    // every body advances with the full timestep if not set
+    pub block_timesteps: Option<BlockTimesteps>,
+
+    // a random seed is picked if none is given
+    pub seed: Option<u64>,
+    pub preset: Preset,
+    pub masses: MassDistribution,
+
+    // lowered to what the adapter supports
+    pub sample_count: u32,
+    // falls back to fifo if not supported
+    pub present_mode: PresentMode,
+    pub depth_buffer: bool,
+    // the renderer's near black is used if not given
+    pub background: Option<Color>,
+
+    // run a fixed number of steps without a window instead of the interactive application
+    pub bench: bool,
+    pub steps: usize,
+
+    // bodies are only exported if a target is given
+    pub csv: Option<CsvTarget>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            num_bodies: 1_000_000,
+            pseudobody_threshold: 0.5,
+            softening: DEFAULT_SOFTENING,
+            boundary_mode: BoundaryMode::default(),
+            contact_force: None,
+            force_mode: ForceMode::default(),
+            warm_start: false,
+            gpu_forces: false,
+            timestep: None,
+            step_interval: Duration::from_millis(10),
+            block_timesteps: None,
+            seed: None,
+            preset: Preset::default(),
+            masses: MassDistribution::default(),
+            sample_count: 4,
+            present_mode: PresentMode::Fifo,
+            depth_buffer: false,
+            background: None,
+            bench: false,
+            steps: 10,
+            csv: None,
+        }
+    }
+}
+
+impl Config {
+    /// Parses command line arguments, without the program name. Options that aren't given keep their
+    /// default value.
+    pub fn from_args<I>(args: I) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut config = Self::default();
+        let mut args = args.into_iter().peekable();
+
+        if args.next_if(|arg| arg == "bench").is_some() {
+            config.bench = true;
+        }
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--bodies" => config.num_bodies = parse_value("--bodies", args.next())?,
+                "--theta" => config.pseudobody_threshold = parse_value("--theta", args.next())?,
+                "--softening" => config.softening = parse_value("--softening", args.next())?,
+                "--exact" => config.force_mode = ForceMode::Exact,
+                "--warm-start" => config.warm_start = true,
+                "--gpu-forces" => config.gpu_forces = true,
+                "--boundary" => {
+                    config.boundary_mode = args.next().ok_or(ConfigError::MissingValue("--boundary"))?.parse()?
+                }
+                "--contact" => {
+                    config.contact_force = Some(args.next().ok_or(ConfigError::MissingValue("--contact"))?.parse()?)
+                }
+                "--timestep" => {
+                    let millis: f64 = parse_value("--timestep", args.next())?;
+                    config.timestep = Some(
+                        Duration::try_from_secs_f64(millis / 1000.0).map_err(|_| ConfigError::InvalidValue {
+                            name: "--timestep",
+                            value: millis.to_string(),
+                        })?,
+                    );
+                }
+                "--step-rate" => {
+                    let rate: f64 = parse_value("--step-rate", args.next())?;
+                    config.step_interval =
+                        Duration::try_from_secs_f64(1.0 / rate).map_err(|_| ConfigError::InvalidValue {
+                            name: "--step-rate",
+                            value: rate.to_string(),
+                        })?;
+                }
+                "--block-timesteps" => {
+                    let max_level: u32 = parse_value("--block-timesteps", args.next())?;
+                    if max_level > MAX_TIMESTEP_LEVEL {
+                        return Err(ConfigError::InvalidValue {
+                            name: "--block-timesteps",
+                            value: max_level.to_string(),
+                        });
+                    }
+                    config.block_timesteps = Some(BlockTimesteps::new(max_level));
+                }
+                "--seed" => config.seed = Some(parse_value("--seed", args.next())?),
+                "--preset" => config.preset = args.next().ok_or(ConfigError::MissingValue("--preset"))?.parse()?,
+                "--masses" => config.masses = args.next().ok_or(ConfigError::MissingValue("--masses"))?.parse()?,
+                "--steps" => config.steps = parse_value("--steps", args.next())?,
+                "--csv" => config.csv = Some(CsvTarget::File(parse_value("--csv", args.next())?)),
+                "--csv-dir" => config.csv = Some(CsvTarget::Directory(parse_value("--csv-dir", args.next())?)),
+                "--msaa" => config.sample_count = parse_value("--msaa", args.next())?,
+                "--depth" => config.depth_buffer = true,
+                "--background" => {
+                    let value = args.next().ok_or(ConfigError::MissingValue("--background"))?;
+                    let hex = value.strip_prefix('#').unwrap_or(&value);
+                    config.background = match u32::from_str_radix(hex, 16) {
+                        Ok(hex_code) if hex.len() == 6 => Some(rgb_hex(hex_code)),
+                        _ => return Err(ConfigError::InvalidValue { name: "--background", value }),
+                    };
+                }
+                "--present-mode" => {
+                    let value = args.next().ok_or(ConfigError::MissingValue("--present-mode"))?;
+                    config.present_mode = match value.as_str() {
+                        "fifo" => PresentMode::Fifo,
+                        "mailbox" => PresentMode::Mailbox,
+                        "immediate" => PresentMode::Immediate,
+                        _ => return Err(ConfigError::InvalidValue { name: "--present-mode", value }),
+                    };
+                }
+                "--help" | "-h" => return Err(ConfigError::HelpRequested),
+                _ => return Err(ConfigError::UnknownArgument(arg)),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(
+    name: &'static str,
+    value: Option<String>,
+) -> Result<T, ConfigError> {
+    let value = value.ok_or(ConfigError::MissingValue(name))?;
+    value.parse().map_err(|_| ConfigError::InvalidValue { name, value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Config, ConfigError> {
+        Config::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn block_timesteps_are_limited() {
+        let config = parse(&["--block-timesteps", "20"]).unwrap();
+        assert_eq!(config.block_timesteps.unwrap().max_level, MAX_TIMESTEP_LEVEL);
+
+        assert!(matches!(
+            parse(&["--block-timesteps", "32"]),
+            Err(ConfigError::InvalidValue { name: "--block-timesteps", .. })
+        ));
+    }
+}
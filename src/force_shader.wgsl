@@ -0,0 +1,94 @@
+// Barnes-Hut accelerations of many bodies at once, one thread per body. the quadtree is flattened in
+// depth-first order, so the first child of a twig directly follows it and `next` points past its subtree.
+// the nodes can be walked front to back without a stack by jumping to `next` whenever a node is approximated
+// or is a leaf
+
+struct Params {
+    theta: f32,
+    softening_squared: f32,
+    gravitational_constant: f32,
+    node_count: u32,
+    body_count: u32,
+    // number of threads in a row of the dispatch, bodies beyond it are handled by the following rows
+    threads_per_row: u32,
+};
+
+struct Node {
+    // the pseudobody of the node
+    position: vec2<f32>,
+    mass: f32,
+    width: f32,
+    next: u32,
+    first_element: u32,
+    // 0 for twigs, leaves always hold at least one element
+    element_count: u32,
+};
+
+struct Element {
+    position: vec2<f32>,
+    mass: f32,
+    body: u32,
+};
+
+struct Body {
+    position: vec2<f32>,
+    body: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+@group(0) @binding(1)
+var<storage, read> nodes: array<Node>;
+@group(0) @binding(2)
+var<storage, read> elements: array<Element>;
+@group(0) @binding(3)
+var<storage, read> bodies: array<Body>;
+@group(0) @binding(4)
+var<storage, read_write> accelerations: array<vec2<f32>>;
+
+// the acceleration towards a mass at `position` of a body at `origin`, with plummer softening
+fn attraction(origin: vec2<f32>, position: vec2<f32>, mass: f32) -> vec2<f32> {
+    let direction = position - origin;
+    let distance_squared = dot(direction, direction) + params.softening_squared;
+    if distance_squared <= 0.0 {
+        return vec2<f32>(0.0, 0.0);
+    }
+
+    let distance_cubed = distance_squared * sqrt(distance_squared);
+    return direction * (params.gravitational_constant * mass / distance_cubed);
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.y * params.threads_per_row + id.x;
+    if index >= params.body_count {
+        return;
+    }
+
+    let body = bodies[index];
+    var acceleration = vec2<f32>(0.0, 0.0);
+    var node_index = 0u;
+    while node_index < params.node_count {
+        let node = nodes[node_index];
+        if node.element_count > 0u {
+            for (var i = node.first_element; i < node.first_element + node.element_count; i++) {
+                let element = elements[i];
+                if element.body != body.body {
+                    acceleration += attraction(body.position, element.position, element.mass);
+                }
+            }
+            node_index = node.next;
+        } else {
+            // same criterion as should_approximate on the CPU
+            let distance = length(node.position - body.position);
+            if distance > 0.0 && node.width < params.theta * distance {
+                acceleration += attraction(body.position, node.position, node.mass);
+                node_index = node.next;
+            } else {
+                node_index += 1u;
+            }
+        }
+    }
+
+    accelerations[index] = acceleration;
+}
@@ -1,3 +1,10 @@
+struct CameraUniform {
+    view: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
 struct VertexInput {
     @location(0) position: vec2<f32>,
     @location(1) color: vec4<f32>,
@@ -14,7 +21,7 @@ fn vs_main(
 ) -> VertexOutput {
     var out: VertexOutput;
     out.color = model.color;
-    out.clip_position = vec4<f32>(model.position, 0.0, 1.0);
+    out.clip_position = camera.view * vec4<f32>(model.position, 0.0, 1.0);
     return out;
 }
 
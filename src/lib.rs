@@ -1,43 +1,50 @@
+use std::sync::atomic::{
+    AtomicBool,
+    AtomicU32,
+    Ordering,
+};
 use std::sync::{
     Arc,
     Mutex,
 };
-use std::thread::JoinHandle;
 use std::time::{
     Duration,
     Instant,
+    SystemTime,
+    UNIX_EPOCH,
 };
 
 use cgmath::{
     Point2,
     Vector2,
-    Zero,
 };
-use pipeline::Pipeline;
-use rand::distr::{
-    Distribution,
-    Uniform,
+use config::{
+    Config,
+    ConfigError,
 };
-use rand::rng;
-use rand_distr::Normal;
+use pipeline::Pipeline;
 use rendering::RenderState;
-use simulation::quadtree::Quadtree;
+use rendering::source::{
+    BodyFrame,
+    FrameRequest,
+};
+use simulation::export::CsvExporter;
+use simulation::presets::MassDistribution;
 use simulation::{
     Body,
     Simulation,
+    StepStats,
+    presets,
 };
-use wgpu::{
-    Color,
-    SurfaceError,
-};
-use winit::dpi::{
-    PhysicalSize,
-    Size,
-};
+use utility::triple_buffer::triple_buffer;
+use wgpu::SurfaceError;
+use winit::dpi::PhysicalPosition;
 use winit::event::{
     ElementState,
     Event,
     KeyEvent,
+    MouseButton,
+    MouseScrollDelta,
     WindowEvent,
 };
 use winit::event_loop::EventLoop;
@@ -45,21 +52,61 @@ use winit::keyboard::{
     KeyCode,
     PhysicalKey,
 };
-use winit::window::{
-    Window,
-    WindowBuilder,
-};
+use winit::window::WindowBuilder;
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
-use crate::rendering::rgb;
+pub mod config;
+pub mod pipeline;
+pub mod rendering;
+pub mod simulation;
+pub mod utility;
+
+// timestep used when single-stepping a paused simulation without a fixed timestep
+const SINGLE_STEP_DT: Duration = Duration::from_millis(10);
 
-mod pipeline;
-mod rendering;
-mod simulation;
-mod utility;
+// timestep of the headless benchmark
+const HEADLESS_DT: Duration = Duration::from_millis(10);
 
+// consecutive frames with a lost surface after which the surface is recreated instead of reconfigured
+const MAX_SURFACE_RECONFIGURES: u32 = 3;
+
+// change of the opening angle per key press and the largest one that can be set
+const THRESHOLD_STEP: f32 = 0.05;
+const MAX_THRESHOLD: f32 = 2.0;
+
+// factor the time scale changes by per key press and its limits. faster time runs several steps per frame,
+// so large scales get slow with many bodies
+const TIME_SCALE_FACTOR: f32 = 2.0;
+const MIN_TIME_SCALE: f32 = 1.0 / 16.0;
+const MAX_TIME_SCALE: f32 = 16.0;
+
+// velocity of spawned bodies per world unit the cursor was dragged
+const SPAWN_VELOCITY_SCALE: f32 = 1.0;
+
+fn spawned_body(
+    position: Point2<f32>,
+    velocity: Vector2<f32>,
+) -> Body {
+    Body::at(position).velocity(velocity).build()
+}
+
+// environment variable holding the seed used to generate the initial bodies
+const SEED_VARIABLE: &str = "GRAVITON_SEED";
+
+fn seed_from_env() -> Option<u64> {
+    let value = std::env::var(SEED_VARIABLE).ok()?;
+    match value.parse() {
+        Ok(seed) => Some(seed),
+        Err(e) => {
+            log::warn!("Ignoring invalid {} {:?}: {}", SEED_VARIABLE, value, e);
+            None
+        }
+    }
+}
+
+/// Runs the application with a configuration parsed from the command line arguments.
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
 pub async fn run() {
     cfg_if::cfg_if! {
@@ -71,58 +118,207 @@ pub async fn run() {
         }
     }
 
-    let num_bodies = 1000000;
+    match Config::from_args(std::env::args().skip(1)) {
+        Ok(config) if config.bench => {
+            let seed = config.seed.or_else(seed_from_env).unwrap_or_else(rand::random);
+            run_headless(config.num_bodies, config.masses, config.steps, config.warm_start, seed);
+        }
+        Ok(config) => run_with_config(config).await,
+        Err(ConfigError::HelpRequested) => println!("{}", config::USAGE),
+        Err(e) => eprintln!("{}\n\n{}", e, config::USAGE),
+    }
+}
+
+/// Advances a simulation of `num_bodies` bodies by `steps` fixed steps without any rendering and prints
+/// how long the phases of every step took.
+pub fn run_headless(
+    num_bodies: usize,
+    masses: MassDistribution,
+    steps: usize,
+    warm_start: bool,
+    seed: u64,
+) {
+    let bodies = presets::gaussian_blob(num_bodies, masses, seed);
+    let mut simulation =
+        Simulation::new(bodies.into_iter(), Config::default().pseudobody_threshold).with_warm_start(warm_start);
+    println!("Running {} steps with {} bodies and seed {}", steps, num_bodies, seed);
+
+    let mut total = StepStats::default();
+    for step in 0..steps {
+        let start = Instant::now();
+        let stats = simulation.advance(HEADLESS_DT.as_secs_f32());
+        let duration = Instant::now() - start;
+
+        println!(
+            concat!(
+                "step {:>4}: tree build {:>10.3?}, aggregate {:>10.3?}, force {:>10.3?}, integrate {:>10.3?}, ",
+                "total {:>10.3?}"
+            ),
+            step, stats.tree_build, stats.aggregate, stats.force, stats.integrate, duration
+        );
+
+        total.tree_build += stats.tree_build;
+        total.aggregate += stats.aggregate;
+        total.force += stats.force;
+        total.integrate += stats.integrate;
+    }
+
+    if steps > 0 {
+        let steps = steps as u32;
+        println!(
+            "average:    tree build {:>10.3?}, aggregate {:>10.3?}, force {:>10.3?}, integrate {:>10.3?}",
+            total.tree_build / steps,
+            total.aggregate / steps,
+            total.force / steps,
+            total.integrate / steps
+        );
+
+        let tree_stats = simulation.quadtree().stats();
+        println!(
+            "tree:       {} nodes, {} leaves, max depth {}, average leaf depth {:.1}",
+            tree_stats.node_count, tree_stats.leaf_count, tree_stats.max_depth, tree_stats.average_leaf_depth
+        );
+    }
+}
+
+/// Runs the application with the given configuration. The logger must already be initialized.
+pub async fn run_with_config(config: Config) {
+    let num_bodies = config.num_bodies;
 
     let event_loop = EventLoop::new().unwrap();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
-    let mut pipeline = Pipeline::new(&window).await;
-    let simulation = Arc::new(Mutex::new(Simulation::new(
-        std::iter::repeat_with(|| {
-            // let pos_dist = Uniform::new(-0.5, 0.5).unwrap();
-            let pos_dist = Normal::new(0.0, 0.5).unwrap();
-            let vel_dist = Normal::new(0.0, 0.0001).unwrap();
-
-            let pos_x: f32 = pos_dist.sample(&mut rng());
-            let pos_y: f32 = pos_dist.sample(&mut rng());
-
-            let vel_x: f32 = vel_dist.sample(&mut rng());
-            let vel_y: f32 = vel_dist.sample(&mut rng());
-
-            Body {
-                position: Point2::new(pos_x, pos_y),
-                velocity: Vector2::new(vel_x, vel_y),
-                mass: 1.0,
-                radius: 0.005,
-                color: rgb(0xC4, 0x60, 0x3B),
-            }
-        })
-        .take(num_bodies),
-        0.5,
-    )));
+    let pipeline = Pipeline::new(&window, config.sample_count, config.present_mode, config.depth_buffer).await;
+    let mut pipeline = match pipeline {
+        Ok(pipeline) => pipeline,
+        Err(e) => {
+            log::error!("Couldn't initialize graphics: {}", e);
+            return;
+        }
+    };
+
+    // the seed is logged so a run can be reproduced by passing it back in
+    let seed = config.seed.or_else(seed_from_env).unwrap_or_else(rand::random);
+    log::info!("Generating {} bodies from {:?} preset with seed {}", num_bodies, config.preset, seed);
+
+    let bodies = config.preset.generate(num_bodies, config.masses, seed);
+    let num_bodies = bodies.len();
+    let mut simulation = Simulation::new(bodies.into_iter(), config.pseudobody_threshold)
+        .with_softening(config.softening)
+        .with_boundary_mode(config.boundary_mode)
+        .with_force_mode(config.force_mode)
+        .with_warm_start(config.warm_start);
+    simulation.set_block_timesteps(config.block_timesteps);
+    simulation.set_contact_force(config.contact_force);
+    if config.gpu_forces {
+        #[cfg(feature = "gpu-forces")]
+        match simulation::gpu::GpuForces::new(&pipeline) {
+            Some(gpu_forces) => simulation.set_gpu_forces(Some(gpu_forces)),
+            None => log::warn!("The graphics device has no compute shaders, calculating forces on the CPU"),
+        }
+        #[cfg(not(feature = "gpu-forces"))]
+        log::warn!("Built without the gpu-forces feature, calculating forces on the CPU");
+    }
+    // random initial velocities rarely cancel out exactly, which would move the bodies out of view over time
+    simulation.remove_net_drift();
+
+    // the state after the latest step, which is drawn without ever holding the simulation. only the simulation
+    // thread publishes frames, with what the render thread requested for its enabled views. the render thread
+    // also asks for a new frame after changing the bodies itself
+    let (mut frame_writer, mut frame_reader) = triple_buffer::<BodyFrame>();
+    let (mut request_writer, mut request_reader) = triple_buffer::<FrameRequest>();
+    frame_writer.publish(|frame| frame.capture(&simulation, FrameRequest::default()));
+    let frame_requested = Arc::new(AtomicBool::new(false));
+    let simulation = Arc::new(Mutex::new(simulation));
 
     // two threads with the simulation as shared state:
     // 1. simulation
     // 2. rendering
 
-    let simulation_thread = {
+    // while paused, the simulation only advances by a single fixed step when one is requested
+    let paused = Arc::new(AtomicBool::new(false));
+    let step_requested = Arc::new(AtomicBool::new(false));
+
+    // multiplier for how fast simulation time passes compared to real time, stored as the bits of an f32
+    let time_scale = Arc::new(AtomicU32::new(1.0_f32.to_bits()));
+
+    // set when the event loop exits, the simulation thread is joined afterwards
+    let shutdown = Arc::new(AtomicBool::new(false));
+
+    let mut simulation_thread = Some({
         let simulation = simulation.clone();
+        let frame_requested = frame_requested.clone();
+        let paused = paused.clone();
+        let step_requested = step_requested.clone();
+        let time_scale = time_scale.clone();
+        let shutdown = shutdown.clone();
+
+        let mut csv_exporter = config.csv.clone().and_then(|target| match CsvExporter::new(target) {
+            Ok(csv_exporter) => Some(csv_exporter),
+            Err(e) => {
+                log::error!("Can't export bodies to CSV: {}", e);
+                None
+            }
+        });
 
         std::thread::spawn(move || {
             let mut previous_time = Instant::now();
+            let mut published_request = FrameRequest::default();
 
-            loop {
+            while !shutdown.load(Ordering::Relaxed) {
                 let current_time = Instant::now();
-                let dt = current_time - previous_time;
+                let dt = config.timestep.unwrap_or(current_time - previous_time);
                 previous_time = current_time;
 
-                simulation.lock().unwrap().advance(dt).unwrap();
+                // faster time is split into several steps no longer than the unscaled one, so the integration
+                // stays as stable as at normal speed. single steps aren't scaled
+                let (dt, steps) = if !paused.load(Ordering::Relaxed) {
+                    let time_scale = f32::from_bits(time_scale.load(Ordering::Relaxed));
+                    let steps = time_scale.ceil().max(1.0) as u32;
+                    (Some(dt.mul_f32(time_scale / steps as f32)), steps)
+                } else if step_requested.swap(false, Ordering::Relaxed) {
+                    (Some(config.timestep.unwrap_or(SINGLE_STEP_DT)), 1)
+                } else {
+                    (None, 0)
+                };
 
-                std::thread::sleep(Duration::from_millis(10));
+                // the render thread changed the bodies or its views, which has to be published even while paused
+                let request = *request_reader.read();
+                let stale_frame = frame_requested.swap(false, Ordering::Relaxed) || request != published_request;
+                if dt.is_some() || stale_frame {
+                    // the lock is only poisoned if the render thread panicked, nothing is left to simulate for
+                    let Ok(mut simulation) = simulation.lock() else {
+                        break;
+                    };
+
+                    if let Some(dt) = dt {
+                        for _ in 0..steps {
+                            simulation.advance(dt.as_secs_f32());
+
+                            let exported =
+                                csv_exporter.as_mut().map(|csv_exporter| csv_exporter.write_step(&simulation));
+                            if let Some(Err(e)) = exported {
+                                log::error!("Stopping CSV export: {}", e);
+                                csv_exporter = None;
+                            }
+                        }
+                    }
+
+                    frame_writer.publish(|frame| frame.capture(&simulation, request));
+                    published_request = request;
+                }
+
+                // steps slower than the interval already keep the thread busy
+                if let Some(remaining) = config.step_interval.checked_sub(current_time.elapsed()) {
+                    std::thread::sleep(remaining);
+                }
             }
         })
-    };
+    });
 
-    let mut render_state = RenderState::new(&pipeline.device, num_bodies);
+    let mut render_state = RenderState::new(&pipeline, num_bodies);
+    if let Some(background) = config.background {
+        render_state.settings_mut().set_background(background);
+    }
 
     log::info!("Created window and event loop! Window inner size: {:?}", window.inner_size());
 
@@ -144,21 +340,48 @@ pub async fn run() {
     }
 
     let mut surface_configured = false;
+    let mut lost_surface_frames = 0;
+
+    // the camera is panned while the right mouse button is held
+    let mut panning = false;
+    let mut cursor_position: Option<PhysicalPosition<f64>> = None;
+
+    // a body is spawned where the left mouse button was pressed, with a velocity given by how far the
+    // cursor was dragged until release
+    let mut spawn_position: Option<Point2<f32>> = None;
+
+    // the pipeline borrows the window, so only the reference is moved into the event loop
+    let window = &window;
 
     event_loop
         .run(move |event, control_flow| match event {
-            Event::WindowEvent { window_id, ref event } if window_id == pipeline.window.id() => match event {
+            Event::WindowEvent { window_id, ref event } if window_id == window.id() => match event {
                 WindowEvent::RedrawRequested => {
-                    pipeline.window.request_redraw();
+                    window.request_redraw();
 
                     if !surface_configured {
                         return;
                     }
 
-                    let simulation = simulation.lock().unwrap();
-                    match render_state.render(&mut pipeline, &simulation) {
-                        Ok(_) => {}
-                        Err(SurfaceError::Lost | SurfaceError::Outdated) => {
+                    // the simulation is never locked for drawing, so a long step doesn't hold up drawing and
+                    // drawing doesn't hold up the next step. the request is picked up with the next frame
+                    request_writer.publish(|request| *request = render_state.frame_request(pipeline.size));
+                    let rendered = render_state.render(&mut pipeline, frame_reader.read());
+                    match rendered {
+                        Ok(_) => lost_surface_frames = 0,
+                        Err(SurfaceError::Lost) if lost_surface_frames >= MAX_SURFACE_RECONFIGURES => {
+                            log::warn!("Surface still lost after reconfiguring, recreating it");
+                            lost_surface_frames = 0;
+                            if let Err(e) = pipeline.recreate_surface() {
+                                log::error!("Couldn't recreate surface: {}", e);
+                                control_flow.exit();
+                            }
+                        }
+                        Err(SurfaceError::Lost) => {
+                            lost_surface_frames += 1;
+                            pipeline.resize(pipeline.size);
+                        }
+                        Err(SurfaceError::Outdated) => {
                             pipeline.resize(pipeline.size);
                         }
                         Err(SurfaceError::OutOfMemory | SurfaceError::Other) => {
@@ -188,21 +411,317 @@ pub async fn run() {
                     event:
                         KeyEvent {
                             state: ElementState::Pressed,
-                            physical_key: PhysicalKey::Code(keycode),
+                            physical_key: PhysicalKey::Code(KeyCode::KeyG),
+                            ..
+                        },
+                    ..
+                } => {
+                    // toggle tree drawing
+                    render_state.settings_mut().toggle_draw_tree();
+                }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::KeyL),
+                            ..
+                        },
+                    ..
+                } => {
+                    // toggle the background grid
+                    render_state.settings_mut().toggle_draw_grid();
+                }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::KeyD),
+                            ..
+                        },
+                    ..
+                } => {
+                    // toggle drawing small nodes as single circles
+                    render_state.settings_mut().toggle_level_of_detail();
+                }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::KeyF),
+                            ..
+                        },
+                    ..
+                } => {
+                    // toggle between the free camera and following the center of mass
+                    render_state.settings_mut().toggle_follow_center_of_mass();
+                }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::KeyV),
+                            ..
+                        },
+                    ..
+                } => {
+                    // toggle velocity drawing
+                    render_state.settings_mut().toggle_draw_velocities();
+                }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::KeyP),
                             ..
                         },
                     ..
                 } => {
-                    match keycode {
-                        KeyCode::KeyG => {
-                            // toggle tree drawing
-                            render_state.settings_mut().toggle_draw_tree();
+                    // toggle pseudobody drawing
+                    render_state.settings_mut().toggle_draw_pseudobodies();
+                }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::KeyC),
+                            ..
+                        },
+                    ..
+                } => {
+                    // cycle through body coloring modes
+                    render_state.settings_mut().cycle_color_mode();
+                }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::KeyT),
+                            ..
+                        },
+                    ..
+                } => {
+                    // toggle trails
+                    render_state.settings_mut().toggle_trails();
+                }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::KeyO),
+                            ..
+                        },
+                    ..
+                } => {
+                    // toggle statistics overlay
+                    render_state.settings_mut().toggle_draw_overlay();
+                }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::KeyB),
+                            ..
+                        },
+                    ..
+                } => {
+                    // toggle scale bar
+                    render_state.settings_mut().toggle_draw_scale_bar();
+                }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::Home),
+                            ..
+                        },
+                    ..
+                } => {
+                    // frame all bodies
+                    render_state.fit_camera(&simulation.lock().unwrap(), pipeline.size);
+                }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::KeyW),
+                            ..
+                        },
+                    ..
+                } => {
+                    // toggle outlining the triangles of lines and text
+                    if pipeline.generic_wireframe_pipeline.is_some() {
+                        render_state.settings_mut().toggle_wireframe();
+                    } else {
+                        log::warn!("Wireframe rendering is not supported by the adapter");
+                    }
+                }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::KeyR),
+                            ..
+                        },
+                    ..
+                } => {
+                    // regenerate the bodies of the preset with a new seed
+                    let seed = rand::random();
+                    let (num_bodies, preset) = (config.num_bodies, config.preset);
+                    log::info!("Regenerating {} bodies from {:?} preset with seed {}", num_bodies, preset, seed);
+
+                    let bodies = preset.generate(num_bodies, config.masses, seed);
+                    let mut simulation = simulation.lock().unwrap();
+                    simulation.reset(bodies.into_iter());
+                    simulation.remove_net_drift();
+                    frame_requested.store(true, Ordering::Relaxed);
+                    render_state.clear_history();
+                }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(key @ (KeyCode::BracketLeft | KeyCode::BracketRight)),
+                            ..
+                        },
+                    ..
+                } => {
+                    // trade accuracy for speed by changing the opening angle
+                    let step = if *key == KeyCode::BracketRight { THRESHOLD_STEP } else { -THRESHOLD_STEP };
+                    let mut simulation = simulation.lock().unwrap();
+                    let threshold = (simulation.threshold() + step).clamp(0.0, MAX_THRESHOLD);
+                    simulation.set_threshold(threshold);
+                    log::info!("Opening angle set to {:.2}", threshold);
+                }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(key @ (KeyCode::Minus | KeyCode::Equal)),
+                            ..
+                        },
+                    ..
+                } => {
+                    // speed up or slow down simulation time, independent of the frame rate
+                    let factor = if *key == KeyCode::Equal { TIME_SCALE_FACTOR } else { 1.0 / TIME_SCALE_FACTOR };
+                    let scale = (f32::from_bits(time_scale.load(Ordering::Relaxed)) * factor)
+                        .clamp(MIN_TIME_SCALE, MAX_TIME_SCALE);
+                    time_scale.store(scale.to_bits(), Ordering::Relaxed);
+                    log::info!("Time scale set to {}", scale);
+                }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::KeyM),
+                            repeat: false,
+                            ..
+                        },
+                    ..
+                } => {
+                    // cycle through the supported present modes, e.g. to turn off vsync
+                    let present_mode = pipeline.next_present_mode();
+                    pipeline.set_present_mode(present_mode);
+                    log::info!("Presenting with {:?}", present_mode);
+                }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::F12),
+                            ..
+                        },
+                    ..
+                } => {
+                    // save the next frame next to the working directory
+                    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                    render_state.request_screenshot(format!("graviton-{}.png", timestamp));
+                }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::Space),
+                            repeat: false,
+                            ..
+                        },
+                    ..
+                } => {
+                    let was_paused = paused.fetch_xor(true, Ordering::Relaxed);
+                    log::info!("Simulation {}", if was_paused { "resumed" } else { "paused" });
+                }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::Period),
+                            ..
+                        },
+                    ..
+                } if paused.load(Ordering::Relaxed) => {
+                    // single steps are only taken while paused
+                    step_requested.store(true, Ordering::Relaxed);
+                }
+                WindowEvent::MouseInput {
+                    state,
+                    button: MouseButton::Right,
+                    ..
+                } => {
+                    panning = *state == ElementState::Pressed;
+                }
+                WindowEvent::MouseInput {
+                    state,
+                    button: MouseButton::Left,
+                    ..
+                } => {
+                    let Some(cursor) = cursor_position else {
+                        return;
+                    };
+
+                    let position = render_state.camera().screen_to_world(cursor, pipeline.size);
+                    match state {
+                        ElementState::Pressed => spawn_position = Some(position),
+                        ElementState::Released => {
+                            if let Some(spawn) = spawn_position.take() {
+                                let velocity = (position - spawn) * SPAWN_VELOCITY_SCALE;
+                                let mut simulation = simulation.lock().unwrap();
+                                simulation.add_body(spawned_body(spawn, velocity));
+                                frame_requested.store(true, Ordering::Relaxed);
+                            }
                         }
-                        _ => {}
                     }
                 }
+                WindowEvent::CursorMoved { position, .. } => {
+                    if let (true, Some(previous)) = (panning, cursor_position) {
+                        let delta = Vector2::new((position.x - previous.x) as f32, (position.y - previous.y) as f32);
+                        render_state.pan(delta, pipeline.size);
+                    }
+
+                    cursor_position = Some(*position);
+                    render_state.set_cursor_position(cursor_position);
+                }
+                WindowEvent::CursorLeft { .. } => {
+                    cursor_position = None;
+                    render_state.set_cursor_position(None);
+                }
+                WindowEvent::MouseWheel { delta, .. } => {
+                    let lines = match delta {
+                        MouseScrollDelta::LineDelta(_, y) => *y,
+                        MouseScrollDelta::PixelDelta(position) => position.y as f32 / 50.0,
+                    };
+
+                    render_state.zoom(1.1_f32.powf(lines));
+                }
                 _ => {}
             },
+            Event::LoopExiting => {
+                shutdown.store(true, Ordering::Relaxed);
+                // waits for the current step to finish
+                if let Some(simulation_thread) = simulation_thread.take()
+                    && simulation_thread.join().is_err()
+                {
+                    log::error!("Simulation thread panicked");
+                }
+            }
             _ => {}
         })
         .unwrap();
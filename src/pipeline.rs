@@ -1,97 +1,175 @@
-use wgpu::{Device, RenderPipeline, SurfaceConfiguration};
-use winit::{
-    dpi::PhysicalSize,
-    event::{Event, KeyEvent, WindowEvent},
-    event_loop::EventLoop,
-    keyboard::{KeyCode, PhysicalKey},
-    window::{Window, WindowBuilder},
-};
-
-#[cfg(target_arch = "wasm32")]
-use wasm_bindgen::prelude::*;
+use thiserror::Error;
+use wgpu::{BindGroupLayout, Device, RenderPipeline, SurfaceConfiguration};
+use winit::window::Window;
 
 use crate::rendering::{bodies::{BodyInstance, CircleVertex}, generic::GenericVertex};
 
+#[derive(Debug, Error)]
+pub enum PipelineError {
+    #[error("No suitable graphics adapter found: {0}")]
+    NoAdapter(#[from] wgpu::RequestAdapterError),
+    #[error("Couldn't create graphics device: {0}")]
+    NoDevice(#[from] wgpu::RequestDeviceError),
+    #[error("Couldn't create surface for the window: {0}")]
+    SurfaceCreation(#[from] wgpu::CreateSurfaceError),
+    #[error("The surface doesn't support any format with the adapter.")]
+    NoSurfaceFormat,
+}
+
+/// The present modes that can be chosen, in the order they are cycled through. Fifo is always supported.
+pub const PRESENT_MODES: [wgpu::PresentMode; 3] =
+    [wgpu::PresentMode::Fifo, wgpu::PresentMode::Mailbox, wgpu::PresentMode::Immediate];
+
+pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+// color format of headless pipelines, which is what images are saved as
+pub const HEADLESS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
 pub struct Pipeline<'a> {
-    pub surface: wgpu::Surface<'a>,
+    // kept for recreating the surface
+    pub instance: wgpu::Instance,
+    pub adapter: wgpu::Adapter,
+    // both are None for headless pipelines, which can only render to textures
+    pub surface: Option<wgpu::Surface<'a>>,
+    // the present modes of PRESENT_MODES the surface supports
+    pub present_modes: Vec<wgpu::PresentMode>,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
     pub size: winit::dpi::PhysicalSize<u32>,
-    pub window: &'a Window,
+    pub window: Option<&'a Window>,
+    // number of samples per pixel of every render target, 1 disables multisampling
+    pub sample_count: u32,
+    // every render pass has a depth attachment of this format if set, which only the bodies write to
+    pub depth_format: Option<wgpu::TextureFormat>,
+    pub camera_bind_group_layout: wgpu::BindGroupLayout,
     pub circle_pipeline: wgpu::RenderPipeline,
     pub generic_pipeline: wgpu::RenderPipeline,
+    // draws generic meshes as triangle outlines, only if the device supports line polygons
+    pub generic_wireframe_pipeline: Option<wgpu::RenderPipeline>,
+    pub blit_bind_group_layout: wgpu::BindGroupLayout,
+    pub fade_pipeline: wgpu::RenderPipeline,
+    pub blit_pipeline: wgpu::RenderPipeline,
+    // calculates forces on the GPU, only if the device supports compute shaders
+    #[cfg(feature = "gpu-forces")]
+    pub force_bind_group_layout: wgpu::BindGroupLayout,
+    #[cfg(feature = "gpu-forces")]
+    pub force_pipeline: Option<wgpu::ComputePipeline>,
 }
 
 impl<'a> Pipeline<'a> {
-    // Creating some of the wgpu types requires async code
-    pub async fn new(window: &'a Window) -> Pipeline<'a> {
+    // Creating some of the wgpu types requires async code. The sample count is lowered to the highest one
+    // the adapter supports if needed, unsupported present modes fall back to Fifo
+    pub async fn new(window: &'a Window, sample_count: u32, present_mode: wgpu::PresentMode, depth_buffer: bool) -> Result<Pipeline<'a>, PipelineError> {
         let size = window.inner_size();
-
-        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
-            #[cfg(not(target_arch = "wasm32"))]
-            backends: wgpu::Backends::PRIMARY,
-            #[cfg(target_arch = "wasm32")]
-            backends: wgpu::Backends::GL,
-            ..Default::default()
-        });
-
-        let surface = instance.create_surface(window).unwrap();
-
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
-
-        let (device, queue) = adapter
-            .request_device(&wgpu::DeviceDescriptor {
-                label: None,
-                required_features: wgpu::Features::default(),
-                required_limits: if cfg!(target_arch = "wasm32") {
-                    wgpu::Limits::downlevel_webgl2_defaults()
-                } else {
-                    wgpu::Limits::default()
-                },
-                memory_hints: wgpu::MemoryHints::Performance,
-                trace: wgpu::Trace::Off,
-            })
-            .await
-            .unwrap();
+        let instance = create_instance();
+        let surface = instance.create_surface(window)?;
+        let (adapter, device, queue) = request_device(&instance, Some(&surface)).await?;
 
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = surface_caps
             .formats
             .iter()
             .find(|f| f.is_srgb())
+            .or(surface_caps.formats.first())
             .copied()
-            .unwrap_or(surface_caps.formats[0]);
+            .ok_or(PipelineError::NoSurfaceFormat)?;
+        let sample_count = supported_sample_count(&adapter, surface_format, sample_count);
+
+        let present_modes = PRESENT_MODES
+            .into_iter()
+            .filter(|mode| *mode == wgpu::PresentMode::Fifo || surface_caps.present_modes.contains(mode))
+            .collect::<Vec<_>>();
+        let present_mode = if present_modes.contains(&present_mode) {
+            present_mode
+        } else {
+            log::warn!("Present mode {:?} is not supported, using Fifo instead", present_mode);
+            wgpu::PresentMode::Fifo
+        };
+
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: surface_caps.present_modes[0],
+            present_mode,
             desired_maximum_frame_latency: 2,
             alpha_mode: surface_caps.alpha_modes[0],
             view_formats: vec![],
         };
 
-        let circle_pipeline = create_circle_pipeline(&config, &device);
-        let generic_pipeline = create_generic_pipeline(&config, &device);
+        Ok(Self::with_target(instance, adapter, Some(surface), Some(window), present_modes, device, queue, config, sample_count, depth_buffer))
+    }
+
+    /// Creates a pipeline without a window, which renders to textures of the given size with
+    /// [HEADLESS_FORMAT], e.g. for comparing frames in tests.
+    pub async fn new_headless(size: winit::dpi::PhysicalSize<u32>, sample_count: u32, depth_buffer: bool) -> Result<Pipeline<'a>, PipelineError> {
+        let instance = create_instance();
+        let (adapter, device, queue) = request_device(&instance, None).await?;
+        let sample_count = supported_sample_count(&adapter, HEADLESS_FORMAT, sample_count);
+
+        // only describes the render targets, there is no surface to configure
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: HEADLESS_FORMAT,
+            width: size.width.max(1),
+            height: size.height.max(1),
+            present_mode: wgpu::PresentMode::Fifo,
+            desired_maximum_frame_latency: 2,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+        };
+
+        Ok(Self::with_target(instance, adapter, None, None, vec![wgpu::PresentMode::Fifo], device, queue, config, sample_count, depth_buffer))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn with_target(instance: wgpu::Instance, adapter: wgpu::Adapter, surface: Option<wgpu::Surface<'a>>, window: Option<&'a Window>, present_modes: Vec<wgpu::PresentMode>, device: wgpu::Device, queue: wgpu::Queue, config: wgpu::SurfaceConfiguration, sample_count: u32, depth_buffer: bool) -> Self {
+        let size = winit::dpi::PhysicalSize::new(config.width, config.height);
+        let depth_format = depth_buffer.then_some(DEPTH_FORMAT);
+
+        let camera_bind_group_layout = create_camera_bind_group_layout(&device);
+        let circle_pipeline = create_circle_pipeline(&config, &device, &camera_bind_group_layout, sample_count, depth_format);
+        let generic_pipeline = create_generic_pipeline(&config, &device, &camera_bind_group_layout, sample_count, depth_format, wgpu::PolygonMode::Fill);
+        let generic_wireframe_pipeline = device
+            .features()
+            .contains(wgpu::Features::POLYGON_MODE_LINE)
+            .then(|| create_generic_pipeline(&config, &device, &camera_bind_group_layout, sample_count, depth_format, wgpu::PolygonMode::Line));
+        let blit_bind_group_layout = create_blit_bind_group_layout(&device);
+        let fade_pipeline = create_fade_pipeline(&config, &device, sample_count, depth_format);
+        let blit_pipeline = create_blit_pipeline(&config, &device, &blit_bind_group_layout, sample_count, depth_format);
+        #[cfg(feature = "gpu-forces")]
+        let force_bind_group_layout = create_force_bind_group_layout(&device);
+        #[cfg(feature = "gpu-forces")]
+        let force_pipeline = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+            .then(|| create_force_pipeline(&device, &force_bind_group_layout));
 
         Self {
+            instance,
+            adapter,
             surface,
+            present_modes,
             device,
             queue,
             config,
             size,
             window,
+            sample_count,
+            depth_format,
+            camera_bind_group_layout,
             circle_pipeline,
             generic_pipeline,
+            generic_wireframe_pipeline,
+            blit_bind_group_layout,
+            fade_pipeline,
+            blit_pipeline,
+            #[cfg(feature = "gpu-forces")]
+            force_bind_group_layout,
+            #[cfg(feature = "gpu-forces")]
+            force_pipeline,
         }
     }
 
@@ -100,8 +178,52 @@ impl<'a> Pipeline<'a> {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+            if let Some(surface) = &self.surface {
+                surface.configure(&self.device, &self.config);
+            }
+        }
+    }
+
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        self.config.present_mode
+    }
+
+    /// Reconfigures the surface to present with the given mode. Returns false and keeps the current mode if
+    /// the surface doesn't support it.
+    pub fn set_present_mode(&mut self, present_mode: wgpu::PresentMode) -> bool {
+        if !self.present_modes.contains(&present_mode) {
+            return false;
+        }
+
+        self.config.present_mode = present_mode;
+        // an empty surface can't be configured, it is configured on the next resize instead
+        if let Some(surface) = &self.surface
+            && self.config.width > 0
+            && self.config.height > 0
+        {
+            surface.configure(&self.device, &self.config);
         }
+        true
+    }
+
+    /// The supported present mode after the current one in [PRESENT_MODES], wrapping around.
+    pub fn next_present_mode(&self) -> wgpu::PresentMode {
+        let current = self.present_modes.iter().position(|mode| *mode == self.config.present_mode).unwrap_or(0);
+        self.present_modes[(current + 1) % self.present_modes.len()]
+    }
+
+    /// Creates a new surface for the window and configures it like the old one. Needed when the window's
+    /// native surface was destroyed, which reconfiguring the old surface can't recover from. Does nothing for
+    /// headless pipelines.
+    pub fn recreate_surface(&mut self) -> Result<(), PipelineError> {
+        let Some(window) = self.window else {
+            return Ok(());
+        };
+
+        let surface = self.instance.create_surface(window)?;
+        surface.configure(&self.device, &self.config);
+        self.surface = Some(surface);
+        Ok(())
     }
 
     pub fn start_encoder(&self) -> wgpu::CommandEncoder {
@@ -116,7 +238,97 @@ impl<'a> Pipeline<'a> {
     }
 }
 
-fn create_circle_pipeline(config: &SurfaceConfiguration, device: &Device) -> RenderPipeline {
+fn create_instance() -> wgpu::Instance {
+    // WGPU_BACKEND and the other variables read by wgpu override the defaults, e.g. WGPU_BACKEND=vulkan
+    wgpu::Instance::new(
+        &wgpu::InstanceDescriptor {
+            #[cfg(not(target_arch = "wasm32"))]
+            backends: wgpu::Backends::PRIMARY,
+            #[cfg(target_arch = "wasm32")]
+            backends: wgpu::Backends::GL,
+            ..Default::default()
+        }
+        .with_env(),
+    )
+}
+
+// the adapter has to be able to present to the surface if one is given
+async fn request_device(instance: &wgpu::Instance, surface: Option<&wgpu::Surface<'_>>) -> Result<(wgpu::Adapter, wgpu::Device, wgpu::Queue), PipelineError> {
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            // WGPU_POWER_PREF=low picks the integrated GPU on laptops
+            power_preference: wgpu::PowerPreference::from_env().unwrap_or(wgpu::PowerPreference::HighPerformance),
+            compatible_surface: surface,
+            force_fallback_adapter: false,
+        })
+        .await?;
+
+    let adapter_info = adapter.get_info();
+    log::info!("Using adapter {} with {:?} backend", adapter_info.name, adapter_info.backend);
+
+    let (device, queue) = adapter
+        .request_device(&wgpu::DeviceDescriptor {
+            label: None,
+            // only used for debugging, so it is left out where it isn't supported
+            required_features: adapter.features() & wgpu::Features::POLYGON_MODE_LINE,
+            required_limits: if cfg!(target_arch = "wasm32") {
+                wgpu::Limits::downlevel_webgl2_defaults()
+            } else {
+                wgpu::Limits::default()
+            },
+            memory_hints: wgpu::MemoryHints::Performance,
+            trace: wgpu::Trace::Off,
+        })
+        .await?;
+
+    Ok((adapter, device, queue))
+}
+
+// the highest sample count not above the requested one that can be used for rendering to the format
+fn supported_sample_count(adapter: &wgpu::Adapter, format: wgpu::TextureFormat, requested: u32) -> u32 {
+    let flags = adapter.get_texture_format_features(format).flags;
+    let sample_count = [16, 8, 4, 2]
+        .into_iter()
+        .filter(|&count| count <= requested)
+        .find(|&count| flags.sample_count_supported(count))
+        .unwrap_or(1);
+
+    if sample_count != requested {
+        log::warn!("Sample count {} is not supported, using {} instead", requested, sample_count);
+    }
+
+    sample_count
+}
+
+// only pipelines that write depth test against it, everything else is drawn in order like without a depth
+// buffer. depth is cleared to 0, so larger depths are in front
+fn depth_stencil_state(depth_format: Option<wgpu::TextureFormat>, write: bool) -> Option<wgpu::DepthStencilState> {
+    depth_format.map(|format| wgpu::DepthStencilState {
+        format,
+        depth_write_enabled: write,
+        depth_compare: if write { wgpu::CompareFunction::GreaterEqual } else { wgpu::CompareFunction::Always },
+        stencil: wgpu::StencilState::default(),
+        bias: wgpu::DepthBiasState::default(),
+    })
+}
+
+fn create_camera_bind_group_layout(device: &Device) -> BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Camera Bind Group Layout"),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+    })
+}
+
+fn create_circle_pipeline(config: &SurfaceConfiguration, device: &Device, camera_layout: &BindGroupLayout, sample_count: u32, depth_format: Option<wgpu::TextureFormat>) -> RenderPipeline {
     let circle_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
         label: Some("Circle Shader"),
         source: wgpu::ShaderSource::Wgsl(include_str!("circle_shader.wgsl").into()),
@@ -124,11 +336,11 @@ fn create_circle_pipeline(config: &SurfaceConfiguration, device: &Device) -> Ren
 
     let circle_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
         label: Some("Circle Render Pipeline Layout"),
-        bind_group_layouts: &[],
+        bind_group_layouts: &[camera_layout],
         push_constant_ranges: &[],
     });
 
-    let circle_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: Some("Render Pipeline"),
         layout: Some(&circle_pipeline_layout),
         vertex: wgpu::VertexState {
@@ -142,7 +354,8 @@ fn create_circle_pipeline(config: &SurfaceConfiguration, device: &Device) -> Ren
             entry_point: Some("fs_main"),
             targets: &[Some(wgpu::ColorTargetState {
                 format: config.format,
-                blend: Some(wgpu::BlendState::REPLACE),
+                // soft edges of the circles are blended with what is behind them
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
                 write_mask: wgpu::ColorWrites::ALL,
             })],
             compilation_options: wgpu::PipelineCompilationOptions::default(),
@@ -156,20 +369,19 @@ fn create_circle_pipeline(config: &SurfaceConfiguration, device: &Device) -> Ren
             unclipped_depth: false,
             conservative: false,
         },
-        depth_stencil: None,
+        // bigger bodies are drawn in front of smaller ones
+        depth_stencil: depth_stencil_state(depth_format, true),
         multisample: wgpu::MultisampleState {
-            count: 1,
+            count: sample_count,
             mask: !0,
             alpha_to_coverage_enabled: false,
         },
         multiview: None,
         cache: None,
-    });
-
-    circle_pipeline
+    })
 }
 
-fn create_generic_pipeline(config: &SurfaceConfiguration, device: &Device) -> RenderPipeline {
+fn create_generic_pipeline(config: &SurfaceConfiguration, device: &Device, camera_layout: &BindGroupLayout, sample_count: u32, depth_format: Option<wgpu::TextureFormat>, polygon_mode: wgpu::PolygonMode) -> RenderPipeline {
     let generic_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
         label: Some("Generic Shader"),
         source: wgpu::ShaderSource::Wgsl(include_str!("generic_shader.wgsl").into()),
@@ -177,11 +389,11 @@ fn create_generic_pipeline(config: &SurfaceConfiguration, device: &Device) -> Re
 
     let generic_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
         label: Some("Generic Render Pipeline Layout"),
-        bind_group_layouts: &[],
+        bind_group_layouts: &[camera_layout],
         push_constant_ranges: &[],
     });
 
-    let generic_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: Some("Generic Render Pipeline"),
         layout: Some(&generic_pipeline_layout),
         vertex: wgpu::VertexState {
@@ -205,19 +417,183 @@ fn create_generic_pipeline(config: &SurfaceConfiguration, device: &Device) -> Re
             strip_index_format: None,
             front_face: wgpu::FrontFace::Ccw,
             cull_mode: Some(wgpu::Face::Back),
+            polygon_mode,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: depth_stencil_state(depth_format, false),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+        cache: None,
+    })
+}
+
+fn create_blit_bind_group_layout(device: &Device) -> BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Blit Bind Group Layout"),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        }],
+    })
+}
+
+// both trail pipelines draw a single fullscreen triangle without any vertex buffers
+#[allow(clippy::too_many_arguments)]
+fn create_fullscreen_pipeline(
+    config: &SurfaceConfiguration,
+    device: &Device,
+    label: &str,
+    bind_group_layouts: &[&BindGroupLayout],
+    fragment_entry_point: &str,
+    blend: wgpu::BlendState,
+    sample_count: u32,
+    depth_format: Option<wgpu::TextureFormat>,
+) -> RenderPipeline {
+    let trail_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Trail Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("trail_shader.wgsl").into()),
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts,
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &trail_shader,
+            entry_point: Some("vs_fullscreen"),
+            buffers: &[],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &trail_shader,
+            entry_point: Some(fragment_entry_point),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: config.format,
+                blend: Some(blend),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
             polygon_mode: wgpu::PolygonMode::Fill,
             unclipped_depth: false,
             conservative: false,
         },
-        depth_stencil: None,
+        depth_stencil: depth_stencil_state(depth_format, false),
         multisample: wgpu::MultisampleState {
-            count: 1,
+            count: sample_count,
             mask: !0,
             alpha_to_coverage_enabled: false,
         },
         multiview: None,
         cache: None,
+    })
+}
+
+fn create_fade_pipeline(config: &SurfaceConfiguration, device: &Device, sample_count: u32, depth_format: Option<wgpu::TextureFormat>) -> RenderPipeline {
+    // keeps the blend constant's fraction of the previous contents
+    let fade = wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::Constant,
+        operation: wgpu::BlendOperation::Add,
+    };
+
+    create_fullscreen_pipeline(
+        config,
+        device,
+        "Fade Render Pipeline",
+        &[],
+        "fs_fade",
+        wgpu::BlendState {
+            color: fade,
+            alpha: fade,
+        },
+        sample_count,
+        depth_format,
+    )
+}
+
+fn create_blit_pipeline(config: &SurfaceConfiguration, device: &Device, blit_layout: &BindGroupLayout, sample_count: u32, depth_format: Option<wgpu::TextureFormat>) -> RenderPipeline {
+    create_fullscreen_pipeline(
+        config,
+        device,
+        "Blit Render Pipeline",
+        &[blit_layout],
+        "fs_blit",
+        // the trail texture is transparent where nothing has been drawn, with its colors premultiplied by the
+        // fade. opaque textures like the screenshot capture are copied unchanged
+        wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING,
+        sample_count,
+        depth_format,
+    )
+}
+
+// the parameters are a uniform, the flattened quadtree and the bodies are read and the accelerations written,
+// see force_shader.wgsl
+#[cfg(feature = "gpu-forces")]
+fn create_force_bind_group_layout(device: &Device) -> BindGroupLayout {
+    let entry = |binding: u32, ty: wgpu::BufferBindingType| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Force Bind Group Layout"),
+        entries: &[
+            entry(0, wgpu::BufferBindingType::Uniform),
+            entry(1, wgpu::BufferBindingType::Storage { read_only: true }),
+            entry(2, wgpu::BufferBindingType::Storage { read_only: true }),
+            entry(3, wgpu::BufferBindingType::Storage { read_only: true }),
+            entry(4, wgpu::BufferBindingType::Storage { read_only: false }),
+        ],
+    })
+}
+
+#[cfg(feature = "gpu-forces")]
+fn create_force_pipeline(device: &Device, force_layout: &BindGroupLayout) -> wgpu::ComputePipeline {
+    let force_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Force Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("force_shader.wgsl").into()),
     });
 
-    generic_pipeline
+    let force_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Force Compute Pipeline Layout"),
+        bind_group_layouts: &[force_layout],
+        push_constant_ranges: &[],
+    });
+
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Force Compute Pipeline"),
+        layout: Some(&force_pipeline_layout),
+        module: &force_shader,
+        entry_point: Some("cs_main"),
+        compilation_options: wgpu::PipelineCompilationOptions::default(),
+        cache: None,
+    })
 }
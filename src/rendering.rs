@@ -1,139 +1,910 @@
+use std::path::PathBuf;
+use std::time::Instant;
+
 use bodies::BodyBuffers;
-use bytemuck::Zeroable;
-use cgmath::Point2;
-use cgmath::prelude::*;
+use camera::{
+    Camera,
+    CameraUniform,
+};
+use cgmath::{
+    InnerSpace,
+    Point2,
+    Vector2,
+};
+use coloring::{
+    BodyColoring,
+    ColorMode,
+    Colormap,
+};
 use generic::{
+    GenericBuffers,
     Mesh,
-    push_line,
 };
+use depth::DepthTarget;
+use grid::generate_grid_mesh;
+use multisample::MultisampleTarget;
+use pseudobodies::generate_pseudobody_mesh;
 use quadtree::generate_quadtree_mesh;
+use scale_bar::generate_scale_bar_mesh;
+use screenshot::Capture;
+pub use screenshot::ScreenshotError;
+use text::push_text;
+use trails::TrailTarget;
+use velocities::generate_velocity_mesh;
 use wgpu::util::{
-    self,
     BufferInitDescriptor,
     DeviceExt,
 };
 use wgpu::{
+    BindGroup,
+    BindGroupDescriptor,
+    BindGroupEntry,
     Buffer,
-    BufferAddress,
-    BufferDescriptor,
     BufferUsages,
     Color,
-    Device,
-    IndexFormat,
+    CommandEncoder,
     LoadOp,
     Operations,
+    RenderPass,
     RenderPassColorAttachment,
+    RenderPassDepthStencilAttachment,
     RenderPassDescriptor,
     StoreOp,
     SurfaceError,
+    TextureView,
     TextureViewDescriptor,
-    VertexAttribute,
-    VertexBufferLayout,
-    VertexFormat,
-    VertexStepMode,
 };
 
 use crate::pipeline::Pipeline;
-use crate::simulation::quadtree::Quadtree;
 use crate::simulation::{
     Body,
+    BodyKey,
     Simulation,
 };
+use source::{
+    BodyFrame,
+    FrameRequest,
+};
+use crate::utility::index_map::MapKey;
+use crate::utility::rolling_average::RollingAverage;
+use winit::dpi::{
+    PhysicalPosition,
+    PhysicalSize,
+};
+
+// number of frames the render statistics are averaged over
+const STATS_WINDOW: usize = 60;
+
+// near black, used unless another background is set
+const DEFAULT_BACKGROUND: Color = Color {
+    r: 0.001,
+    g: 0.001,
+    b: 0.002,
+    a: 1.0,
+};
+
+// distance of the overlay text from the window corner and size of a font pixel, in screen pixels
+const OVERLAY_MARGIN: f32 = 8.0;
+const OVERLAY_SCALE: f32 = 2.0;
+
+// bodies within this many screen pixels of the cursor are inspected in the overlay, even if they are drawn
+// smaller
+const HOVER_PIXELS: f32 = 10.0;
+
+// fraction of the size of the bodies' bounding box that is added around it when fitting the camera to them
+const FIT_MARGIN: f32 = 0.05;
+
+// trail decay used when toggling trails on
+const DEFAULT_TRAIL_DECAY: f32 = 0.9;
 
 pub mod bodies;
+pub mod camera;
+pub mod coloring;
+mod depth;
 pub mod generic;
+mod grid;
+mod multisample;
+mod pseudobodies;
 mod quadtree;
+mod scale_bar;
+mod screenshot;
+pub mod source;
+pub mod text;
+mod trails;
+mod velocities;
 
 pub fn rgb(
     r: u8,
     g: u8,
     b: u8,
 ) -> Color {
-    let f = 1.0 / 256.0;
+    rgba(r, g, b, u8::MAX)
+}
+
+pub fn rgba(
+    r: u8,
+    g: u8,
+    b: u8,
+    a: u8,
+) -> Color {
+    let f = 1.0 / u8::MAX as f64;
     Color {
         r: r as f64 * f,
         g: g as f64 * f,
         b: b as f64 * f,
-        a: 1.0,
+        a: a as f64 * f,
     }
 }
 
-#[derive(Default)]
+/// An opaque color from a hex literal like 0xC4603B, ignoring the highest byte.
+pub fn rgb_hex(hex: u32) -> Color {
+    let [_, r, g, b] = hex.to_be_bytes();
+    rgb(r, g, b)
+}
+
+/// Mixes two colors, with `weight` being the fraction of `b` between 0 and 1. The color channels are blended
+/// in linear space and converted back to sRGB, since blending the encoded values makes the mix too dark.
+/// Alpha isn't encoded and is blended directly.
+pub fn blend(
+    a: Color,
+    b: Color,
+    weight: f64,
+) -> Color {
+    let weight = weight.clamp(0.0, 1.0);
+    let mix = |a: f64, b: f64| linear_to_srgb(srgb_to_linear(a) * (1.0 - weight) + srgb_to_linear(b) * weight);
+
+    Color {
+        r: mix(a.r, b.r),
+        g: mix(a.g, b.g),
+        b: mix(a.b, b.b),
+        a: a.a * (1.0 - weight) + b.a * weight,
+    }
+}
+
+fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.0031308 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 }
+}
+
 pub struct RenderSettings {
+    // color the frame is cleared with
+    pub background: Color,
+    pub color_mode: ColorMode,
+    pub colormap: Colormap,
     pub draw_tree: bool,
+    // world space grid behind the bodies
+    pub draw_grid: bool,
+    pub draw_velocities: bool,
+    // centers of mass of the heavier twigs
+    pub draw_pseudobodies: bool,
+    // draws nodes that are small on screen as one circle instead of all of their bodies
+    pub level_of_detail: bool,
+    // outlines the triangles of generic meshes instead of filling them, if the device supports it
+    pub wireframe: bool,
+    // text with frame and simulation statistics in the top left corner
+    pub draw_overlay: bool,
+    // bar of a round world length in the bottom left corner
+    pub draw_scale_bar: bool,
+    // keeps the camera centered on the bodies' center of mass, panning has no effect while set
+    pub follow_center_of_mass: bool,
+    // fraction of brightness the previous frame keeps, between 0 and 1. 0 disables trails
+    pub trail_decay: f32,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            background: DEFAULT_BACKGROUND,
+            color_mode: ColorMode::default(),
+            colormap: Colormap::default(),
+            draw_tree: false,
+            draw_grid: false,
+            draw_velocities: false,
+            draw_pseudobodies: false,
+            level_of_detail: false,
+            wireframe: false,
+            draw_overlay: false,
+            draw_scale_bar: false,
+            follow_center_of_mass: false,
+            trail_decay: 0.0,
+        }
+    }
 }
 
 impl RenderSettings {
+    pub fn set_background(
+        &mut self,
+        background: Color,
+    ) {
+        self.background = background;
+    }
+
     pub fn toggle_draw_tree(&mut self) {
         self.draw_tree = !self.draw_tree;
     }
+
+    pub fn toggle_draw_grid(&mut self) {
+        self.draw_grid = !self.draw_grid;
+    }
+
+    pub fn toggle_draw_velocities(&mut self) {
+        self.draw_velocities = !self.draw_velocities;
+    }
+
+    pub fn toggle_draw_pseudobodies(&mut self) {
+        self.draw_pseudobodies = !self.draw_pseudobodies;
+    }
+
+    pub fn toggle_level_of_detail(&mut self) {
+        self.level_of_detail = !self.level_of_detail;
+    }
+
+    pub fn toggle_wireframe(&mut self) {
+        self.wireframe = !self.wireframe;
+    }
+
+    pub fn toggle_draw_overlay(&mut self) {
+        self.draw_overlay = !self.draw_overlay;
+    }
+
+    pub fn toggle_draw_scale_bar(&mut self) {
+        self.draw_scale_bar = !self.draw_scale_bar;
+    }
+
+    pub fn toggle_follow_center_of_mass(&mut self) {
+        self.follow_center_of_mass = !self.follow_center_of_mass;
+    }
+
+    pub fn toggle_trails(&mut self) {
+        self.trail_decay = if self.trail_decay > 0.0 { 0.0 } else { DEFAULT_TRAIL_DECAY };
+    }
+
+    pub fn cycle_color_mode(&mut self) {
+        self.color_mode = self.color_mode.next();
+    }
+}
+
+/// Frame timing statistics gathered without any GPU readback.
+pub struct RenderStats {
+    frame_time: RollingAverage,
+    // time spent waiting for the next surface texture, which rises when the GPU can't keep up
+    acquire_time: RollingAverage,
+    previous_frame: Option<Instant>,
+}
+
+impl Default for RenderStats {
+    fn default() -> Self {
+        Self {
+            frame_time: RollingAverage::new(STATS_WINDOW),
+            acquire_time: RollingAverage::new(STATS_WINDOW),
+            previous_frame: None,
+        }
+    }
+}
+
+impl RenderStats {
+    fn record_frame(
+        &mut self,
+        frame_start: Instant,
+        acquire_end: Instant,
+    ) {
+        if let Some(previous_frame) = self.previous_frame {
+            self.frame_time.push(frame_start - previous_frame);
+        }
+
+        self.acquire_time.push(acquire_end - frame_start);
+        self.previous_frame = Some(frame_start);
+    }
+
+    /// Frames per second averaged over the last few frames.
+    pub fn fps(&self) -> f32 {
+        let frame_time = self.frame_time.average().as_secs_f32();
+        if frame_time > 0.0 { 1.0 / frame_time } else { 0.0 }
+    }
+
+    /// Heuristic fraction of the frame time during which the GPU was busy, inferred from how long
+    /// acquiring the surface texture blocks. Only meaningful with a blocking present mode like Fifo.
+    pub fn gpu_busy(&self) -> f32 {
+        let frame_time = self.frame_time.average().as_secs_f32();
+        if frame_time > 0.0 {
+            (self.acquire_time.average().as_secs_f32() / frame_time).clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
 }
 
 pub struct RenderState {
     settings: RenderSettings,
+    stats: RenderStats,
+    camera: Camera,
+    camera_buffer: Buffer,
+    camera_bind_group: BindGroup,
+    // screen space camera for text and other overlays
+    overlay_camera_buffer: Buffer,
+    overlay_camera_bind_group: BindGroup,
     body_buffers: BodyBuffers,
+    generic_buffers: GenericBuffers,
+    // filled with every generic mesh in turn, so drawing them doesn't allocate once it has grown large enough
+    generic_mesh: Mesh,
+    trail_target: Option<TrailTarget>,
+    // only used when multisampling, the surface texture is the resolve target
+    multisample_target: Option<MultisampleTarget>,
+    // only used if the pipeline was created with a depth buffer
+    depth_target: Option<DepthTarget>,
+    // where to save the next frame
+    screenshot_path: Option<PathBuf>,
+    // relative to the top left corner of the window, the body below it is shown in the overlay
+    cursor_position: Option<PhysicalPosition<f64>>,
 }
 
 impl RenderState {
+    /// `num_instances` is the number of bodies the instance buffer is allocated for. It grows when more bodies
+    /// are visible, so it is only a hint.
     pub fn new(
-        device: &Device,
+        pipeline: &Pipeline,
         num_instances: usize,
     ) -> Self {
+        let device = &pipeline.device;
         let body_buffers = BodyBuffers::new(device, num_instances);
+        let generic_buffers = GenericBuffers::new(device);
+
+        let camera = Camera::default();
+        let (camera_buffer, camera_bind_group) =
+            create_camera_binding(pipeline, "Camera", CameraUniform::new(&camera, pipeline.size));
+        let (overlay_camera_buffer, overlay_camera_bind_group) =
+            create_camera_binding(pipeline, "Overlay Camera", CameraUniform::screen_space(pipeline.size));
 
         Self {
             settings: Default::default(),
+            stats: Default::default(),
+            camera,
+            camera_buffer,
+            camera_bind_group,
+            overlay_camera_buffer,
+            overlay_camera_bind_group,
             body_buffers,
+            generic_buffers,
+            generic_mesh: Mesh::default(),
+            trail_target: None,
+            multisample_target: None,
+            depth_target: None,
+            screenshot_path: None,
+            cursor_position: None,
         }
     }
 
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    /// Forgets what was drawn for the bodies so far, i.e. their trails. Needed when the simulation is reset,
+    /// since the bodies would otherwise leave trails to where the new ones start.
+    pub fn clear_history(&mut self) {
+        self.trail_target = None;
+    }
+
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    /// Moves the camera so the scene follows a cursor movement of `delta` pixels.
+    pub fn pan(
+        &mut self,
+        delta: Vector2<f32>,
+        size: PhysicalSize<u32>,
+    ) {
+        let delta = self.camera.pixels_to_world(delta, size);
+        self.camera.pan(-delta);
+    }
+
+    pub fn zoom(
+        &mut self,
+        factor: f32,
+    ) {
+        self.camera.zoom_by(factor);
+    }
+
+    /// Frames all bodies of the simulation, with a small margin around them.
+    pub fn fit_camera(
+        &mut self,
+        simulation: &Simulation,
+        size: PhysicalSize<u32>,
+    ) {
+        let (min, max) = simulation.bounding_box();
+        let margin = (max - min) * FIT_MARGIN;
+        self.camera.fit_to((min - margin, max + margin), size);
+    }
+
+    /// Sets where the cursor is, or [None] if it left the window.
+    pub fn set_cursor_position(
+        &mut self,
+        cursor_position: Option<PhysicalPosition<f64>>,
+    ) {
+        self.cursor_position = cursor_position;
+    }
+
     pub fn settings_mut(&mut self) -> &mut RenderSettings {
         &mut self.settings
     }
 
+    pub fn stats(&self) -> &RenderStats {
+        &self.stats
+    }
+
+    /// Saves the next rendered frame as a PNG image. Rendering that frame blocks until it has been written.
+    pub fn request_screenshot(
+        &mut self,
+        path: impl Into<PathBuf>,
+    ) {
+        self.screenshot_path = Some(path.into());
+    }
+
+    /// What the next frame has to be captured with for the enabled views. Views whose parts are missing from
+    /// a frame are left out, or drawn from the circles of the bodies, until a frame with them arrives.
+    pub fn frame_request(
+        &self,
+        size: PhysicalSize<u32>,
+    ) -> FrameRequest {
+        let settings = &self.settings;
+        let pick_point = self.cursor_position.map(|cursor| self.camera.screen_to_world(cursor, size));
+        FrameRequest {
+            bodies: settings.color_mode != ColorMode::Fixed || settings.draw_velocities || settings.level_of_detail,
+            neighbor_counts: settings.color_mode == ColorMode::Density,
+            quadtree: settings.draw_tree || settings.draw_pseudobodies || settings.level_of_detail,
+            stats: settings.draw_overlay || settings.follow_center_of_mass,
+            pick_point: pick_point.filter(|_| settings.draw_overlay),
+        }
+    }
+
+    /// Draws a frame to the surface and presents it. Panics if the pipeline is headless, which can only
+    /// [render to textures](RenderState::render_to_texture).
     pub fn render(
         &mut self,
         pipeline: &mut Pipeline,
-        simulation: &Simulation,
+        frame: &BodyFrame,
     ) -> Result<(), SurfaceError> {
-        let output = pipeline.surface.get_current_texture()?;
+        let frame_start = Instant::now();
+        let surface = pipeline.surface.as_ref().expect("headless pipelines have no surface to render to");
+        let output = surface.get_current_texture()?;
+        self.stats.record_frame(frame_start, Instant::now());
+
         let view = output
             .texture
             .create_view(&TextureViewDescriptor::default());
 
-        // create command encoder and render pass
         let mut encoder = pipeline.start_encoder();
-        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-            label: Some("Render Pass"),
-            color_attachments: &[Some(RenderPassColorAttachment {
-                view: &view,
-                resolve_target: None,
-                ops: Operations {
-                    load: LoadOp::Clear(Color {
-                        r: 0.001,
-                        g: 0.001,
-                        b: 0.002,
-                        a: 1.0,
-                    }),
-                    store: StoreOp::Store,
-                },
-            })],
-            depth_stencil_attachment: None,
-            timestamp_writes: None,
-            occlusion_query_set: None,
+
+        // a captured frame is drawn into an offscreen texture, which is copied to the surface afterwards
+        let capture = self.screenshot_path.take().and_then(|path| match Capture::new(pipeline) {
+            Ok(capture) => Some((path, capture)),
+            Err(e) => {
+                log::error!("Can't take screenshot: {}", e);
+                None
+            }
         });
+        let target_view = capture.as_ref().map_or(&view, |(_, capture)| capture.view());
+
+        self.draw_frame(pipeline, &mut encoder, target_view, frame)?;
 
-        self.render_bodies(pipeline, &mut render_pass, simulation.bodies())?;
+        if let Some((_, capture)) = &capture {
+            let mut render_pass = begin_render_pass(
+                &mut encoder,
+                &view,
+                self.multisample_target.as_ref(),
+                self.depth_target.as_ref(),
+                LoadOp::Clear(self.settings.background),
+            );
+            capture.blit(pipeline, &mut render_pass);
+            drop(render_pass);
 
-        if self.settings.draw_tree {
-            let quadtree_mesh = generate_quadtree_mesh(simulation.quadtree());
-            self.render_generic(pipeline, &mut render_pass, &quadtree_mesh.vertices, &quadtree_mesh.indices)?;
+            capture.copy_to_buffer(&mut encoder);
         }
 
-        drop(render_pass);
         pipeline.queue.submit(std::iter::once(encoder.finish()));
         output.present();
 
+        if let Some((path, capture)) = capture {
+            match capture.save(pipeline, &path) {
+                Ok(()) => log::info!("Saved screenshot to {}", path.display()),
+                Err(e) => log::error!("Couldn't save screenshot to {}: {}", path.display(), e),
+            }
+        }
+
         Ok(())
     }
+
+    /// Draws a frame like [RenderState::render], but into a texture of the pipeline's size instead of the
+    /// surface, and reads it back as opaque RGBA rows from top to bottom. Blocks until the frame is rendered.
+    /// Works with headless pipelines, so frames can be compared without a window.
+    pub fn render_to_texture(
+        &mut self,
+        pipeline: &mut Pipeline,
+        frame: &BodyFrame,
+    ) -> Result<Vec<u8>, ScreenshotError> {
+        let capture = Capture::new(pipeline)?;
+        let mut encoder = pipeline.start_encoder();
+
+        self.draw_frame(pipeline, &mut encoder, capture.view(), frame)?;
+        capture.copy_to_buffer(&mut encoder);
+        pipeline.queue.submit(std::iter::once(encoder.finish()));
+
+        capture.read_pixels(pipeline)
+    }
+
+    // records all passes of a frame drawn into the view
+    fn draw_frame(
+        &mut self,
+        pipeline: &mut Pipeline,
+        encoder: &mut CommandEncoder,
+        target_view: &TextureView,
+        frame: &BodyFrame,
+    ) -> Result<(), SurfaceError> {
+        if let (true, Some(stats)) = (self.settings.follow_center_of_mass, frame.stats()) {
+            self.camera.center = stats.center_of_mass;
+        }
+
+        self.generic_buffers.start_frame();
+        pipeline.queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[CameraUniform::new(&self.camera, pipeline.size)]),
+        );
+        pipeline.queue.write_buffer(
+            &self.overlay_camera_buffer,
+            0,
+            bytemuck::cast_slice(&[CameraUniform::screen_space(pipeline.size)]),
+        );
+
+        self.multisample_target = match self.multisample_target.take() {
+            _ if pipeline.sample_count == 1 => None,
+            Some(multisample_target) if multisample_target.matches(pipeline) => Some(multisample_target),
+            _ => Some(MultisampleTarget::new(pipeline, "Multisampled Surface Texture")),
+        };
+
+        self.depth_target = match self.depth_target.take() {
+            Some(depth_target) if depth_target.matches(pipeline) => Some(depth_target),
+            _ => DepthTarget::new(pipeline),
+        };
+
+        // circles alone are drawn in their own colors
+        let coloring = frame.body_items().map(|body_items| {
+            let neighbor_counts = frame.neighbor_counts().filter(|_| self.settings.color_mode == ColorMode::Density);
+            BodyColoring::new(self.settings.color_mode, &self.settings.colormap, body_items, neighbor_counts)
+        });
+
+        let trail_decay = self.settings.trail_decay;
+        if trail_decay > 0.0 {
+            // bodies go into the trail texture, which is faded instead of cleared. helpers are drawn on top
+            // after copying it to the surface so they don't leave trails themselves
+            let trail_target = match self.trail_target.take() {
+                Some(trail_target) if trail_target.matches(pipeline) => trail_target,
+                _ => TrailTarget::new(pipeline),
+            };
+
+            let mut render_pass = begin_render_pass(
+                encoder,
+                trail_target.view(),
+                trail_target.multisample_target(),
+                self.depth_target.as_ref(),
+                LoadOp::Load,
+            );
+            trail_target.fade(pipeline, &mut render_pass, trail_decay);
+            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            self.draw_bodies(pipeline, &mut render_pass, frame, coloring.as_ref())?;
+            drop(render_pass);
+
+            let mut render_pass = begin_render_pass(
+                encoder,
+                target_view,
+                self.multisample_target.as_ref(),
+                self.depth_target.as_ref(),
+                LoadOp::Clear(self.settings.background),
+            );
+            trail_target.blit(pipeline, &mut render_pass);
+            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            // the grid covers the trails, but it is faint enough to not hide them
+            self.draw_grid(pipeline, &mut render_pass)?;
+            self.draw_helpers(pipeline, &mut render_pass, frame)?;
+            drop(render_pass);
+
+            self.trail_target = Some(trail_target);
+        } else {
+            // the trail texture is dropped so trails start out empty when enabled again
+            self.trail_target = None;
+
+            let mut render_pass = begin_render_pass(
+                encoder,
+                target_view,
+                self.multisample_target.as_ref(),
+                self.depth_target.as_ref(),
+                LoadOp::Clear(self.settings.background),
+            );
+            // both pipelines share the camera at group 0
+            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            self.draw_grid(pipeline, &mut render_pass)?;
+            self.draw_bodies(pipeline, &mut render_pass, frame, coloring.as_ref())?;
+            self.draw_helpers(pipeline, &mut render_pass, frame)?;
+            drop(render_pass);
+        }
+
+        Ok(())
+    }
+
+    fn draw_bodies(
+        &mut self,
+        pipeline: &mut Pipeline,
+        render_pass: &mut RenderPass,
+        frame: &BodyFrame,
+        coloring: Option<&BodyColoring>,
+    ) -> Result<(), SurfaceError> {
+        // bodies are captured in ascending key order, so they are drawn in the same order every frame
+        let (Some(body_items), Some(coloring)) = (frame.body_items(), coloring) else {
+            return self.render_circles(pipeline, render_pass, frame.circles().iter().copied());
+        };
+
+        match frame.quadtree() {
+            Some(quadtree) if self.settings.level_of_detail => {
+                self.render_level_of_detail(pipeline, render_pass, frame, quadtree, coloring)
+            }
+            _ => self.render_bodies(pipeline, render_pass, body_items, coloring),
+        }
+    }
+
+    // drawn before the bodies so they cover it
+    fn draw_grid(
+        &mut self,
+        pipeline: &mut Pipeline,
+        render_pass: &mut RenderPass,
+    ) -> Result<(), SurfaceError> {
+        if !self.settings.draw_grid {
+            return Ok(());
+        }
+
+        let (camera, size) = (self.camera, pipeline.size);
+        self.render_mesh(pipeline, render_pass, |mesh| generate_grid_mesh(mesh, &camera, size))
+    }
+
+    // draws the debugging aids enabled in the settings
+    fn draw_helpers(
+        &mut self,
+        pipeline: &mut Pipeline,
+        render_pass: &mut RenderPass,
+        frame: &BodyFrame,
+    ) -> Result<(), SurfaceError> {
+        if let (true, Some(body_items)) = (self.settings.draw_velocities, frame.body_items()) {
+            let bodies = body_items.map(|(_, body)| body);
+            self.render_mesh(pipeline, render_pass, |mesh| generate_velocity_mesh(mesh, bodies))?;
+        }
+
+        if let (true, Some(quadtree)) = (self.settings.draw_tree, frame.quadtree()) {
+            self.render_mesh(pipeline, render_pass, |mesh| generate_quadtree_mesh(mesh, quadtree))?;
+        }
+
+        if let (true, Some(quadtree)) = (self.settings.draw_pseudobodies, frame.quadtree()) {
+            self.render_mesh(pipeline, render_pass, |mesh| generate_pseudobody_mesh(mesh, quadtree))?;
+        }
+
+        // the overlay shows the stats of the step, which only frames captured for it have
+        let overlay_stats = frame.stats().filter(|_| self.settings.draw_overlay);
+
+        // screen space meshes are drawn last since they replace the camera
+        if self.settings.draw_scale_bar || overlay_stats.is_some() {
+            render_pass.set_bind_group(0, &self.overlay_camera_bind_group, &[]);
+        }
+
+        if self.settings.draw_scale_bar {
+            let (camera, size) = (self.camera, pipeline.size);
+            self.render_mesh(pipeline, render_pass, |mesh| generate_scale_bar_mesh(mesh, &camera, size))?;
+        }
+
+        if let Some(stats) = overlay_stats {
+            let (tree_stats, step_stats) = (stats.quadtree_stats, stats.step_stats);
+            let millis = |duration: std::time::Duration| duration.as_secs_f32() * 1000.0;
+            let mut overlay_text = format!(
+                concat!(
+                    "FPS: {:.1}\nBODIES: {} ({} VISIBLE)\nTIME: {:.2} S ({} STEPS)\nSTEP: {:.1} MS\n",
+                    "  TREE: {:.1} MS\n  AGGREGATE: {:.1} MS\n  FORCE: {:.1} MS\n  INTEGRATE: {:.1} MS\n",
+                    "THETA: {:.2}\nNODES: {} ({} LEAVES)\nDEPTH: {} MAX, {:.1} AVG"
+                ),
+                self.stats.fps(),
+                stats.body_count,
+                self.body_buffers.num_instances(),
+                stats.sim_time,
+                stats.step_count,
+                millis(stats.step_duration),
+                millis(step_stats.tree_build),
+                millis(step_stats.aggregate),
+                millis(step_stats.force),
+                millis(step_stats.integrate),
+                stats.threshold,
+                tree_stats.node_count,
+                tree_stats.leaf_count,
+                tree_stats.max_depth,
+                tree_stats.average_leaf_depth,
+            );
+            if let Some((body_key, body)) = self.hovered_body(frame, pipeline.size) {
+                overlay_text += &format!(
+                    "\nBODY: {}\n  MASS: {:.3}\n  SPEED: {:.3}",
+                    body_key.to_index(),
+                    body.mass,
+                    body.velocity.magnitude(),
+                );
+            }
+
+            let position = Point2::new(OVERLAY_MARGIN, OVERLAY_MARGIN);
+            self.render_mesh(pipeline, render_pass, |mesh| {
+                push_text(mesh, &overlay_text, position, OVERLAY_SCALE, Color::WHITE)
+            })?;
+        }
+
+        Ok(())
+    }
+
+    // the body closest to the cursor, if it is close enough to be pointed at
+    fn hovered_body<'a>(
+        &self,
+        frame: &'a BodyFrame,
+        size: PhysicalSize<u32>,
+    ) -> Option<(BodyKey, &'a Body)> {
+        // the nearest body is found when capturing the frame, which may have been a step before the cursor
+        // moved here
+        let cursor = self.camera.screen_to_world(self.cursor_position?, size);
+        let (body_key, body) = frame.nearest_body()?;
+
+        let pick_radius = self.camera.pixels_to_world(Vector2::new(HOVER_PIXELS, 0.0), size).x;
+        ((body.position - cursor).magnitude() <= body.radius().max(pick_radius)).then_some((body_key, body))
+    }
+}
+
+fn create_camera_binding(
+    pipeline: &Pipeline,
+    label: &str,
+    uniform: CameraUniform,
+) -> (Buffer, BindGroup) {
+    let buffer = pipeline.device.create_buffer_init(&BufferInitDescriptor {
+        label: Some(&format!("{} Buffer", label)),
+        contents: bytemuck::cast_slice(&[uniform]),
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+    });
+
+    let bind_group = pipeline.device.create_bind_group(&BindGroupDescriptor {
+        label: Some(&format!("{} Bind Group", label)),
+        layout: &pipeline.camera_bind_group_layout,
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: buffer.as_entire_binding(),
+        }],
+    });
+
+    (buffer, bind_group)
+}
+
+// when multisampling, the pass draws into the multisample target and resolves into the view. the
+// multisample target is always stored, it is only loaded again by the trail pass
+fn begin_render_pass<'a>(
+    encoder: &'a mut CommandEncoder,
+    view: &TextureView,
+    multisample_target: Option<&MultisampleTarget>,
+    depth_target: Option<&DepthTarget>,
+    load: LoadOp<Color>,
+) -> RenderPass<'a> {
+    let (view, resolve_target) = match multisample_target {
+        Some(multisample_target) => (multisample_target.view(), Some(view)),
+        None => (view, None),
+    };
+
+    encoder.begin_render_pass(&RenderPassDescriptor {
+        label: Some("Render Pass"),
+        color_attachments: &[Some(RenderPassColorAttachment {
+            view,
+            resolve_target,
+            ops: Operations {
+                load,
+                store: StoreOp::Store,
+            },
+        })],
+        // depth only orders the bodies within a pass, so it doesn't need to be kept
+        depth_stencil_attachment: depth_target.map(|depth_target| RenderPassDepthStencilAttachment {
+            view: depth_target.view(),
+            depth_ops: Some(Operations {
+                load: LoadOp::Clear(0.0),
+                store: StoreOp::Discard,
+            }),
+            stencil_ops: None,
+        }),
+        timestamp_writes: None,
+        occlusion_query_set: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Mutex,
+        mpsc,
+    };
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn rgb_scales_bytes_to_the_unit_interval() {
+        assert_eq!(rgb(0, 0, 0), Color { r: 0.0, g: 0.0, b: 0.0, a: 1.0 });
+        assert_eq!(rgb(255, 255, 255), Color { r: 1.0, g: 1.0, b: 1.0, a: 1.0 });
+    }
+
+    #[test]
+    fn gpu_busy_is_the_acquire_share_of_the_frame_time() {
+        let mut stats = RenderStats::default();
+        let mut frame_start = Instant::now();
+
+        // 20ms frames of which 5ms are spent waiting for the surface
+        for _ in 0..2 * STATS_WINDOW {
+            stats.record_frame(frame_start, frame_start + Duration::from_millis(5));
+            frame_start += Duration::from_millis(20);
+        }
+
+        assert!((stats.fps() - 50.0).abs() < 1e-3);
+        assert!((stats.gpu_busy() - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn gpu_busy_is_clamped() {
+        let mut stats = RenderStats::default();
+        let frame_start = Instant::now();
+
+        // the first frame has no frame time yet
+        stats.record_frame(frame_start, frame_start + Duration::from_millis(5));
+        assert_eq!(stats.gpu_busy(), 0.0);
+
+        stats.record_frame(frame_start + Duration::from_millis(1), frame_start + Duration::from_millis(30));
+        assert_eq!(stats.gpu_busy(), 1.0);
+    }
+
+    #[test]
+    fn frames_are_colored_while_the_simulation_is_locked() {
+        let size = PhysicalSize::new(64, 64);
+        let Ok(mut pipeline) = pollster::block_on(Pipeline::new_headless(size, 1, false)) else {
+            eprintln!("No adapter for headless rendering, skipping");
+            return;
+        };
+
+        let mut render_state = RenderState::new(&pipeline, 1);
+        render_state.settings_mut().color_mode = ColorMode::Speed;
+
+        // the body is white, while the default colormap starts with blue
+        let body = Body::new(Point2::new(0.0, 0.0), Vector2::new(0.0, 0.0), 1.0, 0.5, Color::WHITE);
+        let simulation = Mutex::new(Simulation::new(std::iter::once(body), 0.5));
+        let mut frame = BodyFrame::default();
+        frame.capture(&simulation.lock().unwrap(), render_state.frame_request(size));
+
+        let (locked_sender, locked_receiver) = mpsc::channel();
+        let (done_sender, done_receiver) = mpsc::channel();
+        let pixels = std::thread::scope(|scope| {
+            // stands in for the simulation thread in the middle of a step
+            let simulation = &simulation;
+            scope.spawn(move || {
+                let _simulation = simulation.lock().unwrap();
+                locked_sender.send(()).unwrap();
+                done_receiver.recv().unwrap();
+            });
+
+            locked_receiver.recv().unwrap();
+            assert!(simulation.try_lock().is_err());
+            let pixels = render_state.render_to_texture(&mut pipeline, &frame);
+            done_sender.send(()).unwrap();
+            pixels
+        })
+        .unwrap();
+
+        let center = 4 * (size.width as usize * size.height as usize / 2 + size.width as usize / 2);
+        let [r, g, b, _] = pixels[center..center + 4] else {
+            unreachable!()
+        };
+        assert!(b > r.saturating_add(100) && b > g.saturating_add(100), "center is ({r}, {g}, {b})");
+    }
 }
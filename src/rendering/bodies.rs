@@ -1,11 +1,35 @@
-use bytemuck::Zeroable;
+use cgmath::{
+    Point2,
+    Vector2,
+};
 use wgpu::{
-    util::{BufferInitDescriptor, DeviceExt}, Buffer, BufferAddress, BufferDescriptor, BufferUsages, Color, CommandEncoder, Device, IndexFormat, LoadOp, Operations, RenderPass, RenderPassColorAttachment, RenderPassDescriptor, StoreOp, SurfaceError, TextureViewDescriptor, VertexAttribute, VertexBufferLayout, VertexFormat, VertexStepMode
+    util::{BufferInitDescriptor, DeviceExt}, Buffer, BufferAddress, BufferDescriptor, BufferUsages, Color, Device, IndexFormat, RenderPass, SurfaceError, VertexAttribute, VertexBufferLayout, VertexFormat, VertexStepMode
 };
 
-use crate::{pipeline::Pipeline, simulation::{quadtree::Positioned, Body}};
+use crate::pipeline::Pipeline;
+use crate::simulation::quadtree::{
+    ContinueTraverse,
+    Quadtree,
+    QuadtreeChild,
+    QuadtreeNode,
+};
+use crate::simulation::{
+    Body,
+    BodyKey,
+    Pseudobody,
+    QuadtreeBody,
+    SimFloat,
+};
 
 use super::RenderState;
+use super::coloring::BodyColoring;
+use super::source::{
+    BodyCircle,
+    BodyFrame,
+};
+
+// nodes smaller than this many pixels on screen are drawn as a single circle in level of detail mode
+const LOD_NODE_PIXELS: f32 = 2.0;
 
 const QUAD_VERTICES: &[CircleVertex] = &[
     CircleVertex {
@@ -53,6 +77,18 @@ pub struct BodyInstance {
 }
 
 impl BodyInstance {
+    fn new(
+        position: Point2<SimFloat>,
+        color: Color,
+        radius: SimFloat,
+    ) -> Self {
+        Self {
+            position: [position.x, position.y],
+            color: [color.r as f32, color.g as f32, color.b as f32, color.a as f32],
+            radius,
+        }
+    }
+
     pub fn layout() -> VertexBufferLayout<'static> {
         VertexBufferLayout {
             array_stride: std::mem::size_of::<BodyInstance>() as u64,
@@ -83,11 +119,23 @@ pub(super) struct BodyBuffers {
     index_buffer: Buffer,
     num_indices: u32,
     instance_buffer: Buffer,
-    // must be the same for every render call
+    // number of instances the instance buffer can hold
+    instance_capacity: usize,
+    // number of instances drawn in the last render call
     num_instances: u32,
     instances: Vec<BodyInstance>,
 }
 
+fn create_instance_buffer(device: &Device, capacity: usize) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some("Instance Buffer"),
+        size: BufferAddress::try_from(capacity.max(1) * std::mem::size_of::<BodyInstance>())
+            .unwrap(),
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
 impl BodyBuffers {
     pub(super) fn new(device: &Device, num_instances: usize) -> Self {
         let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
@@ -104,25 +152,35 @@ impl BodyBuffers {
 
         let num_indices = QUAD_INDICES.len() as u32;
 
-        let instance_buffer = device.create_buffer(&BufferDescriptor {
-            label: Some("Instance Buffer"),
-            size: BufferAddress::try_from(num_instances * std::mem::size_of::<BodyInstance>())
-                .unwrap(),
-            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
+        let instance_buffer = create_instance_buffer(device, num_instances);
 
-        let instances = vec![BodyInstance::zeroed(); num_instances];
+        let instances = Vec::with_capacity(num_instances);
 
         Self {
             vertex_buffer,
             index_buffer,
             num_indices,
             instance_buffer,
-            num_instances: u32::try_from(num_instances).unwrap(),
+            instance_capacity: num_instances,
+            num_instances: 0,
             instances,
         }
     }
+
+    /// The number of bodies drawn by the last call to `render_bodies`.
+    pub(super) fn num_instances(&self) -> u32 {
+        self.num_instances
+    }
+
+    // reallocates the instance buffer if it can't hold the given number of instances. like a Vec, the capacity
+    // at least doubles, so a slowly growing body count doesn't reallocate every frame. it never shrinks
+    fn reserve(&mut self, device: &Device, num_instances: usize) {
+        if num_instances > self.instance_capacity {
+            let capacity = num_instances.max(2 * self.instance_capacity);
+            self.instance_buffer = create_instance_buffer(device, capacity);
+            self.instance_capacity = capacity;
+        }
+    }
 }
 
 impl RenderState {
@@ -130,29 +188,121 @@ impl RenderState {
         &mut self,
         pipeline: &mut Pipeline,
         render_pass: &mut RenderPass,
-        bodies: impl ExactSizeIterator<Item = &'a Body>,
+        bodies: impl Iterator<Item = (BodyKey, &'a Body)>,
+        coloring: &BodyColoring,
     ) -> Result<(), SurfaceError> {
-        let bufs = &mut self.body_buffers;
+        let (min, max) = self.camera.view_bounds(pipeline.size);
 
-        assert!(
-            bodies.len() == bufs.num_instances as usize,
-            "Number of bodies must not change across rendering calls"
-        );
+        let instances = &mut self.body_buffers.instances;
+        instances.clear();
+        let visible = bodies.filter(|(_, body)| is_visible(body.position, body.radius(), min, max));
+        instances.extend(visible.map(|(body_key, body)| {
+            BodyInstance::new(body.position, coloring.color(body_key, body), body.radius())
+        }));
 
-        render_pass.set_pipeline(&pipeline.circle_pipeline);
+        self.draw_instances(pipeline, render_pass);
 
-        for (instance, body) in bufs.instances.iter_mut().zip(bodies) {
-            *instance = BodyInstance {
-                position: [body.position.x, body.position.y],
-                color: [
-                    body.color().r as f32,
-                    body.color().g as f32,
-                    body.color().b as f32,
-                    body.color().a as f32,
-                ],
-                radius: body.radius(),
+        Ok(())
+    }
+
+    /// Draws bodies in their own colors from their circles alone, e.g. from a
+    /// [BodyFrame](super::source::BodyFrame).
+    pub(super) fn render_circles(
+        &mut self,
+        pipeline: &mut Pipeline,
+        render_pass: &mut RenderPass,
+        circles: impl Iterator<Item = BodyCircle>,
+    ) -> Result<(), SurfaceError> {
+        let (min, max) = self.camera.view_bounds(pipeline.size);
+
+        let instances = &mut self.body_buffers.instances;
+        instances.clear();
+        let visible = circles.filter(|circle| is_visible(circle.position, circle.radius, min, max));
+        instances.extend(visible.map(|circle| BodyInstance::new(circle.position, circle.color, circle.radius)));
+
+        self.draw_instances(pipeline, render_pass);
+
+        Ok(())
+    }
+
+    /// Draws the bodies of a node that appears smaller than [LOD_NODE_PIXELS] on screen as a single circle
+    /// at its pseudobody covering the node, like distant nodes are approximated when calculating forces.
+    /// The number of circles is then bounded by how many nodes fit on screen instead of the number of
+    /// bodies. The circle takes the color of one of the node's bodies.
+    pub(super) fn render_level_of_detail(
+        &mut self,
+        pipeline: &mut Pipeline,
+        render_pass: &mut RenderPass,
+        frame: &BodyFrame,
+        quadtree: &Quadtree<QuadtreeBody, Pseudobody>,
+        coloring: &BodyColoring,
+    ) -> Result<(), SurfaceError> {
+        let (min, max) = self.camera.view_bounds(pipeline.size);
+        let pixel_size = self.camera.pixels_to_world(Vector2::new(1.0, 0.0), pipeline.size).x;
+
+        // bodies may reach out of their node by their radius, so nodes are culled against a larger view
+        let max_radius = frame.circles().iter().map(|circle| circle.radius).fold(0.0, SimFloat::max);
+        let margin = Vector2::new(max_radius, max_radius);
+        let (padded_min, padded_max) = (min - margin, max + margin);
+
+        let instances = &mut self.body_buffers.instances;
+        instances.clear();
+
+        quadtree.traverse(|node, _| {
+            let (node_min, node_max) = (node.position - node.extent, node.position + node.extent);
+            if node_max.x < padded_min.x
+                || node_min.x > padded_max.x
+                || node_max.y < padded_min.y
+                || node_min.y > padded_max.y
+            {
+                return ContinueTraverse::Stop;
             }
-        }
+
+            match &node.child_key {
+                QuadtreeChild::Node(_) if node.width() < LOD_NODE_PIXELS * pixel_size => {
+                    let body_key = first_element(quadtree, node).map(QuadtreeBody::body_key);
+                    if let Some((body_key, body)) = body_key.and_then(|key| Some((key, frame.body(key)?))) {
+                        let radius = node.width().max(body.radius());
+                        let color = coloring.color(body_key, body);
+                        // nodes of only tracers have no center of mass
+                        let position = if node.data.mass() > 0.0 { node.data.position() } else { node.position };
+                        instances.push(BodyInstance::new(position, color, radius));
+                    }
+                    ContinueTraverse::Stop
+                }
+                QuadtreeChild::Node(_) => ContinueTraverse::Continue,
+                QuadtreeChild::Elements(element_keys) => {
+                    let bodies = element_keys.iter().filter_map(|key| {
+                        let body_key = quadtree.element(*key).body_key();
+                        Some((body_key, frame.body(body_key)?))
+                    });
+                    let visible = bodies.filter(|(_, body)| is_visible(body.position, body.radius(), min, max));
+                    instances.extend(visible.map(|(body_key, body)| {
+                        BodyInstance::new(body.position, coloring.color(body_key, body), body.radius())
+                    }));
+                    ContinueTraverse::Continue
+                }
+            }
+        });
+
+        self.draw_instances(pipeline, render_pass);
+
+        Ok(())
+    }
+
+    // uploads the instances collected for this frame and draws them
+    fn draw_instances(
+        &mut self,
+        pipeline: &mut Pipeline,
+        render_pass: &mut RenderPass,
+    ) {
+        let bufs = &mut self.body_buffers;
+
+        // the number of visible bodies changes between calls, grow the buffer if needed
+        bufs.reserve(&pipeline.device, bufs.instances.len());
+        bufs.num_instances = u32::try_from(bufs.instances.len()).unwrap();
+
+        render_pass.set_pipeline(&pipeline.circle_pipeline);
 
         pipeline.queue.write_buffer(
             &bufs.instance_buffer,
@@ -165,7 +315,33 @@ impl RenderState {
         render_pass.set_index_buffer(bufs.index_buffer.slice(..), IndexFormat::Uint16);
 
         render_pass.draw_indexed(0..bufs.num_indices, 0, 0..bufs.num_instances);
+    }
+}
 
-        Ok(())
+// whether any part of a body's quad lies inside the view. the quad spans half the radius in each direction,
+// checking against the full radius leaves some margin
+fn is_visible(
+    position: Point2<SimFloat>,
+    radius: SimFloat,
+    min: Point2<SimFloat>,
+    max: Point2<SimFloat>,
+) -> bool {
+    position.x + radius >= min.x
+        && position.x - radius <= max.x
+        && position.y + radius >= min.y
+        && position.y - radius <= max.y
+}
+
+// the first element below a node, skipping empty children
+fn first_element<'a>(
+    quadtree: &'a Quadtree<QuadtreeBody, Pseudobody>,
+    node: &QuadtreeNode<Pseudobody>,
+) -> Option<&'a QuadtreeBody> {
+    match &node.child_key {
+        QuadtreeChild::Elements(element_keys) => element_keys.first().map(|key| quadtree.element(*key)),
+        QuadtreeChild::Node(children_key) => quadtree
+            .child_keys(*children_key)
+            .filter_map(|child_key| quadtree.nodes()[child_key].as_ref())
+            .find_map(|child| first_element(quadtree, child)),
     }
 }
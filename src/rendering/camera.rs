@@ -0,0 +1,138 @@
+use cgmath::{
+    Matrix4,
+    Point2,
+    Vector2,
+    Vector3,
+};
+use winit::dpi::{
+    PhysicalPosition,
+    PhysicalSize,
+};
+
+// limits for the zoom factor so the view transform stays invertible
+const MIN_ZOOM: f32 = 1e-4;
+const MAX_ZOOM: f32 = 1e6;
+
+/// A 2D camera looking at `center`, with `zoom` being the scale from world to normalized device coordinates.
+/// At a zoom of 1, the view spans two world units vertically and proportionally more or less horizontally,
+/// depending on the aspect ratio of the window.
+#[derive(Debug, Clone, Copy)]
+pub struct Camera {
+    pub center: Point2<f32>,
+    pub zoom: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            center: Point2::new(0.0, 0.0),
+            zoom: 1.0,
+        }
+    }
+}
+
+impl Camera {
+    /// Moves the view by `delta` in world units.
+    pub fn pan(
+        &mut self,
+        delta: Vector2<f32>,
+    ) {
+        self.center += delta;
+    }
+
+    /// Multiplies the zoom by `factor`, keeping the center fixed.
+    pub fn zoom_by(
+        &mut self,
+        factor: f32,
+    ) {
+        self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+
+    /// Centers the view on the box between the corners `min` and `max` and zooms so it just fits on a surface
+    /// of the given size. The default zoom is used if the box has no size, like for a single body.
+    pub fn fit_to(
+        &mut self,
+        (min, max): (Point2<f32>, Point2<f32>),
+        size: PhysicalSize<u32>,
+    ) {
+        self.center = Point2::new(0.5 * (min.x + max.x), 0.5 * (min.y + max.y));
+
+        // the view spans 2 / zoom units vertically and the aspect ratio times that horizontally
+        let aspect_ratio = size.width.max(1) as f32 / size.height.max(1) as f32;
+        let zoom = (2.0 / (max.y - min.y)).min(2.0 * aspect_ratio / (max.x - min.x));
+        self.zoom = if zoom.is_finite() { zoom.clamp(MIN_ZOOM, MAX_ZOOM) } else { Camera::default().zoom };
+    }
+
+    /// Converts a distance on screen in pixels into world units.
+    pub fn pixels_to_world(
+        &self,
+        delta: Vector2<f32>,
+        size: PhysicalSize<u32>,
+    ) -> Vector2<f32> {
+        // the window height spans two units in normalized device coordinates, with y pointing up. both
+        // axes are scaled equally
+        Vector2::new(delta.x, -delta.y) * 2.0 / (size.height.max(1) as f32 * self.zoom)
+    }
+
+    /// Converts a cursor position, relative to the top left corner of a window of the given size, into
+    /// world coordinates.
+    pub fn screen_to_world(
+        &self,
+        cursor: PhysicalPosition<f64>,
+        size: PhysicalSize<u32>,
+    ) -> Point2<f32> {
+        let half_size = Vector2::new(size.width as f32, size.height as f32) * 0.5;
+        let offset = Vector2::new(cursor.x as f32, cursor.y as f32) - half_size;
+        self.center + self.pixels_to_world(offset, size)
+    }
+
+    /// The corners of the region visible on a surface of the given size in world coordinates, with the
+    /// minimum first.
+    pub fn view_bounds(
+        &self,
+        size: PhysicalSize<u32>,
+    ) -> (Point2<f32>, Point2<f32>) {
+        // screen y points down, world y up
+        let half_extent = self.pixels_to_world(Vector2::new(size.width as f32, -(size.height as f32)), size) * 0.5;
+        (self.center - half_extent, self.center + half_extent)
+    }
+
+    /// The transform from world to normalized device coordinates for a surface of the given size.
+    pub fn view_matrix(
+        &self,
+        size: PhysicalSize<u32>,
+    ) -> Matrix4<f32> {
+        let aspect_ratio = size.width.max(1) as f32 / size.height.max(1) as f32;
+
+        Matrix4::from_nonuniform_scale(self.zoom / aspect_ratio, self.zoom, 1.0)
+            * Matrix4::from_translation(Vector3::new(-self.center.x, -self.center.y, 0.0))
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CameraUniform {
+    view: [[f32; 4]; 4],
+}
+
+impl CameraUniform {
+    pub fn new(
+        camera: &Camera,
+        size: PhysicalSize<u32>,
+    ) -> Self {
+        Self {
+            view: camera.view_matrix(size).into(),
+        }
+    }
+
+    /// A transform from pixels relative to the top left corner of a surface of the given size to
+    /// normalized device coordinates, for drawing in screen space.
+    pub fn screen_space(size: PhysicalSize<u32>) -> Self {
+        let (width, height) = (size.width.max(1) as f32, size.height.max(1) as f32);
+        let scale = Matrix4::from_nonuniform_scale(2.0 / width, -2.0 / height, 1.0);
+
+        Self {
+            view: (Matrix4::from_translation(Vector3::new(-1.0, 1.0, 0.0)) * scale).into(),
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use cgmath::InnerSpace;
+use wgpu::Color;
+
+use crate::simulation::{
+    Body,
+    BodyKey,
+    SimFloat,
+    Simulation,
+};
+use crate::utility::index_map::SecondaryMap;
+
+// bodies closer than this count towards the density of a body
+const DENSITY_RADIUS: SimFloat = 0.01;
+
+/// Decides which property of a body determines its color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorMode {
+    /// The color stored in the body.
+    #[default]
+    Fixed,
+    /// The magnitude of the velocity, mapped through the colormap.
+    Speed,
+    /// The mass, mapped through the colormap.
+    Mass,
+    /// The number of other bodies within a small radius, mapped through the colormap on a logarithmic scale
+    /// so both sparse regions and cluster cores are told apart.
+    Density,
+}
+
+impl ColorMode {
+    /// The mode after this one, wrapping around. Used for cycling through modes with a key.
+    pub fn next(self) -> Self {
+        match self {
+            ColorMode::Fixed => ColorMode::Speed,
+            ColorMode::Speed => ColorMode::Mass,
+            ColorMode::Mass => ColorMode::Density,
+            ColorMode::Density => ColorMode::Fixed,
+        }
+    }
+}
+
+/// Maps values between 0 and 1 to colors by blending linearly between evenly spaced stops.
+#[derive(Debug, Clone)]
+pub struct Colormap {
+    stops: Vec<Color>,
+}
+
+impl Colormap {
+    pub fn new(stops: Vec<Color>) -> Self {
+        assert!(!stops.is_empty(), "A colormap needs at least one stop");
+        Self { stops }
+    }
+
+    pub fn sample(
+        &self,
+        t: f32,
+    ) -> Color {
+        let position = t.clamp(0.0, 1.0) * (self.stops.len() - 1) as f32;
+        let index = (position as usize).min(self.stops.len() - 1);
+        let (from, to) = (self.stops[index], self.stops[(index + 1).min(self.stops.len() - 1)]);
+
+        let f = (position - index as f32) as f64;
+        Color {
+            r: from.r + (to.r - from.r) * f,
+            g: from.g + (to.g - from.g) * f,
+            b: from.b + (to.b - from.b) * f,
+            a: from.a + (to.a - from.a) * f,
+        }
+    }
+}
+
+impl Default for Colormap {
+    // blue for low values, red for high values
+    fn default() -> Self {
+        Self::new(vec![
+            Color {
+                r: 0.1,
+                g: 0.2,
+                b: 1.0,
+                a: 1.0,
+            },
+            Color {
+                r: 0.9,
+                g: 0.9,
+                b: 0.9,
+                a: 1.0,
+            },
+            Color {
+                r: 1.0,
+                g: 0.15,
+                b: 0.1,
+                a: 1.0,
+            },
+        ])
+    }
+}
+
+// number of neighbors of every body within the density radius
+pub(super) type NeighborCounts = SecondaryMap<BodyKey, u32>;
+
+// counts with the quadtree of the last step. this queries the tree once per body, so it is only done once per
+// step when capturing a frame
+pub(super) fn count_neighbors(simulation: &Simulation) -> NeighborCounts {
+    let quadtree = simulation.quadtree();
+    let mut counts = NeighborCounts::default();
+    for (body_key, body) in simulation.body_items() {
+        // the body itself is within the radius too
+        let count = quadtree.count_within(body.position, DENSITY_RADIUS).saturating_sub(1);
+        counts.insert(body_key, u32::try_from(count).unwrap_or(u32::MAX));
+    }
+
+    counts
+}
+
+// colors bodies for one frame. the range of the mapped property is taken from the current bodies so the
+// whole colormap is used
+pub(super) struct BodyColoring<'a> {
+    mode: ColorMode,
+    colormap: Colormap,
+    // only set when coloring by density
+    neighbor_counts: Option<&'a NeighborCounts>,
+    min: f32,
+    max: f32,
+}
+
+impl<'a> BodyColoring<'a> {
+    pub(super) fn new<'b>(
+        mode: ColorMode,
+        colormap: &Colormap,
+        bodies: impl Iterator<Item = (BodyKey, &'b Body)>,
+        neighbor_counts: Option<&'a NeighborCounts>,
+    ) -> Self {
+        let mut coloring = Self {
+            mode,
+            colormap: colormap.clone(),
+            neighbor_counts,
+            min: 0.0,
+            max: 0.0,
+        };
+
+        if mode != ColorMode::Fixed {
+            (coloring.min, coloring.max) = bodies
+                .map(|(body_key, body)| coloring.property(body_key, body))
+                .fold((f32::MAX, f32::MIN), |(min, max), value| (min.min(value), max.max(value)));
+        }
+
+        coloring
+    }
+
+    pub(super) fn color(
+        &self,
+        body_key: BodyKey,
+        body: &Body,
+    ) -> Color {
+        if self.mode == ColorMode::Fixed {
+            return body.color();
+        }
+
+        let range = self.max - self.min;
+        let t = if range > 0.0 { (self.property(body_key, body) - self.min) / range } else { 0.0 };
+        self.colormap.sample(t)
+    }
+
+    fn property(
+        &self,
+        body_key: BodyKey,
+        body: &Body,
+    ) -> f32 {
+        match self.mode {
+            ColorMode::Fixed => 0.0,
+            ColorMode::Speed => body.velocity.magnitude(),
+            ColorMode::Mass => body.mass,
+            ColorMode::Density => {
+                let count = self.neighbor_counts.map_or(0, |neighbor_counts| neighbor_counts[body_key]);
+                (count as f32).ln_1p()
+            }
+        }
+    }
+}
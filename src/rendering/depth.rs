@@ -0,0 +1,53 @@
+use wgpu::{
+    Extent3d,
+    Texture,
+    TextureDescriptor,
+    TextureDimension,
+    TextureUsages,
+    TextureView,
+    TextureViewDescriptor,
+};
+
+use crate::pipeline::Pipeline;
+
+use super::multisample::matches_surface;
+
+// depth attachment shared by all render passes, with the same size and sample count as their color targets
+pub(super) struct DepthTarget {
+    texture: Texture,
+    view: TextureView,
+}
+
+impl DepthTarget {
+    // returns None if the pipeline was created without a depth buffer
+    pub(super) fn new(pipeline: &Pipeline) -> Option<Self> {
+        let texture = pipeline.device.create_texture(&TextureDescriptor {
+            label: Some("Depth Texture"),
+            size: Extent3d {
+                width: pipeline.config.width,
+                height: pipeline.config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: pipeline.sample_count,
+            dimension: TextureDimension::D2,
+            format: pipeline.depth_format?,
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+
+        Some(Self { texture, view })
+    }
+
+    pub(super) fn matches(
+        &self,
+        pipeline: &Pipeline,
+    ) -> bool {
+        matches_surface(&self.texture, pipeline)
+    }
+
+    pub(super) fn view(&self) -> &TextureView {
+        &self.view
+    }
+}
@@ -3,16 +3,8 @@ use cgmath::{
     Point2,
     Vector2,
 };
-use wgpu::util::{
-    BufferInitDescriptor,
-    DeviceExt,
-};
-use wgpu::wgc::pipeline::{
-    self,
-    VertexStep,
-};
 use wgpu::{
-    BufferAddress, BufferUsages, Color, IndexFormat, RenderPass, SurfaceError, VertexAttribute, VertexBufferLayout, VertexFormat, VertexStepMode
+    Buffer, BufferAddress, BufferDescriptor, BufferUsages, Color, Device, IndexFormat, RenderPass, SurfaceError, VertexAttribute, VertexBufferLayout, VertexFormat, VertexStepMode
 };
 
 use crate::pipeline::Pipeline;
@@ -74,9 +66,119 @@ impl Mesh {
     ) -> Self {
         Self { vertices, indices }
     }
+
+    /// Removes all vertices and indices, keeping the allocated capacity so refilling the mesh doesn't allocate.
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.indices.clear();
+    }
+}
+
+/// Corners of polylines are only mitered if the tip is at most this many line widths away from the corner.
+pub const MITER_LIMIT: f32 = 4.0;
+
+// corners of the discs capping the ends of open polylines
+const CAP_SEGMENTS: u32 = 8;
+
+// initial number of vertices and indices the generic buffers can hold
+const INITIAL_GENERIC_CAPACITY: usize = 1024;
+
+// persistent buffers shared by all generic meshes drawn in a frame. every mesh is written behind the
+// previous one, since buffer writes only take effect on submission
+pub(super) struct GenericBuffers {
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    vertex_capacity: usize,
+    index_capacity: usize,
+    vertex_offset: usize,
+    index_offset: usize,
+}
+
+impl GenericBuffers {
+    pub(super) fn new(device: &Device) -> Self {
+        Self {
+            vertex_buffer: create_vertex_buffer(device, INITIAL_GENERIC_CAPACITY),
+            index_buffer: create_index_buffer(device, INITIAL_GENERIC_CAPACITY),
+            vertex_capacity: INITIAL_GENERIC_CAPACITY,
+            index_capacity: INITIAL_GENERIC_CAPACITY,
+            vertex_offset: 0,
+            index_offset: 0,
+        }
+    }
+
+    // must be called before the first mesh of a frame is written
+    pub(super) fn start_frame(&mut self) {
+        self.vertex_offset = 0;
+        self.index_offset = 0;
+    }
+
+    // makes sure the given number of vertices and indices fit behind the current offsets. reallocating
+    // starts at the beginning of the new buffers, earlier draws keep using the old ones
+    fn reserve(
+        &mut self,
+        device: &Device,
+        num_vertices: usize,
+        num_indices: usize,
+    ) {
+        if self.vertex_offset + num_vertices > self.vertex_capacity {
+            self.vertex_capacity = (2 * self.vertex_capacity).max(num_vertices).next_power_of_two();
+            self.vertex_buffer = create_vertex_buffer(device, self.vertex_capacity);
+            self.vertex_offset = 0;
+        }
+
+        if self.index_offset + num_indices > self.index_capacity {
+            self.index_capacity = (2 * self.index_capacity).max(num_indices).next_power_of_two();
+            self.index_buffer = create_index_buffer(device, self.index_capacity);
+            self.index_offset = 0;
+        }
+    }
+}
+
+fn create_vertex_buffer(
+    device: &Device,
+    capacity: usize,
+) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some("Generic Vertex Buffer"),
+        size: BufferAddress::try_from(capacity * std::mem::size_of::<GenericVertex>()).unwrap(),
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_index_buffer(
+    device: &Device,
+    capacity: usize,
+) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some("Generic Index Buffer"),
+        size: BufferAddress::try_from(capacity * std::mem::size_of::<u32>()).unwrap(),
+        usage: BufferUsages::INDEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
 }
 
 impl RenderState {
+    /// Draws the mesh built by `fill` like [RenderState::render_generic]. It is built in a mesh kept between
+    /// calls, which is cleared beforehand.
+    pub(super) fn render_mesh<F>(
+        &mut self,
+        pipeline: &mut Pipeline,
+        render_pass: &mut RenderPass,
+        fill: F,
+    ) -> Result<(), SurfaceError>
+    where
+        F: FnOnce(&mut Mesh),
+    {
+        let mut mesh = std::mem::take(&mut self.generic_mesh);
+        mesh.clear();
+        fill(&mut mesh);
+
+        let result = self.render_generic(pipeline, render_pass, &mesh.vertices, &mesh.indices);
+        self.generic_mesh = mesh;
+        result
+    }
+
     pub(super) fn render_generic(
         &mut self,
         pipeline: &mut Pipeline,
@@ -89,25 +191,36 @@ impl RenderState {
             return Ok(());
         }
 
-        render_pass.set_pipeline(&pipeline.generic_pipeline);
+        match &pipeline.generic_wireframe_pipeline {
+            Some(wireframe_pipeline) if self.settings.wireframe => render_pass.set_pipeline(wireframe_pipeline),
+            _ => render_pass.set_pipeline(&pipeline.generic_pipeline),
+        }
+
+        let bufs = &mut self.generic_buffers;
+        bufs.reserve(&pipeline.device, vertices.len(), indices.len());
+
+        pipeline.queue.write_buffer(
+            &bufs.vertex_buffer,
+            BufferAddress::try_from(bufs.vertex_offset * std::mem::size_of::<GenericVertex>()).unwrap(),
+            bytemuck::cast_slice(vertices),
+        );
 
-        // this is very slow. too bad!
-        let vertex_buffer = pipeline.device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Generic Vertex Buffer"),
-            contents: bytemuck::cast_slice(vertices),
-            usage: BufferUsages::VERTEX,
-        });
+        pipeline.queue.write_buffer(
+            &bufs.index_buffer,
+            BufferAddress::try_from(bufs.index_offset * std::mem::size_of::<u32>()).unwrap(),
+            bytemuck::cast_slice(indices),
+        );
 
-        let index_buffer = pipeline.device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Generic Index Buffer"),
-            contents: bytemuck::cast_slice(indices),
-            usage: BufferUsages::INDEX,
-        });
+        render_pass.set_vertex_buffer(0, bufs.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(bufs.index_buffer.slice(..), IndexFormat::Uint32);
 
-        render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
-        render_pass.set_index_buffer(index_buffer.slice(..), IndexFormat::Uint32);
+        let first_index = u32::try_from(bufs.index_offset).unwrap();
+        let last_index = u32::try_from(bufs.index_offset + indices.len()).unwrap();
+        let base_vertex = i32::try_from(bufs.vertex_offset).unwrap();
+        render_pass.draw_indexed(first_index..last_index, base_vertex, 0..1);
 
-        render_pass.draw_indexed(0..u32::try_from(indices.len()).unwrap(), 0, 0..1);
+        bufs.vertex_offset += vertices.len();
+        bufs.index_offset += indices.len();
 
         Ok(())
     }
@@ -119,6 +232,115 @@ pub fn push_line(
     to: Point2<f32>,
     width: f32,
     color: Color,
+) {
+    push_gradient_line(mesh, from, to, width, color, color);
+}
+
+/// Pushes lines connecting the points one after another. Corners are mitered, or beveled if they are so sharp
+/// that the miter would reach further than [MITER_LIMIT] times the width. A polyline ending where it started
+/// is closed, other ones get round caps at both ends. Repeated points are skipped, since a segment of length
+/// 0 has no direction.
+pub fn push_polyline(
+    mesh: &mut Mesh,
+    points: &[Point2<f32>],
+    width: f32,
+    color: Color,
+) {
+    let mut corners = Vec::with_capacity(points.len());
+    for &point in points {
+        if corners.last() != Some(&point) {
+            corners.push(point);
+        }
+    }
+
+    if corners.len() < 2 {
+        return;
+    }
+
+    for segment in corners.windows(2) {
+        push_line(mesh, segment[0], segment[1], width, color);
+    }
+
+    for joint in corners.windows(3) {
+        push_join(mesh, joint[0], joint[1], joint[2], width, color);
+    }
+
+    let (first, last) = (corners[0], corners[corners.len() - 1]);
+    if corners.len() > 2 && first == last {
+        push_join(mesh, corners[corners.len() - 2], first, corners[1], width, color);
+    } else {
+        push_circle(mesh, first, 0.5 * width, CAP_SEGMENTS, color);
+        push_circle(mesh, last, 0.5 * width, CAP_SEGMENTS, color);
+    }
+}
+
+// fills the wedge between the lines from `from` to `joint` and from `joint` to `to` on the outside of the
+// corner. the inner sides of the lines overlap anyway
+fn push_join(
+    mesh: &mut Mesh,
+    from: Point2<f32>,
+    joint: Point2<f32>,
+    to: Point2<f32>,
+    width: f32,
+    color: Color,
+) {
+    let (incoming, outgoing) = ((joint - from).normalize(), (to - joint).normalize());
+    let turn = incoming.perp_dot(outgoing);
+    // straight lines have no gap, and reversing lines have no outside
+    if turn == 0.0 {
+        return;
+    }
+
+    // the normals point to the left of the lines, the outside of a left turn is on their right
+    let side = -turn.signum() * 0.5 * width;
+    let incoming_corner = joint + Vector2::new(-incoming.y, incoming.x) * side;
+    let outgoing_corner = joint + Vector2::new(-outgoing.y, outgoing.x) * side;
+    push_triangle(mesh, [joint, incoming_corner, outgoing_corner], color);
+
+    // the miter tip lies where the outer edges of both lines meet
+    let bisector = (incoming_corner - joint) + (outgoing_corner - joint);
+    let cos_half_angle = bisector.magnitude() / width;
+    if cos_half_angle > 0.5 / MITER_LIMIT {
+        let tip = joint + bisector.normalize() * (0.5 * width / cos_half_angle);
+        push_triangle(mesh, [incoming_corner, tip, outgoing_corner], color);
+    }
+}
+
+// the corners are reordered to be counterclockwise, so the triangle isn't culled
+fn push_triangle(
+    mesh: &mut Mesh,
+    [a, mut b, mut c]: [Point2<f32>; 3],
+    color: Color,
+) {
+    if (b - a).perp_dot(c - a) < 0.0 {
+        std::mem::swap(&mut b, &mut c);
+    }
+
+    let index_offset = u32::try_from(mesh.vertices.len()).unwrap();
+    mesh.vertices.extend([a, b, c].map(|corner| GenericVertex::from_point_and_color(corner, color)));
+    mesh.indices.extend([0, 1, 2].map(|index| index + index_offset));
+}
+
+/// Pushes the outline of the axis-aligned rectangle with the corners `min` and `max`.
+pub fn push_rect(
+    mesh: &mut Mesh,
+    min: Point2<f32>,
+    max: Point2<f32>,
+    width: f32,
+    color: Color,
+) {
+    let corners = [min, Point2::new(max.x, min.y), max, Point2::new(min.x, max.y), min];
+    push_polyline(mesh, &corners, width, color);
+}
+
+/// Pushes a line whose color blends from `from_color` at `from` to `to_color` at `to`.
+pub fn push_gradient_line(
+    mesh: &mut Mesh,
+    from: Point2<f32>,
+    to: Point2<f32>,
+    width: f32,
+    from_color: Color,
+    to_color: Color,
 ) {
     let direction = (to - from).normalize();
     let direction_perp = Vector2::new(-direction.y, direction.x) * 0.5 * width;
@@ -129,10 +351,10 @@ pub fn push_line(
     let p3 = to + direction_perp;
 
     let vertices = vec![
-        GenericVertex::from_point_and_color(p0, color),
-        GenericVertex::from_point_and_color(p1, color),
-        GenericVertex::from_point_and_color(p2, color),
-        GenericVertex::from_point_and_color(p3, color),
+        GenericVertex::from_point_and_color(p0, from_color),
+        GenericVertex::from_point_and_color(p1, from_color),
+        GenericVertex::from_point_and_color(p2, to_color),
+        GenericVertex::from_point_and_color(p3, to_color),
     ];
 
     let indices = vec![
@@ -142,6 +364,30 @@ pub fn push_line(
 
     let index_offset = u32::try_from(mesh.vertices.len()).unwrap();
 
-    mesh.vertices.extend(vertices.into_iter());
+    mesh.vertices.extend(vertices);
     mesh.indices.extend(indices.into_iter().map(|index| index + index_offset));
 }
+
+/// Pushes a filled disc approximated by a regular polygon with `segments` corners, which are clamped to at
+/// least 3.
+pub fn push_circle(
+    mesh: &mut Mesh,
+    center: Point2<f32>,
+    radius: f32,
+    segments: u32,
+    color: Color,
+) {
+    let segments = segments.max(3);
+    let index_offset = u32::try_from(mesh.vertices.len()).unwrap();
+
+    // a fan around the center, counterclockwise so it isn't culled
+    mesh.vertices.push(GenericVertex::from_point_and_color(center, color));
+    mesh.vertices.extend((0..segments).map(|segment| {
+        let angle = segment as f32 / segments as f32 * std::f32::consts::TAU;
+        GenericVertex::from_point_and_color(center + Vector2::new(angle.cos(), angle.sin()) * radius, color)
+    }));
+
+    mesh.indices.extend((0..segments).flat_map(|segment| {
+        [0, 1 + segment, 1 + (segment + 1) % segments].map(|index| index + index_offset)
+    }));
+}
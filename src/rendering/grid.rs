@@ -0,0 +1,66 @@
+use cgmath::{
+    Point2,
+    Vector2,
+};
+use wgpu::Color;
+use winit::dpi::PhysicalSize;
+
+use super::camera::Camera;
+use super::generic::{
+    Mesh,
+    push_line,
+};
+
+// every this many minor lines there is a major line, which is also the factor between the spacing at
+// different zoom levels
+const MAJOR_INTERVAL: i64 = 10;
+
+// the minor spacing is the largest power of ten that fits this many times into the view height
+const MIN_MINOR_LINES: f32 = 10.0;
+
+// width of the lines in screen pixels, independent of the zoom
+const LINE_WIDTH: f32 = 1.0;
+
+const MINOR_COLOR: Color = Color {
+    r: 0.04,
+    g: 0.04,
+    b: 0.05,
+    a: 1.0,
+};
+
+const MAJOR_COLOR: Color = Color {
+    r: 0.1,
+    g: 0.1,
+    b: 0.12,
+    a: 1.0,
+};
+
+/// Lines in world space covering the view of the camera, spaced at a power of ten that adapts to the zoom.
+/// Every tenth line is drawn brighter, so the spacing can be read off when zooming from one to the next.
+pub(super) fn generate_grid_mesh(
+    grid_mesh: &mut Mesh,
+    camera: &Camera,
+    size: PhysicalSize<u32>,
+) {
+    let (min, max) = camera.view_bounds(size);
+    let line_width = camera.pixels_to_world(Vector2::new(LINE_WIDTH, 0.0), size).x;
+
+    let spacing = 10.0_f32.powf(((max.y - min.y) / MIN_MINOR_LINES).log10().floor());
+    if !spacing.is_normal() {
+        return;
+    }
+
+    // lines are indexed by their multiple of the spacing, so major lines stay put while panning
+    let lines = |min: f32, max: f32| (min / spacing).ceil() as i64..=(max / spacing).floor() as i64;
+    let color = |line: i64| if line % MAJOR_INTERVAL == 0 { MAJOR_COLOR } else { MINOR_COLOR };
+
+    for line in lines(min.x, max.x) {
+        let x = line as f32 * spacing;
+        push_line(grid_mesh, Point2::new(x, min.y), Point2::new(x, max.y), line_width, color(line));
+    }
+
+    for line in lines(min.y, max.y) {
+        let y = line as f32 * spacing;
+        push_line(grid_mesh, Point2::new(min.x, y), Point2::new(max.x, y), line_width, color(line));
+    }
+}
@@ -0,0 +1,71 @@
+use wgpu::{
+    Extent3d,
+    Texture,
+    TextureDescriptor,
+    TextureDimension,
+    TextureUsages,
+    TextureView,
+    TextureViewDescriptor,
+};
+
+use crate::pipeline::Pipeline;
+
+// creates a texture with the size and format of the surface
+pub(super) fn create_render_texture(
+    pipeline: &Pipeline,
+    label: &str,
+    sample_count: u32,
+    usage: TextureUsages,
+) -> Texture {
+    pipeline.device.create_texture(&TextureDescriptor {
+        label: Some(label),
+        size: Extent3d {
+            width: pipeline.config.width,
+            height: pipeline.config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count,
+        dimension: TextureDimension::D2,
+        format: pipeline.config.format,
+        usage,
+        view_formats: &[],
+    })
+}
+
+// the texture has to be recreated whenever the surface is resized
+pub(super) fn matches_surface(
+    texture: &Texture,
+    pipeline: &Pipeline,
+) -> bool {
+    texture.width() == pipeline.config.width && texture.height() == pipeline.config.height
+}
+
+// multisampled texture that is rendered to instead of a single-sampled one and then resolved into it
+pub(super) struct MultisampleTarget {
+    texture: Texture,
+    view: TextureView,
+}
+
+impl MultisampleTarget {
+    pub(super) fn new(
+        pipeline: &Pipeline,
+        label: &str,
+    ) -> Self {
+        let texture = create_render_texture(pipeline, label, pipeline.sample_count, TextureUsages::RENDER_ATTACHMENT);
+        let view = texture.create_view(&TextureViewDescriptor::default());
+
+        Self { texture, view }
+    }
+
+    pub(super) fn matches(
+        &self,
+        pipeline: &Pipeline,
+    ) -> bool {
+        matches_surface(&self.texture, pipeline)
+    }
+
+    pub(super) fn view(&self) -> &TextureView {
+        &self.view
+    }
+}
@@ -0,0 +1,61 @@
+use cgmath::Vector2;
+use wgpu::Color;
+
+use crate::simulation::quadtree::{
+    ContinueTraverse,
+    Quadtree,
+    QuadtreeChild,
+};
+use crate::simulation::{
+    Pseudobody,
+    QuadtreeBody,
+};
+
+use super::generic::{
+    Mesh,
+    push_line,
+};
+
+// nodes lighter than this fraction of the total mass get no marker. their children are skipped too, since
+// they can't be any heavier
+const MIN_MASS_FRACTION: f32 = 1e-3;
+
+// half the width of the cross marking the root, lighter nodes get smaller crosses
+const MAX_MARKER_SIZE: f32 = 0.05;
+
+const MARKER_COLOR: Color = Color {
+    r: 1.0,
+    g: 0.6,
+    b: 0.0,
+    a: 1.0,
+};
+
+/// Marks the center of mass of every twig with a cross whose area grows with the mass of the twig.
+pub(super) fn generate_pseudobody_mesh(
+    pseudobody_mesh: &mut Mesh,
+    quadtree: &Quadtree<QuadtreeBody, Pseudobody>,
+) {
+    let mut total_mass = None;
+
+    quadtree.traverse(|node, _| {
+        // the root is visited first
+        let total_mass = *total_mass.get_or_insert(node.data.mass());
+        let mass_fraction = node.data.mass() / total_mass;
+        // an empty tree has no mass, so the fraction isn't a number
+        if mass_fraction.is_nan() || mass_fraction < MIN_MASS_FRACTION {
+            return ContinueTraverse::Stop;
+        }
+
+        // leaves are drawn as bodies anyway
+        if let QuadtreeChild::Node(_) = node.child_key {
+            let size = MAX_MARKER_SIZE * mass_fraction.sqrt();
+            let center = node.data.position();
+            let (diagonal, antidiagonal) = (Vector2::new(size, size), Vector2::new(size, -size));
+
+            push_line(pseudobody_mesh, center - diagonal, center + diagonal, 0.1 * size, MARKER_COLOR);
+            push_line(pseudobody_mesh, center - antidiagonal, center + antidiagonal, 0.1 * size, MARKER_COLOR);
+        }
+
+        ContinueTraverse::Continue
+    });
+}
@@ -1,40 +1,35 @@
 use std::fmt::Debug;
 
-use cgmath::{
-    Point2,
-    Vector2,
-};
 use wgpu::Color;
 
-use crate::simulation::{quadtree::Positioned, Body};
-use crate::simulation::quadtree::{ContinueTraverse, Quadtree};
+use crate::simulation::quadtree::ContinueTraverse;
+use crate::simulation::quadtree::Positioned;
+use crate::simulation::quadtree::Quadtree;
 
 use super::generic::{
-    GenericVertex,
     Mesh,
-    push_line,
+    push_rect,
 };
 
-pub(super) fn generate_quadtree_mesh<T, U>(quadtree: &Quadtree<T, U>) -> Mesh
+// colors of the nodes by depth, starting again at the first one after the last
+const DEPTH_PALETTE: [Color; 6] = [
+    Color { r: 0.0, g: 1.0, b: 0.0, a: 1.0 },
+    Color { r: 0.0, g: 0.8, b: 1.0, a: 1.0 },
+    Color { r: 0.3, g: 0.3, b: 1.0, a: 1.0 },
+    Color { r: 0.8, g: 0.0, b: 1.0, a: 1.0 },
+    Color { r: 1.0, g: 0.2, b: 0.2, a: 1.0 },
+    Color { r: 1.0, g: 0.8, b: 0.0, a: 1.0 },
+];
+
+pub(super) fn generate_quadtree_mesh<T, U>(quadtree_mesh: &mut Mesh, quadtree: &Quadtree<T, U>)
 where T: Positioned + Debug,
       U: Default + Debug + Copy + Clone
 {
-    let mut quadtree_mesh = Mesh::default();
-
-    for node in quadtree.nodes().values() {
-        if let Some(node) = node {
-            let extent = node.extent;
-            let p0 = node.position + Vector2::new(-extent, extent);
-            let p1 = node.position + Vector2::new(-extent, -extent);
-            let p2 = node.position + Vector2::new(extent, extent);
-            let p3 = node.position + Vector2::new(extent, -extent);
+    quadtree.traverse(|node, depth| {
+        let color = DEPTH_PALETTE[depth as usize % DEPTH_PALETTE.len()];
 
-            push_line(&mut quadtree_mesh, p0, p1, 0.003, Color::GREEN);
-            push_line(&mut quadtree_mesh, p1, p3, 0.003, Color::GREEN);
-            push_line(&mut quadtree_mesh, p3, p2, 0.003, Color::GREEN);
-            push_line(&mut quadtree_mesh, p2, p0, 0.003, Color::GREEN);
-        }
-    }
+        push_rect(quadtree_mesh, node.position - node.extent, node.position + node.extent, 0.003, color);
 
-    quadtree_mesh
+        ContinueTraverse::Continue
+    });
 }
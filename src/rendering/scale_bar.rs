@@ -0,0 +1,73 @@
+use cgmath::{
+    Point2,
+    Vector2,
+};
+use wgpu::Color;
+use winit::dpi::PhysicalSize;
+
+use super::camera::Camera;
+use super::generic::Mesh;
+use super::text::{
+    GLYPH_HEIGHT,
+    push_screen_rect,
+    push_text,
+};
+
+// the bar is the longest round length that is at most this many pixels long
+const MAX_BAR_PIXELS: f32 = 200.0;
+
+// distance from the bottom left corner of the window, thickness of the bar and height of the ticks at its
+// ends, all in screen pixels
+const MARGIN: f32 = 8.0;
+const BAR_THICKNESS: f32 = 2.0;
+const TICK_HEIGHT: f32 = 8.0;
+
+// size of a font pixel of the label and its distance from the bar
+const LABEL_SCALE: f32 = 2.0;
+const LABEL_SPACING: f32 = 4.0;
+
+/// A bar in screen space in the bottom left corner, labeled with the world length it covers at the current
+/// zoom. The length is one, two or five times a power of ten, so the label stays easy to read.
+pub(super) fn generate_scale_bar_mesh(
+    scale_bar_mesh: &mut Mesh,
+    camera: &Camera,
+    size: PhysicalSize<u32>,
+) {
+    let world_per_pixel = camera.pixels_to_world(Vector2::new(1.0, 0.0), size).x;
+    let max_length = MAX_BAR_PIXELS * world_per_pixel;
+    if !max_length.is_normal() {
+        return;
+    }
+
+    let exponent = max_length.log10().floor() as i32;
+    let factor = [5, 2, 1]
+        .into_iter()
+        .find(|&factor| factor as f32 * 10.0_f32.powi(exponent) <= max_length)
+        .unwrap_or(1);
+    let length = factor as f32 * 10.0_f32.powi(exponent);
+
+    let pixels = length / world_per_pixel;
+    let left = MARGIN;
+    let bottom = size.height as f32 - MARGIN;
+
+    push_screen_rect(
+        scale_bar_mesh,
+        Point2::new(left, bottom - 0.5 * (TICK_HEIGHT + BAR_THICKNESS)),
+        pixels,
+        BAR_THICKNESS,
+        Color::WHITE,
+    );
+    for x in [left, left + pixels - BAR_THICKNESS] {
+        let top_left = Point2::new(x, bottom - TICK_HEIGHT);
+        push_screen_rect(scale_bar_mesh, top_left, BAR_THICKNESS, TICK_HEIGHT, Color::WHITE);
+    }
+
+    // formatted from the factor and exponent, since the float itself isn't exactly round. very small or
+    // large lengths would need too many zeros and are written in scientific notation instead
+    let label = match exponent {
+        -3..=3 => format!("{:.*}", (-exponent).max(0) as usize, length),
+        _ => format!("{}E{}", factor, exponent),
+    };
+    let label_top = bottom - TICK_HEIGHT - LABEL_SPACING - GLYPH_HEIGHT as f32 * LABEL_SCALE;
+    push_text(scale_bar_mesh, &label, Point2::new(left, label_top), LABEL_SCALE, Color::WHITE);
+}
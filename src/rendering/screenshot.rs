@@ -0,0 +1,195 @@
+use std::path::Path;
+use std::sync::mpsc;
+
+use thiserror::Error;
+use wgpu::{
+    BindGroup,
+    BindGroupDescriptor,
+    BindGroupEntry,
+    BindingResource,
+    Buffer,
+    BufferAddress,
+    BufferAsyncError,
+    BufferDescriptor,
+    BufferUsages,
+    CommandEncoder,
+    Extent3d,
+    MapMode,
+    Origin3d,
+    PollType,
+    RenderPass,
+    TexelCopyBufferInfo,
+    TexelCopyBufferLayout,
+    TexelCopyTextureInfo,
+    Texture,
+    TextureAspect,
+    TextureFormat,
+    TextureUsages,
+    TextureView,
+    TextureViewDescriptor,
+};
+
+use crate::pipeline::Pipeline;
+
+use super::multisample::create_render_texture;
+
+#[derive(Debug, Error)]
+pub enum ScreenshotError {
+    #[error("Can't capture surface with format {0:?}.")]
+    UnsupportedFormat(TextureFormat),
+    #[error("Couldn't draw frame: {0}")]
+    Draw(#[from] wgpu::SurfaceError),
+    #[error("Couldn't read back frame: {0}")]
+    Readback(#[from] BufferAsyncError),
+    #[error("Couldn't wait for frame: {0}")]
+    Poll(#[from] wgpu::PollError),
+    #[error("Couldn't write image: {0}")]
+    Image(#[from] image::ImageError),
+}
+
+// the last pass of a captured frame draws into this texture instead of the surface. it is then copied to the
+// surface for presenting and into a buffer for reading it back
+pub(super) struct Capture {
+    texture: Texture,
+    view: TextureView,
+    bind_group: BindGroup,
+    buffer: Buffer,
+    padded_bytes_per_row: u32,
+    // surfaces are commonly bgra, while images are written as rgba
+    swap_red_blue: bool,
+}
+
+impl Capture {
+    pub(super) fn new(pipeline: &Pipeline) -> Result<Self, ScreenshotError> {
+        let swap_red_blue = match pipeline.config.format {
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => false,
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => true,
+            format => return Err(ScreenshotError::UnsupportedFormat(format)),
+        };
+
+        let texture = create_render_texture(
+            pipeline,
+            "Capture Texture",
+            1,
+            TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC,
+        );
+
+        let view = texture.create_view(&TextureViewDescriptor::default());
+
+        let bind_group = pipeline.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Capture Bind Group"),
+            layout: &pipeline.blit_bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&view),
+            }],
+        });
+
+        // rows copied into buffers must be aligned
+        let padded_bytes_per_row = (4 * pipeline.config.width).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = pipeline.device.create_buffer(&BufferDescriptor {
+            label: Some("Capture Buffer"),
+            size: BufferAddress::from(padded_bytes_per_row * pipeline.config.height),
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            bind_group,
+            buffer,
+            padded_bytes_per_row,
+            swap_red_blue,
+        })
+    }
+
+    pub(super) fn view(&self) -> &TextureView {
+        &self.view
+    }
+
+    // copies the captured frame onto the render target of the pass
+    pub(super) fn blit(
+        &self,
+        pipeline: &Pipeline,
+        render_pass: &mut RenderPass,
+    ) {
+        render_pass.set_pipeline(&pipeline.blit_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    pub(super) fn copy_to_buffer(
+        &self,
+        encoder: &mut CommandEncoder,
+    ) {
+        encoder.copy_texture_to_buffer(
+            TexelCopyTextureInfo {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            TexelCopyBufferInfo {
+                buffer: &self.buffer,
+                layout: TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_bytes_per_row),
+                    rows_per_image: Some(self.texture.height()),
+                },
+            },
+            Extent3d {
+                width: self.texture.width(),
+                height: self.texture.height(),
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    // blocks until the frame has been rendered. must be called after the copy has been submitted
+    pub(super) fn save(
+        self,
+        pipeline: &Pipeline,
+        path: &Path,
+    ) -> Result<(), ScreenshotError> {
+        let (width, height) = (self.texture.width(), self.texture.height());
+        let pixels = self.read_pixels(pipeline)?;
+        image::save_buffer(path, &pixels, width, height, image::ColorType::Rgba8)?;
+
+        Ok(())
+    }
+
+    // the frame as tightly packed, opaque rgba rows from top to bottom. blocks like [Capture::save]
+    pub(super) fn read_pixels(
+        self,
+        pipeline: &Pipeline,
+    ) -> Result<Vec<u8>, ScreenshotError> {
+        let (sender, receiver) = mpsc::channel();
+        let slice = self.buffer.slice(..);
+        slice.map_async(MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+
+        pipeline.device.poll(PollType::Wait)?;
+        // the callback has run once polling returns
+        receiver.recv().unwrap_or(Err(BufferAsyncError))?;
+
+        let (width, height) = (self.texture.width(), self.texture.height());
+        let mut pixels = Vec::with_capacity((4 * width * height) as usize);
+        for row in slice.get_mapped_range().chunks_exact(self.padded_bytes_per_row as usize) {
+            pixels.extend_from_slice(&row[..(4 * width) as usize]);
+        }
+        self.buffer.unmap();
+
+        for pixel in pixels.chunks_exact_mut(4) {
+            if self.swap_red_blue {
+                pixel.swap(0, 2);
+            }
+
+            // the surface is presented opaque, but fading trails also lowers the alpha channel
+            pixel[3] = u8::MAX;
+        }
+
+        Ok(pixels)
+    }
+}
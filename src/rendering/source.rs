@@ -0,0 +1,225 @@
+use std::time::Duration;
+
+use cgmath::Point2;
+use wgpu::Color;
+
+use crate::simulation::quadtree::{
+    Quadtree,
+    QuadtreeStats,
+};
+use crate::simulation::{
+    Body,
+    BodyKey,
+    Pseudobody,
+    QuadtreeBody,
+    SimFloat,
+    Simulation,
+    StepStats,
+};
+
+use super::coloring::{
+    NeighborCounts,
+    count_neighbors,
+};
+
+/// The part of a body needed to draw it in its own color.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BodyCircle {
+    pub position: Point2<SimFloat>,
+    pub color: Color,
+    pub radius: SimFloat,
+}
+
+impl From<&Body> for BodyCircle {
+    fn from(body: &Body) -> Self {
+        Self {
+            position: body.position,
+            color: body.color(),
+            radius: body.radius(),
+        }
+    }
+}
+
+/// What a [BodyFrame] holds besides the circles of the bodies. The render thread asks for what its enabled
+/// views need, see [RenderState::frame_request](super::RenderState::frame_request).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameRequest {
+    /// Copies of the whole bodies, for coloring them by their properties, drawing their velocities and level
+    /// of detail.
+    pub bodies: bool,
+    /// How many neighbors every body has, for coloring by density.
+    pub neighbor_counts: bool,
+    /// A copy of the quadtree, for drawing it, its pseudobodies and level of detail.
+    pub quadtree: bool,
+    /// Statistics of the last step, for the overlay and following the center of mass.
+    pub stats: bool,
+    /// Where the cursor points at in the world. The body nearest to it is captured for the overlay.
+    pub pick_point: Option<Point2<SimFloat>>,
+}
+
+/// The state of a simulation after a step, see [FrameRequest::stats].
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStats {
+    pub center_of_mass: Point2<SimFloat>,
+    pub body_count: usize,
+    pub step_count: u64,
+    pub sim_time: SimFloat,
+    pub step_duration: Duration,
+    pub step_stats: StepStats,
+    pub threshold: SimFloat,
+    pub quadtree_stats: QuadtreeStats,
+}
+
+/// Everything [RenderState](super::RenderState) draws a frame from, captured from a simulation after a step.
+/// The simulation thread hands one over after every step, so rendering never has to wait for the simulation,
+/// see [triple_buffer](crate::utility::triple_buffer::triple_buffer). Only the circles are always captured,
+/// the rest only if it was requested, so views that need nothing else don't pay for copying it.
+#[derive(Debug, Default)]
+pub struct BodyFrame {
+    // what was captured besides the circles
+    request: FrameRequest,
+    circles: Vec<BodyCircle>,
+    // in key order, like the simulation iterates over them
+    bodies: Vec<(BodyKey, Body)>,
+    neighbor_counts: NeighborCounts,
+    quadtree: Option<Quadtree<QuadtreeBody, Pseudobody>>,
+    stats: Option<FrameStats>,
+    nearest_body: Option<(BodyKey, Body)>,
+}
+
+impl BodyFrame {
+    /// Replaces the frame with the current state of the simulation, reusing the allocations for the bodies.
+    pub fn capture(
+        &mut self,
+        simulation: &Simulation,
+        request: FrameRequest,
+    ) {
+        self.request = request;
+
+        self.circles.clear();
+        self.circles.extend(simulation.bodies().map(BodyCircle::from));
+
+        self.bodies.clear();
+        if request.bodies {
+            self.bodies.extend(simulation.body_items().map(|(body_key, body)| (body_key, body.clone())));
+        }
+
+        self.neighbor_counts =
+            if request.neighbor_counts { count_neighbors(simulation) } else { NeighborCounts::default() };
+        self.quadtree = request.quadtree.then(|| simulation.quadtree().clone());
+        self.stats = request.stats.then(|| FrameStats {
+            center_of_mass: simulation.center_of_mass(),
+            body_count: simulation.bodies().len(),
+            step_count: simulation.step_count(),
+            sim_time: simulation.sim_time(),
+            step_duration: simulation.step_duration(),
+            step_stats: simulation.step_stats(),
+            threshold: simulation.threshold(),
+            quadtree_stats: simulation.quadtree().stats(),
+        });
+
+        let nearest_body = request.pick_point.and_then(|point| simulation.nearest_body(point));
+        self.nearest_body = nearest_body.map(|(body_key, body)| (body_key, body.clone()));
+    }
+
+    /// What the frame was captured with.
+    pub fn request(&self) -> FrameRequest {
+        self.request
+    }
+
+    /// The circles of all bodies in key order.
+    pub fn circles(&self) -> &[BodyCircle] {
+        &self.circles
+    }
+
+    /// All bodies in key order, see [FrameRequest::bodies].
+    pub fn body_items(&self) -> Option<impl ExactSizeIterator<Item = (BodyKey, &Body)>> {
+        self.request.bodies.then(|| self.bodies.iter().map(|(body_key, body)| (*body_key, body)))
+    }
+
+    /// The body of the key, if bodies were captured and it existed at the time.
+    pub fn body(
+        &self,
+        body_key: BodyKey,
+    ) -> Option<&Body> {
+        let index = self.bodies.binary_search_by_key(&body_key, |(key, _)| *key).ok()?;
+        Some(&self.bodies[index].1)
+    }
+
+    pub(super) fn neighbor_counts(&self) -> Option<&NeighborCounts> {
+        self.request.neighbor_counts.then_some(&self.neighbor_counts)
+    }
+
+    pub fn quadtree(&self) -> Option<&Quadtree<QuadtreeBody, Pseudobody>> {
+        self.quadtree.as_ref()
+    }
+
+    pub fn stats(&self) -> Option<&FrameStats> {
+        self.stats.as_ref()
+    }
+
+    /// The body nearest to [FrameRequest::pick_point].
+    pub fn nearest_body(&self) -> Option<(BodyKey, &Body)> {
+        self.nearest_body.as_ref().map(|(body_key, body)| (*body_key, body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Vector2;
+
+    fn simulation() -> Simulation {
+        let bodies = (0..5).map(|i| {
+            let color = Color { r: f64::from(i) / 4.0, g: 0.0, b: 0.0, a: 1.0 };
+            Body::new(Point2::new(i as SimFloat, 0.0), Vector2::new(0.0, 1.0), 1.0, 0.1 * (i + 1) as SimFloat, color)
+        });
+        let mut simulation = Simulation::new(bodies, 0.5);
+        simulation.advance(0.01);
+        simulation
+    }
+
+    #[test]
+    fn capture_copies_the_circles_in_key_order() {
+        let mut simulation = simulation();
+        let mut frame = BodyFrame::default();
+        frame.capture(&simulation, FrameRequest::default());
+        assert!(frame.circles().iter().copied().eq(simulation.bodies().map(BodyCircle::from)));
+
+        // captures reuse the frame instead of appending to it
+        simulation.advance(0.01);
+        frame.capture(&simulation, FrameRequest::default());
+        assert!(frame.circles().iter().copied().eq(simulation.bodies().map(BodyCircle::from)));
+    }
+
+    #[test]
+    fn capture_only_copies_what_was_requested() {
+        let simulation = simulation();
+        let mut frame = BodyFrame::default();
+        frame.capture(&simulation, FrameRequest::default());
+        assert!(frame.body_items().is_none());
+        assert!(frame.neighbor_counts().is_none());
+        assert!(frame.quadtree().is_none());
+        assert!(frame.stats().is_none());
+        assert!(frame.nearest_body().is_none());
+
+        let request = FrameRequest {
+            bodies: true,
+            neighbor_counts: true,
+            quadtree: true,
+            stats: true,
+            pick_point: Some(Point2::new(3.9, 0.0)),
+        };
+        frame.capture(&simulation, request);
+        assert_eq!(frame.body_items().unwrap().len(), 5);
+        for (body_key, body) in simulation.body_items() {
+            assert_eq!(frame.body(body_key).unwrap().position, body.position);
+        }
+        assert!(frame.neighbor_counts().is_some());
+        assert_eq!(frame.quadtree().unwrap().elements().len(), 5);
+        assert_eq!(frame.stats().unwrap().step_count, 1);
+
+        let (body_key, _) = frame.nearest_body().unwrap();
+        assert_eq!(simulation.body(body_key).unwrap().position, simulation.bodies().last().unwrap().position);
+    }
+}
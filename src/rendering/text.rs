@@ -0,0 +1,137 @@
+use cgmath::Point2;
+use wgpu::Color;
+
+use super::generic::{
+    GenericVertex,
+    Mesh,
+};
+
+pub const GLYPH_WIDTH: u32 = 5;
+pub const GLYPH_HEIGHT: u32 = 7;
+
+// horizontal and vertical space between glyphs, in font pixels
+const GLYPH_SPACING: u32 = 1;
+const LINE_SPACING: u32 = 3;
+
+// rows of a 5x7 bitmap font, top to bottom. the most significant of the five bits is the leftmost pixel.
+// lowercase letters are drawn as uppercase ones
+fn glyph(c: char) -> Option<[u8; 7]> {
+    let rows = match c.to_ascii_uppercase() {
+        'A' => [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'B' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110],
+        'C' => [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110],
+        'D' => [0b11110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11110],
+        'E' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111],
+        'F' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000],
+        'G' => [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111],
+        'H' => [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'I' => [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        'J' => [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100],
+        'K' => [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001],
+        'L' => [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111],
+        'M' => [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001],
+        'N' => [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001],
+        'O' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'P' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000],
+        'Q' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101],
+        'R' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001],
+        'S' => [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110],
+        'T' => [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100],
+        'U' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'V' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100],
+        'W' => [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010],
+        'X' => [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001],
+        'Y' => [0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100, 0b00100],
+        'Z' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111],
+        '0' => [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110],
+        '1' => [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        '2' => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111],
+        '3' => [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110],
+        '4' => [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010],
+        '5' => [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110],
+        '6' => [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110],
+        '7' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000],
+        '8' => [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110],
+        '9' => [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100],
+        ' ' => [0; 7],
+        '.' => [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100],
+        ',' => [0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b00100, 0b01000],
+        ':' => [0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b01100, 0b00000],
+        '-' => [0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000],
+        '+' => [0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000],
+        '=' => [0b00000, 0b00000, 0b11111, 0b00000, 0b11111, 0b00000, 0b00000],
+        '%' => [0b11000, 0b11001, 0b00010, 0b00100, 0b01000, 0b10011, 0b00011],
+        '/' => [0b00000, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b00000],
+        '(' => [0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010],
+        ')' => [0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000],
+        _ => return None,
+    };
+
+    Some(rows)
+}
+
+/// Pushes text in screen space, i.e. pixels from the top left corner of the window, with `position`
+/// being the top left corner of the first glyph. Every font pixel is `scale` screen pixels wide. Newlines
+/// start a new line, characters without a glyph are skipped.
+pub fn push_text(
+    mesh: &mut Mesh,
+    text: &str,
+    position: Point2<f32>,
+    scale: f32,
+    color: Color,
+) {
+    let mut cursor = position;
+
+    for c in text.chars() {
+        if c == '\n' {
+            cursor.x = position.x;
+            cursor.y += (GLYPH_HEIGHT + LINE_SPACING) as f32 * scale;
+            continue;
+        }
+
+        let Some(rows) = glyph(c) else {
+            continue;
+        };
+
+        for (y, row) in rows.into_iter().enumerate() {
+            // consecutive pixels in a row are merged into one rectangle
+            let mut x = 0;
+            while x < GLYPH_WIDTH {
+                if row & (1 << (GLYPH_WIDTH - 1 - x)) == 0 {
+                    x += 1;
+                    continue;
+                }
+
+                let start = x;
+                while x < GLYPH_WIDTH && row & (1 << (GLYPH_WIDTH - 1 - x)) != 0 {
+                    x += 1;
+                }
+
+                let top_left = Point2::new(cursor.x + start as f32 * scale, cursor.y + y as f32 * scale);
+                push_screen_rect(mesh, top_left, (x - start) as f32 * scale, scale, color);
+            }
+        }
+
+        cursor.x += (GLYPH_WIDTH + GLYPH_SPACING) as f32 * scale;
+    }
+}
+
+/// Pushes a filled rectangle in screen space, with `top_left` in pixels from the top left corner of the window.
+// screen space has y pointing down, so the corners are ordered to stay counterclockwise after flipping
+pub fn push_screen_rect(
+    mesh: &mut Mesh,
+    top_left: Point2<f32>,
+    width: f32,
+    height: f32,
+    color: Color,
+) {
+    let index_offset = u32::try_from(mesh.vertices.len()).unwrap();
+
+    mesh.vertices.extend([
+        GenericVertex::from_point_and_color(top_left, color),
+        GenericVertex::from_point_and_color(Point2::new(top_left.x, top_left.y + height), color),
+        GenericVertex::from_point_and_color(Point2::new(top_left.x + width, top_left.y + height), color),
+        GenericVertex::from_point_and_color(Point2::new(top_left.x + width, top_left.y), color),
+    ]);
+    mesh.indices.extend([0, 1, 2, 0, 2, 3].into_iter().map(|index| index + index_offset));
+}
@@ -0,0 +1,107 @@
+use wgpu::{
+    BindGroup,
+    BindGroupDescriptor,
+    BindGroupEntry,
+    BindingResource,
+    Color,
+    RenderPass,
+    Texture,
+    TextureUsages,
+    TextureView,
+    TextureViewDescriptor,
+};
+
+use crate::pipeline::Pipeline;
+
+use super::multisample::{
+    MultisampleTarget,
+    create_render_texture,
+    matches_surface,
+};
+
+// persistent texture bodies are drawn into when trails are enabled. it is only faded instead of cleared
+// every frame, so previous positions stay visible for a while
+pub(super) struct TrailTarget {
+    texture: Texture,
+    view: TextureView,
+    bind_group: BindGroup,
+    // drawn to instead of the texture when multisampling. it is kept across frames as well, since it is
+    // loaded before drawing
+    multisample_target: Option<MultisampleTarget>,
+}
+
+impl TrailTarget {
+    pub(super) fn new(pipeline: &Pipeline) -> Self {
+        let texture = create_render_texture(
+            pipeline,
+            "Trail Texture",
+            1,
+            TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        );
+
+        let view = texture.create_view(&TextureViewDescriptor::default());
+
+        let bind_group = pipeline.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Trail Bind Group"),
+            layout: &pipeline.blit_bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&view),
+            }],
+        });
+
+        let multisample_target =
+            (pipeline.sample_count > 1).then(|| MultisampleTarget::new(pipeline, "Multisampled Trail Texture"));
+
+        Self {
+            texture,
+            view,
+            bind_group,
+            multisample_target,
+        }
+    }
+
+    pub(super) fn matches(
+        &self,
+        pipeline: &Pipeline,
+    ) -> bool {
+        matches_surface(&self.texture, pipeline)
+    }
+
+    pub(super) fn view(&self) -> &TextureView {
+        &self.view
+    }
+
+    pub(super) fn multisample_target(&self) -> Option<&MultisampleTarget> {
+        self.multisample_target.as_ref()
+    }
+
+    // fades the previous contents towards transparent, keeping `decay` of their brightness and opacity
+    pub(super) fn fade(
+        &self,
+        pipeline: &Pipeline,
+        render_pass: &mut RenderPass,
+        decay: f32,
+    ) {
+        let decay = decay as f64;
+        render_pass.set_pipeline(&pipeline.fade_pipeline);
+        render_pass.set_blend_constant(Color {
+            r: decay,
+            g: decay,
+            b: decay,
+            a: decay,
+        });
+        render_pass.draw(0..3, 0..1);
+    }
+
+    // draws the texture over the render target of the pass, which shows through where the trails have faded
+    pub(super) fn blit(
+        &self,
+        pipeline: &Pipeline,
+        render_pass: &mut RenderPass,
+    ) {
+        render_pass.set_pipeline(&pipeline.blit_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
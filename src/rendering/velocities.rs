@@ -0,0 +1,27 @@
+use wgpu::Color;
+
+use crate::simulation::Body;
+
+use super::generic::{
+    Mesh,
+    push_gradient_line,
+};
+
+// velocity lines show the distance a body travels in this many seconds
+const VELOCITY_LINE_SCALE: f32 = 0.5;
+
+pub(super) fn generate_velocity_mesh<'a>(
+    velocity_mesh: &mut Mesh,
+    bodies: impl Iterator<Item = &'a Body>,
+) {
+    for body in bodies {
+        let tip = body.position + body.velocity * VELOCITY_LINE_SCALE;
+
+        // resting bodies have no direction to draw
+        if tip == body.position {
+            continue;
+        }
+
+        push_gradient_line(velocity_mesh, body.position, tip, 0.002, body.color(), Color::WHITE);
+    }
+}
@@ -1,35 +1,94 @@
-use std::num::NonZeroU8;
+use std::str::FromStr;
 use std::time::{
     Duration,
     Instant,
 };
 
+pub mod export;
+#[cfg(feature = "gpu-forces")]
+pub mod gpu;
+pub mod kernel;
+pub mod octree;
+pub mod presets;
 pub mod quadtree;
+#[cfg(feature = "serde")]
+pub mod snapshot;
 
-type SimFloat = f32;
+pub type SimFloat = f32;
+
+/// Called at the end of every step, see [Simulation::with_step_callback]. It has to be [Sync] since forces
+/// are calculated in parallel with shared access to the simulation it is stored in.
+pub type StepCallback = Box<dyn FnMut(&Simulation) + Send + Sync>;
+
+// relative margin added around the bodies' bounding box when sizing the quadtree
+const QUADTREE_MARGIN: SimFloat = 0.01;
+
+// when the tree is kept between steps, a wider margin lets the bodies spread out for a while before a body
+// leaves the tree and it has to be rebuilt
+const WARM_START_MARGIN: SimFloat = 0.25;
+
+// merging nodes leaves their children's slots unused, the tree is rebuilt once it has grown by this factor
+// since it was last built
+const WARM_START_MAX_GROWTH: usize = 2;
+
+// in simulation units, i.e. distance units, body masses and seconds
+pub const GRAVITATIONAL_CONSTANT: SimFloat = 5e-9;
+
+/// Default softening length, about the radius of a single body. Bodies closer than this overlap anyway, so
+/// resolving their attraction exactly isn't meaningful.
+pub const DEFAULT_SOFTENING: SimFloat = 0.005;
+
+/// Default accuracy parameter of [BlockTimesteps], smaller values pick smaller timesteps.
+pub const DEFAULT_TIMESTEP_ACCURACY: SimFloat = 0.025;
+
+/// Deepest level of [BlockTimesteps], whose timestep is about a millionth of the step.
+pub const MAX_TIMESTEP_LEVEL: u32 = 20;
+
+// mass of bodies built with [BodyBuilder] unless set otherwise
+const DEFAULT_BODY_MASS: SimFloat = 1.0;
+
+/// Density at which a body with a mass of 1 has a radius of 0.005.
+pub const DEFAULT_BODY_DENSITY: SimFloat = 3.0 / (4.0 * std::f32::consts::PI * 0.005 * 0.005 * 0.005);
 
 use cgmath::{
-    EuclideanSpace,
+    InnerSpace,
     Point2,
     Vector2,
 };
+use kernel::ForceKernel;
 use quadtree::{
+    ContinueTraverse,
+    ElementKey,
+    FlatQuadtree,
     Positioned,
     Quadtree,
-    QuadtreeChild,
 };
+use thiserror::Error;
 use wgpu::Color;
 
 use crate::new_map_key;
-use crate::utility::index_map::{MapKey, PrimaryMap};
+use crate::rendering::{
+    blend,
+    rgb_hex,
+};
+use crate::utility::index_map::{MapKey, PrimaryMap, SecondaryMap};
+use crate::utility::should_approximate;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Body {
     pub position: Point2<SimFloat>,
     pub velocity: Vector2<SimFloat>,
     pub mass: SimFloat,
     pub radius: SimFloat,
     pub color: Color,
+    // fixed bodies attract the others but never move themselves, their velocity is kept but not used
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub fixed: bool,
+    // tracers are attracted by the others but don't attract anything themselves, their mass only sets how
+    // they are drawn
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub tracer: bool,
 }
 
 impl Body {
@@ -46,9 +105,25 @@ impl Body {
             mass,
             radius,
             color,
+            fixed: false,
+            tracer: false,
         }
     }
 
+    /// Radius of a sphere with the given mass and density, so bodies of equal density grow with the cube root
+    /// of their mass.
+    pub fn radius_from_mass(
+        mass: SimFloat,
+        density: SimFloat,
+    ) -> SimFloat {
+        (3.0 * mass / (4.0 * std::f32::consts::PI * density)).cbrt()
+    }
+
+    /// Starts building a body at the given position. Everything else has a default, see [BodyBuilder].
+    pub fn at(position: Point2<SimFloat>) -> BodyBuilder {
+        BodyBuilder::new(position)
+    }
+
     pub fn color(&self) -> Color {
         self.color
     }
@@ -56,9 +131,156 @@ impl Body {
     pub fn radius(&self) -> SimFloat {
         self.radius
     }
+
+    /// The mass other bodies are attracted by, which is 0 for tracers.
+    pub fn active_mass(&self) -> SimFloat {
+        if self.tracer { 0.0 } else { self.mass }
+    }
+
+    /// Combines two bodies into one at their center of mass, keeping their momentum and volume. The color
+    /// is a blend weighted by mass, see [blend]. If only one of them is fixed, the result stays fixed in its
+    /// place instead. Tracers merging with other bodies vanish without changing them.
+    pub fn merge(
+        self,
+        other: Self,
+    ) -> Self {
+        match (self.tracer, other.tracer) {
+            (true, false) => return other,
+            (false, true) => return self,
+            _ => {}
+        }
+
+        let mass = self.mass + other.mass;
+        // massless bodies count equally
+        let weight = if mass > 0.0 { other.mass / mass } else { 0.5 };
+        let (position, velocity) = match (self.fixed, other.fixed) {
+            (true, false) => (self.position, self.velocity),
+            (false, true) => (other.position, other.velocity),
+            _ => (
+                self.position + (other.position - self.position) * weight,
+                self.velocity * (1.0 - weight) + other.velocity * weight,
+            ),
+        };
+
+        Self {
+            position,
+            velocity,
+            mass,
+            radius: (self.radius.powi(3) + other.radius.powi(3)).cbrt(),
+            color: blend(self.color, other.color, weight as f64),
+            fixed: self.fixed || other.fixed,
+            tracer: self.tracer,
+        }
+    }
+}
+
+/// Builds a [Body] that is at rest, has a mass of 1 and the default color unless set otherwise. Unless it
+/// is set explicitly, the radius is derived from the mass with [Body::radius_from_mass] and
+/// [DEFAULT_BODY_DENSITY] or the density that is set.
+#[derive(Debug, Clone)]
+pub struct BodyBuilder {
+    position: Point2<SimFloat>,
+    velocity: Vector2<SimFloat>,
+    mass: SimFloat,
+    radius: Option<SimFloat>,
+    density: SimFloat,
+    color: Color,
+    fixed: bool,
+    tracer: bool,
+}
+
+impl BodyBuilder {
+    pub fn new(position: Point2<SimFloat>) -> Self {
+        Self {
+            position,
+            velocity: Vector2::new(0.0, 0.0),
+            mass: DEFAULT_BODY_MASS,
+            radius: None,
+            density: DEFAULT_BODY_DENSITY,
+            color: rgb_hex(0xC4603B),
+            fixed: false,
+            tracer: false,
+        }
+    }
+
+    pub fn velocity(
+        mut self,
+        velocity: Vector2<SimFloat>,
+    ) -> Self {
+        self.velocity = velocity;
+        self
+    }
+
+    pub fn mass(
+        mut self,
+        mass: SimFloat,
+    ) -> Self {
+        self.mass = mass;
+        self
+    }
+
+    pub fn radius(
+        mut self,
+        radius: SimFloat,
+    ) -> Self {
+        self.radius = Some(radius);
+        self
+    }
+
+    /// Derives the radius from the mass with this density instead of [DEFAULT_BODY_DENSITY]. Has no effect if
+    /// the radius is set explicitly.
+    pub fn density(
+        mut self,
+        density: SimFloat,
+    ) -> Self {
+        self.density = density;
+        self
+    }
+
+    pub fn color(
+        mut self,
+        color: Color,
+    ) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Fixed bodies stay in place, see [Body::fixed].
+    pub fn fixed(
+        mut self,
+        fixed: bool,
+    ) -> Self {
+        self.fixed = fixed;
+        self
+    }
+
+    /// Tracers don't attract other bodies, see [Body::tracer].
+    pub fn tracer(
+        mut self,
+        tracer: bool,
+    ) -> Self {
+        self.tracer = tracer;
+        self
+    }
+
+    pub fn build(self) -> Body {
+        let radius = self.radius.unwrap_or_else(|| Body::radius_from_mass(self.mass, self.density));
+        Body {
+            fixed: self.fixed,
+            tracer: self.tracer,
+            ..Body::new(self.position, self.velocity, self.mass, radius, self.color)
+        }
+    }
+}
+
+impl From<BodyBuilder> for Body {
+    fn from(builder: BodyBuilder) -> Self {
+        builder.build()
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Pseudobody {
     position: Point2<SimFloat>,
     mass: SimFloat,
@@ -71,6 +293,32 @@ impl Pseudobody {
     ) -> Self {
         Self { position, mass }
     }
+
+    pub fn position(&self) -> Point2<SimFloat> {
+        self.position
+    }
+
+    pub fn mass(&self) -> SimFloat {
+        self.mass
+    }
+
+    /// Combines two pseudobodies into one at their center of mass.
+    pub fn merge(
+        self,
+        other: Self,
+    ) -> Self {
+        let mass = self.mass + other.mass;
+        if mass <= 0.0 {
+            return Self::default();
+        }
+
+        let position = Point2::new(
+            (self.position.x * self.mass + other.position.x * other.mass) / mass,
+            (self.position.y * self.mass + other.position.y * other.mass) / mass,
+        );
+
+        Self { position, mass }
+    }
 }
 
 impl Default for Pseudobody {
@@ -82,12 +330,161 @@ impl Default for Pseudobody {
     }
 }
 
-#[derive(Debug)]
+/// Wall time spent in the phases of a single step and what happened to the bodies. With block timesteps,
+/// the times are summed over all substeps.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StepStats {
+    pub tree_build: Duration,
+    pub aggregate: Duration,
+    pub force: Duration,
+    // moving the bodies and updating their velocities, including the boundary
+    pub integrate: Duration,
+    // bodies removed by the boundary
+    pub removed_bodies: usize,
+}
+
+/// Lets every body advance with its own timestep, the step passed to [Simulation::advance] divided by a
+/// power of two. A body on level `k` takes `2^k` steps of `dt / 2^k` during every step, so only bodies with
+/// large accelerations take many small steps while the others coast. The level of a body is chosen at the
+/// end of every step such that its timestep is at most `sqrt(accuracy * softening / acceleration)`, up to
+/// `max_level`, which is never deeper than [MAX_TIMESTEP_LEVEL].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockTimesteps {
+    pub max_level: u32,
+    pub accuracy: SimFloat,
+}
+
+impl BlockTimesteps {
+    /// Levels deeper than [MAX_TIMESTEP_LEVEL] are clamped to it.
+    pub fn new(max_level: u32) -> Self {
+        Self {
+            max_level: max_level.min(MAX_TIMESTEP_LEVEL),
+            accuracy: DEFAULT_TIMESTEP_ACCURACY,
+        }
+    }
+
+    // the level whose timestep is short enough for the acceleration, at a length scale of the softening
+    fn level(
+        &self,
+        dt: SimFloat,
+        acceleration: SimFloat,
+        softening: SimFloat,
+    ) -> u32 {
+        let timestep = (self.accuracy * softening / acceleration).sqrt();
+        // bodies without any acceleration have an infinite timestep, or none at all without softening
+        if timestep.is_nan() || timestep >= dt {
+            return 0;
+        }
+
+        // also clamped here since max_level can be set directly, a step can't be split into 2^32 substeps
+        ((dt / timestep).log2().ceil() as u32).min(self.max_level).min(MAX_TIMESTEP_LEVEL)
+    }
+}
+
+/// Decides how the force on every body is calculated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ForceMode {
+    /// Approximates distant groups of bodies by their pseudobody, see [should_approximate].
+    #[default]
+    BarnesHut,
+    /// Sums the attraction of every pair of bodies, which takes quadratic time. Meant as a reference for
+    /// validating the approximation with few bodies.
+    Exact,
+}
+
+/// Decides what happens to bodies far away from the origin.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BoundaryMode {
+    /// Bodies may move arbitrarily far, the quadtree grows to fit them.
+    #[default]
+    Unbounded,
+    /// Bodies leaving the square with half-width `extent` around the origin are removed.
+    Open { extent: SimFloat },
+    /// Bodies bounce off the walls of the square with half-width `extent` around the origin.
+    Reflective { extent: SimFloat },
+    /// Bodies leaving the square with half-width `extent` around the origin enter it again on the opposite
+    /// side. Forces are still calculated without wrapping around, so bodies near opposite walls don't
+    /// attract each other through the boundary.
+    Periodic { extent: SimFloat },
+}
+
+#[derive(Debug, Error)]
+#[error("Invalid boundary {0:?}, expected unbounded or one of open, reflective or periodic followed by :<extent>.")]
+pub struct InvalidBoundaryError(String);
+
+impl FromStr for BoundaryMode {
+    type Err = InvalidBoundaryError;
+
+    // parses e.g. "unbounded" or "periodic:2.5"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "unbounded" {
+            return Ok(BoundaryMode::Unbounded);
+        }
+
+        let error = || InvalidBoundaryError(s.to_owned());
+        let (mode, extent) = s.split_once(':').ok_or_else(error)?;
+        let extent = extent.parse::<SimFloat>().ok().filter(|extent| *extent > 0.0).ok_or_else(error)?;
+
+        match mode {
+            "open" => Ok(BoundaryMode::Open { extent }),
+            "reflective" => Ok(BoundaryMode::Reflective { extent }),
+            "periodic" => Ok(BoundaryMode::Periodic { extent }),
+            _ => Err(error()),
+        }
+    }
+}
+
+/// Pushes overlapping bodies apart like soft spheres, so they pile up instead of passing through each other.
+/// The force grows linearly with how deep the bodies overlap, so the timestep has to be well below
+/// `sqrt(mass / stiffness)` of the lightest bodies to keep contacts from gaining energy. Tracers neither push
+/// nor get pushed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContactForce {
+    /// Force per distance of overlap.
+    pub stiffness: SimFloat,
+    /// Force per speed at which the bodies approach each other. Removes energy on every contact, without it
+    /// bodies bounce off each other elastically and piles never settle.
+    pub damping: SimFloat,
+}
+
+#[derive(Debug, Error)]
+#[error("Invalid contact force {0:?}, expected a stiffness optionally followed by :<damping>.")]
+pub struct InvalidContactForceError(String);
+
+impl FromStr for ContactForce {
+    type Err = InvalidContactForceError;
+
+    // parses e.g. "0.1" or "0.1:0.01"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || InvalidContactForceError(s.to_owned());
+        let (stiffness, damping) = s.split_once(':').unwrap_or((s, "0"));
+        let parse = |value: &str| value.parse::<SimFloat>().ok().filter(|value| *value >= 0.0).ok_or_else(error);
+
+        Ok(ContactForce {
+            stiffness: parse(stiffness)?,
+            damping: parse(damping)?,
+        })
+    }
+}
+
+// the mass is copied into the tree so aggregating and the force walk don't look up every body again
+#[derive(Debug, Clone, Copy)]
 pub struct QuadtreeBody {
     position: Point2<SimFloat>,
+    mass: SimFloat,
     body_key: BodyKey,
 }
 
+impl QuadtreeBody {
+    pub fn body_key(&self) -> BodyKey {
+        self.body_key
+    }
+
+    pub fn mass(&self) -> SimFloat {
+        self.mass
+    }
+}
+
 impl Positioned for QuadtreeBody {
     fn position(&self) -> Point2<SimFloat> {
         self.position
@@ -99,10 +496,48 @@ new_map_key! { pub struct BodyKey; "BODY"; }
 pub struct Simulation {
     bodies: PrimaryMap<BodyKey, Body>,
     quadtree: Quadtree<QuadtreeBody, Pseudobody>,
+    // copy of the quadtree with its pseudobodies that the force walk reads, updated along with them
+    flat_quadtree: FlatQuadtree<QuadtreeBody, Pseudobody>,
 
     // if the size of a pseudoparticle (s) divided by its distance (d) is below
     // this threshold, the pseudoparticle's mass is used and its children are ignored
     pseudobody_threshold: SimFloat,
+
+    // plummer softening length. distances are replaced with sqrt(r^2 + softening^2), which bounds the force
+    // between close bodies. this keeps close encounters from producing huge velocities at the cost of
+    // underestimating forces at distances comparable to the softening length
+    softening: SimFloat,
+    // replaces the plummer softening if set. the softening length above is still used for block timesteps
+    force_kernel: Option<Box<dyn ForceKernel>>,
+    // overlapping bodies pass through each other if not set
+    contact_force: Option<ContactForce>,
+    boundary_mode: BoundaryMode,
+    force_mode: ForceMode,
+    // Barnes-Hut forces are calculated on the CPU if not set
+    #[cfg(feature = "gpu-forces")]
+    gpu_forces: Option<gpu::GpuForces>,
+
+    // moves bodies that left their leaf instead of rebuilding the tree every step
+    warm_start: bool,
+    // the element of every body in the tree, only kept while warm starting
+    body_elements: SecondaryMap<BodyKey, ElementKey>,
+    // number of nodes at which the tree is rebuilt even if all bodies could be moved, 0 if there is no tree to
+    // update
+    max_warm_start_nodes: usize,
+
+    // every body advances with the full step if not set
+    block_timesteps: Option<BlockTimesteps>,
+    // level of every body's block timestep, bodies start out on level 0
+    timestep_levels: SecondaryMap<BodyKey, u32>,
+
+    // number of calls to advance so far
+    step: u64,
+    // sum of the timesteps passed to advance so far
+    sim_time: SimFloat,
+    // wall time the last call to advance took
+    step_duration: Duration,
+    step_stats: StepStats,
+    step_callback: Option<StepCallback>,
 }
 
 impl Simulation {
@@ -115,8 +550,27 @@ impl Simulation {
     {
         let mut slf = Self {
             bodies: PrimaryMap::with_capacity(bodies.len()),
-            quadtree: Quadtree::new(10000.0),
+            quadtree: Quadtree::new(1.0),
+            flat_quadtree: FlatQuadtree::default(),
             pseudobody_threshold,
+            softening: DEFAULT_SOFTENING,
+            force_kernel: None,
+            contact_force: None,
+            boundary_mode: BoundaryMode::default(),
+            force_mode: ForceMode::default(),
+            #[cfg(feature = "gpu-forces")]
+            gpu_forces: None,
+            warm_start: false,
+            // bodies without an element are never looked up by indexing
+            body_elements: SecondaryMap::with_default(ElementKey::try_from_index(0).unwrap()),
+            max_warm_start_nodes: 0,
+            block_timesteps: None,
+            timestep_levels: SecondaryMap::default(),
+            step: 0,
+            sim_time: 0.0,
+            step_duration: Duration::ZERO,
+            step_stats: StepStats::default(),
+            step_callback: None,
         };
 
         for body in bodies {
@@ -126,63 +580,984 @@ impl Simulation {
         slf
     }
 
+    /// Advances all bodies by `dt` seconds of simulated time and returns how long the phases of the step
+    /// took, which are also available through [Simulation::step_stats] afterwards. Velocities are in
+    /// distance units per second.
     pub fn advance(
         &mut self,
-        dt: Duration,
-    ) -> Result<(), String> {
-        log::trace!("Updating simulation with dt={:?}", dt);
+        dt: SimFloat,
+    ) -> StepStats {
+        log::trace!("Updating simulation with dt={}s", dt);
+        let step_start = Instant::now();
+        self.step_stats = StepStats::default();
 
-        // 0. apply old velocity
-        for body in self.bodies.values_mut() {
-            body.position += body.velocity * dt.as_millis() as SimFloat;
+        match self.block_timesteps {
+            None => {
+                self.drift(dt);
+                self.rebuild_tree();
+
+                let start = Instant::now();
+                let accelerations = self.accelerations(|body_key| !self.bodies[body_key].fixed);
+                self.step_stats.force += Instant::now() - start;
+
+                let start = Instant::now();
+                for (body_key, acceleration) in accelerations {
+                    self.bodies[body_key].velocity += acceleration * dt;
+                }
+                self.step_stats.integrate += Instant::now() - start;
+            }
+            Some(block_timesteps) => self.advance_blocks(dt, block_timesteps),
+        }
+
+        self.step += 1;
+        self.sim_time += dt;
+        self.step_duration = Instant::now() - step_start;
+        log::trace!("Finished step in {:?}: {:?}", self.step_duration, self.step_stats);
+
+        // taken out while it runs so it can look at the simulation
+        if let Some(mut step_callback) = self.step_callback.take() {
+            step_callback(self);
+            self.step_callback = Some(step_callback);
+        }
+
+        self.step_stats
+    }
+
+    // splits the step into substeps of the smallest timestep any body has. all bodies drift during every
+    // substep, but only those whose own timestep ends with the substep get their velocity updated. all
+    // timesteps end with the last substep, which is when the bodies pick their next level
+    fn advance_blocks(
+        &mut self,
+        dt: SimFloat,
+        block_timesteps: BlockTimesteps,
+    ) {
+        let deepest_level = self.bodies.keys().map(|body_key| self.timestep_levels[body_key]).max().unwrap_or(0);
+        let substeps = 1u32 << deepest_level;
+        let substep_dt = dt / substeps as SimFloat;
+
+        for substep in 1..=substeps {
+            self.drift(substep_dt);
+            self.rebuild_tree();
+
+            // the timestep on level k spans 2^(deepest - k) substeps
+            let start = Instant::now();
+            let levels = &self.timestep_levels;
+            let accelerations = self.accelerations(|body_key| {
+                !self.bodies[body_key].fixed && substep % (1 << (deepest_level - levels[body_key])) == 0
+            });
+            self.step_stats.force += Instant::now() - start;
+
+            let start = Instant::now();
+            for (body_key, acceleration) in accelerations {
+                let level = self.timestep_levels[body_key];
+                self.bodies[body_key].velocity += acceleration * (dt / (1u32 << level) as SimFloat);
+
+                if substep == substeps {
+                    let level = block_timesteps.level(dt, acceleration.magnitude(), self.softening);
+                    self.timestep_levels.insert(body_key, level);
+                }
+            }
+            self.step_stats.integrate += Instant::now() - start;
         }
 
-        // 1. rebuild quadtree
+        log::trace!("Advanced with {} substeps", substeps);
+    }
+
+    // moves all bodies that aren't fixed along their velocity and applies the boundary
+    fn drift(
+        &mut self,
+        dt: SimFloat,
+    ) {
         let start = Instant::now();
+        for body in self.bodies.values_mut().filter(|body| !body.fixed) {
+            body.position += body.velocity * dt;
+        }
 
-        self.quadtree.clear();
-        for (body_key, body) in self.bodies.items() {
-            self.quadtree.insert(QuadtreeBody { position: body.position, body_key })?;
+        self.step_stats.removed_bodies += self.apply_boundary();
+        self.step_stats.integrate += Instant::now() - start;
+    }
+
+    // brings the quadtree up to date with the bodies and calculates the pseudobodies
+    fn rebuild_tree(&mut self) {
+        let start = Instant::now();
+
+        if !(self.warm_start && self.update_tree()) {
+            self.build_tree();
         }
 
         let duration = Instant::now() - start;
         log::trace!("Built quadtree with {} nodes in {:?}", self.quadtree.nodes().len(), duration);
+        self.step_stats.tree_build += duration;
 
-        // 2. calculate pseudobodies
         let start = Instant::now();
 
+        self.quadtree.aggregate(
+            |pseudobody, element| pseudobody.merge(Pseudobody::new(element.position, element.mass)),
+            Pseudobody::merge,
+        );
+        self.quadtree.flatten_into(&mut self.flat_quadtree);
 
         let duration = Instant::now() - start;
-        log::trace!("Calculated pseudobodies in {:?}", duration);
+        log::trace!("Calculated and flattened pseudobodies in {:?}", duration);
+        self.step_stats.aggregate += duration;
+    }
 
-        // 3. calculate forces for every body and update velocities
+    // builds the quadtree from scratch with a region tightly enclosing all bodies
+    fn build_tree(&mut self) {
+        let (min, max) = self.bounding_box();
+
+        if self.bodies.is_empty() {
+            self.quadtree.reset(Point2::new(0.0, 0.0), 1.0);
+        } else {
+            let center = Point2::new(0.5 * (min.x + max.x), 0.5 * (min.y + max.y));
+            // the root fits the bounding box on both axes, so flat distributions don't waste levels on empty
+            // space along the short one
+            let extents = Vector2::new(
+                (0.5 * (max.x - min.x)).max(SimFloat::EPSILON),
+                (0.5 * (max.y - min.y)).max(SimFloat::EPSILON),
+            );
+            let margin = if self.warm_start { WARM_START_MARGIN } else { QUADTREE_MARGIN };
+            self.quadtree.reset_with_extents(center, extents * (1.0 + margin));
+        }
+
+        let out_of_bounds = self.quadtree.build_from(self.bodies.items().map(|(body_key, body)| QuadtreeBody {
+            position: body.position,
+            mass: body.active_mass(),
+            body_key,
+        }));
+
+        // the region encloses all bodies, so this only happens for invalid positions. such bodies are left out
+        // of the tree for this step
+        for element in out_of_bounds {
+            log::warn!("Skipping body {} at {:?} outside of the quadtree", element.body_key, element.position);
+        }
+
+        self.body_elements.clear();
+        if self.warm_start {
+            for (element_key, element) in self.quadtree.elements() {
+                self.body_elements.insert(element.body_key, element_key);
+            }
+            self.max_warm_start_nodes = WARM_START_MAX_GROWTH * self.quadtree.nodes().len();
+        } else {
+            self.max_warm_start_nodes = 0;
+        }
+    }
+
+    // moves the bodies that left their leaf since the last step and adds and removes the bodies that were
+    // added and removed. returns false if the tree has to be built from scratch instead because a body left
+    // its region or it has grown too much, leaving it partially updated
+    fn update_tree(&mut self) -> bool {
+        if self.quadtree.nodes().len() > self.max_warm_start_nodes {
+            return false;
+        }
+
+        // the map only knows the index of a key, the element tells whether its body is still the same
+        let removed = self
+            .body_elements
+            .items()
+            .map(|(_, &element_key)| (self.quadtree.element(element_key).body_key, element_key))
+            .filter(|(body_key, _)| !self.bodies.contains_key(*body_key))
+            .collect::<Vec<_>>();
+        for (body_key, element_key) in removed {
+            self.quadtree.remove(element_key);
+            self.body_elements.remove(body_key);
+        }
+
+        for (body_key, body) in self.bodies.items() {
+            let element = QuadtreeBody {
+                position: body.position,
+                mass: body.active_mass(),
+                body_key,
+            };
+
+            let updated = match self.body_elements.get(body_key) {
+                Some(&element_key) => self.quadtree.replace(element_key, element).is_ok(),
+                None => match self.quadtree.insert(element) {
+                    Ok(element_key) => {
+                        self.body_elements.insert(body_key, element_key);
+                        true
+                    }
+                    Err(_) => false,
+                },
+            };
+
+            if !updated {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    // calculates the accelerations of the bodies for which `is_active` returns true. the tree isn't modified
+    // during this pass, so every body can walk it independently
+    fn accelerations<F>(
+        &self,
+        is_active: F,
+    ) -> Vec<(BodyKey, Vector2<SimFloat>)>
+    where
+        F: Fn(BodyKey) -> bool + Sync,
+    {
         let start = Instant::now();
 
+        // overlapping bodies are at most the largest radius farther apart than the radius of the body itself
+        let max_radius = match self.contact_force {
+            Some(_) => self.bodies.values().map(Body::radius).fold(0.0, SimFloat::max),
+            None => 0.0,
+        };
+        let contact = |body_key: BodyKey, body: &Body| match self.contact_force {
+            Some(contact_force) => self.contact_acceleration(body_key, body, contact_force, max_radius),
+            None => Vector2::new(0.0, 0.0),
+        };
 
+        #[cfg(feature = "gpu-forces")]
+        if let Some(mut accelerations) = self.gpu_accelerations(&is_active) {
+            for (body_key, acceleration) in &mut accelerations {
+                *acceleration += contact(*body_key, &self.bodies[*body_key]);
+            }
 
-        let duration = Instant::now() - start;
-        log::trace!("Calculated forces in {:?}", duration);
+            let duration = Instant::now() - start;
+            log::trace!("Calculated forces of {} bodies on the GPU in {:?}", accelerations.len(), duration);
+            return accelerations;
+        }
+
+        #[cfg(feature = "parallel")]
+        let accelerations = {
+            use rayon::prelude::*;
+
+            self.bodies
+                .par_items()
+                .filter(|(body_key, _)| is_active(*body_key))
+                .map(|(body_key, body)| (body_key, self.body_force(body_key, body) + contact(body_key, body)))
+                .collect::<Vec<_>>()
+        };
 
-        // 3. apply force to body
+        #[cfg(not(feature = "parallel"))]
+        let accelerations = self
+            .bodies
+            .items()
+            .filter(|(body_key, _)| is_active(*body_key))
+            .map(|(body_key, body)| (body_key, self.body_force(body_key, body) + contact(body_key, body)))
+            .collect::<Vec<_>>();
 
-        Ok(())
+        log::trace!("Calculated forces of {} bodies in {:?}", accelerations.len(), Instant::now() - start);
+        accelerations
     }
 
+    // None if forces aren't calculated on the GPU or it failed, in which case the CPU takes over for this step
+    #[cfg(feature = "gpu-forces")]
+    fn gpu_accelerations<F>(
+        &self,
+        is_active: &F,
+    ) -> Option<Vec<(BodyKey, Vector2<SimFloat>)>>
+    where
+        F: Fn(BodyKey) -> bool,
+    {
+        let gpu_forces = self
+            .gpu_forces
+            .as_ref()
+            .filter(|_| self.force_mode == ForceMode::BarnesHut && self.force_kernel.is_none())?;
+        let bodies = self
+            .bodies
+            .items()
+            .filter(|(body_key, _)| is_active(*body_key))
+            .map(|(body_key, body)| (body_key, body.position))
+            .collect::<Vec<_>>();
+
+        match gpu_forces.accelerations(self, &bodies) {
+            Ok(accelerations) => Some(bodies.into_iter().map(|(body_key, _)| body_key).zip(accelerations).collect()),
+            Err(e) => {
+                log::error!("Calculating forces on the CPU instead: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Uses the given softening length instead of [DEFAULT_SOFTENING]. A softening of 0 gives the exact
+    /// Newtonian force, which diverges for close encounters.
+    pub fn with_softening(
+        mut self,
+        softening: SimFloat,
+    ) -> Self {
+        self.softening = softening;
+        self
+    }
+
+    /// The Barnes-Hut opening angle, see [should_approximate].
+    pub fn threshold(&self) -> SimFloat {
+        self.pseudobody_threshold
+    }
+
+    /// Changes the opening angle starting with the next step. 0 calculates exact forces between all bodies,
+    /// larger values approximate more nodes by their pseudobody, which is faster but less accurate.
+    pub fn set_threshold(
+        &mut self,
+        threshold: SimFloat,
+    ) {
+        self.pseudobody_threshold = threshold;
+    }
+
+    pub fn softening(&self) -> SimFloat {
+        self.softening
+    }
+
+    pub fn set_softening(
+        &mut self,
+        softening: SimFloat,
+    ) {
+        self.softening = softening;
+    }
+
+    /// Calculates the attraction between bodies with the given kernel instead of the plummer softening, see
+    /// [kernel]. Forces are always calculated on the CPU with a kernel, since the compute shader only knows
+    /// the plummer softening.
+    pub fn with_force_kernel(
+        mut self,
+        force_kernel: impl ForceKernel + 'static,
+    ) -> Self {
+        self.force_kernel = Some(Box::new(force_kernel));
+        self
+    }
+
+    pub fn force_kernel(&self) -> Option<&dyn ForceKernel> {
+        self.force_kernel.as_deref()
+    }
+
+    pub fn set_force_kernel(
+        &mut self,
+        force_kernel: Option<Box<dyn ForceKernel>>,
+    ) {
+        self.force_kernel = force_kernel;
+    }
+
+    /// Adds a repulsive force between overlapping bodies, see [ContactForce]. Overlaps are found with the
+    /// quadtree, so they are also handled when forces are calculated exactly or on the GPU.
+    pub fn with_contact_force(
+        mut self,
+        contact_force: ContactForce,
+    ) -> Self {
+        self.contact_force = Some(contact_force);
+        self
+    }
+
+    pub fn contact_force(&self) -> Option<ContactForce> {
+        self.contact_force
+    }
+
+    pub fn set_contact_force(
+        &mut self,
+        contact_force: Option<ContactForce>,
+    ) {
+        self.contact_force = contact_force;
+    }
+
+    /// Calls `step_callback` at the end of every call to [Simulation::advance], after the step count, time
+    /// and stats have been updated. Meant for analysis like logging the energy or stopping on a condition.
+    pub fn with_step_callback(
+        mut self,
+        step_callback: impl FnMut(&Simulation) + Send + Sync + 'static,
+    ) -> Self {
+        self.step_callback = Some(Box::new(step_callback));
+        self
+    }
+
+    pub fn set_step_callback(
+        &mut self,
+        step_callback: Option<StepCallback>,
+    ) {
+        self.step_callback = step_callback;
+    }
+
+    /// Calculates forces with the given mode instead of [ForceMode::BarnesHut].
+    pub fn with_force_mode(
+        mut self,
+        force_mode: ForceMode,
+    ) -> Self {
+        self.force_mode = force_mode;
+        self
+    }
+
+    pub fn force_mode(&self) -> ForceMode {
+        self.force_mode
+    }
+
+    /// Calculates Barnes-Hut forces on the GPU instead of the CPU if set, see [gpu::GpuForces]. Has no effect
+    /// with [ForceMode::Exact] or a force kernel.
+    #[cfg(feature = "gpu-forces")]
+    pub fn with_gpu_forces(
+        mut self,
+        gpu_forces: gpu::GpuForces,
+    ) -> Self {
+        self.gpu_forces = Some(gpu_forces);
+        self
+    }
+
+    #[cfg(feature = "gpu-forces")]
+    pub fn set_gpu_forces(
+        &mut self,
+        gpu_forces: Option<gpu::GpuForces>,
+    ) {
+        self.gpu_forces = gpu_forces;
+    }
+
+    pub fn set_force_mode(
+        &mut self,
+        force_mode: ForceMode,
+    ) {
+        self.force_mode = force_mode;
+    }
+
+    /// Keeps the quadtree between steps and only moves the bodies that left their leaf, see
+    /// [Quadtree::replace]. This is faster than rebuilding the tree when bodies move little per step. The
+    /// tree is still rebuilt when a body leaves its region.
+    pub fn with_warm_start(
+        mut self,
+        warm_start: bool,
+    ) -> Self {
+        self.warm_start = warm_start;
+        self
+    }
+
+    pub fn warm_start(&self) -> bool {
+        self.warm_start
+    }
+
+    pub fn set_warm_start(
+        &mut self,
+        warm_start: bool,
+    ) {
+        self.warm_start = warm_start;
+    }
+
+    /// Advances bodies with their own timesteps, see [BlockTimesteps].
+    pub fn with_block_timesteps(
+        mut self,
+        block_timesteps: BlockTimesteps,
+    ) -> Self {
+        self.block_timesteps = Some(block_timesteps);
+        self
+    }
+
+    pub fn block_timesteps(&self) -> Option<BlockTimesteps> {
+        self.block_timesteps
+    }
+
+    /// Enables or disables block timesteps starting with the next step. Bodies keep their levels while
+    /// disabled.
+    pub fn set_block_timesteps(
+        &mut self,
+        block_timesteps: Option<BlockTimesteps>,
+    ) {
+        self.block_timesteps = block_timesteps;
+    }
+
+    /// The level of the body's block timestep, i.e. into how many steps each step is split as a power of two.
+    /// 0 for bodies that haven't been advanced with block timesteps yet.
+    pub fn timestep_level(
+        &self,
+        body_key: BodyKey,
+    ) -> u32 {
+        self.timestep_levels[body_key]
+    }
+
+    pub fn with_boundary_mode(
+        mut self,
+        boundary_mode: BoundaryMode,
+    ) -> Self {
+        self.boundary_mode = boundary_mode;
+        self
+    }
+
+    pub fn boundary_mode(&self) -> BoundaryMode {
+        self.boundary_mode
+    }
+
+    pub fn set_boundary_mode(
+        &mut self,
+        boundary_mode: BoundaryMode,
+    ) {
+        self.boundary_mode = boundary_mode;
+    }
+
+    /// Removes all bodies for which `remove` returns true and returns how many were removed. The keys of the
+    /// remaining bodies stay valid.
+    pub fn remove_bodies_where(
+        &mut self,
+        mut remove: impl FnMut(BodyKey, &Body) -> bool,
+    ) -> usize {
+        let timestep_levels = &mut self.timestep_levels;
+        self.bodies.retain(|body_key, body| {
+            let keep = !remove(body_key, body);
+            if !keep {
+                timestep_levels.remove(body_key);
+            }
+            keep
+        })
+    }
+
+    /// Removes a single body, returning [None] if it has already been removed.
+    pub fn remove_body(
+        &mut self,
+        body_key: BodyKey,
+    ) -> Option<Body> {
+        self.timestep_levels.remove(body_key);
+        self.bodies.remove(body_key)
+    }
+
+    // returns the number of removed bodies
+    fn apply_boundary(&mut self) -> usize {
+        match self.boundary_mode {
+            BoundaryMode::Unbounded => 0,
+            BoundaryMode::Open { extent } => {
+                let removed = self.remove_bodies_where(|_, body| {
+                    body.position.x.abs() > extent || body.position.y.abs() > extent
+                });
+
+                if removed > 0 {
+                    log::debug!("Removed {} bodies outside the open boundary", removed);
+                }
+
+                removed
+            }
+            BoundaryMode::Reflective { extent } => {
+                for body in self.bodies.values_mut() {
+                    reflect(&mut body.position.x, &mut body.velocity.x, extent);
+                    reflect(&mut body.position.y, &mut body.velocity.y, extent);
+                }
+
+                0
+            }
+            BoundaryMode::Periodic { extent } => {
+                for body in self.bodies.values_mut() {
+                    body.position.x = wrap(body.position.x, extent);
+                    body.position.y = wrap(body.position.y, extent);
+                }
+
+                0
+            }
+        }
+    }
+
+    /// Replaces all bodies and starts over at step 0, keeping the parameters and the allocations of the
+    /// bodies and the quadtree. The new bodies are handed out the keys of the old ones again, so keys from
+    /// before the reset may refer to new bodies.
+    pub fn reset<T>(
+        &mut self,
+        bodies: T,
+    ) where
+        T: ExactSizeIterator<Item = Body>,
+    {
+        self.bodies.clear();
+        for body in bodies {
+            self.bodies.insert(body);
+        }
+
+        self.quadtree.clear();
+        // flattening the empty tree empties the flat one without freeing it
+        self.quadtree.flatten_into(&mut self.flat_quadtree);
+        self.body_elements.clear();
+        self.max_warm_start_nodes = 0;
+        self.timestep_levels.clear();
+
+        self.step = 0;
+        self.sim_time = 0.0;
+        self.step_duration = Duration::ZERO;
+        self.step_stats = StepStats::default();
+    }
+
+    /// Adds a body to the simulation. It is included in the quadtree starting with the next step.
+    pub fn add_body(
+        &mut self,
+        body: Body,
+    ) -> BodyKey {
+        self.bodies.insert(body)
+    }
+
+    /// Advances the simulation by `steps` steps of `dt` seconds each. This is only a convenience for calling
+    /// [Simulation::advance] in a loop, every step still rebuilds the tree, records its stats and calls the
+    /// step callback, so it is no faster. Tree rebuilds are saved by [Simulation::with_warm_start] instead,
+    /// which applies to both the same way.
+    pub fn advance_n(
+        &mut self,
+        steps: usize,
+        dt: SimFloat,
+    ) {
+        for _ in 0..steps {
+            self.advance(dt);
+        }
+    }
+
+    /// Returns the body if the key belongs to this simulation and it hasn't been removed.
+    pub fn body(
+        &self,
+        body_key: BodyKey,
+    ) -> Option<&Body> {
+        self.bodies.get(body_key)
+    }
+
+    /// Returns the body closest to `point`, found with the quadtree of the last step. Bodies added since then
+    /// aren't considered.
+    pub fn nearest_body(
+        &self,
+        point: Point2<SimFloat>,
+    ) -> Option<(BodyKey, &Body)> {
+        let body_key = self.quadtree.element(self.quadtree.nearest(point)?).body_key;
+        Some((body_key, self.bodies.get(body_key)?))
+    }
+
+    /// Returns the body for modifying it if the key belongs to this simulation and it hasn't been removed.
+    /// The quadtree and its pseudobodies only pick up the change on the next step.
+    pub fn body_mut(
+        &mut self,
+        body_key: BodyKey,
+    ) -> Option<&mut Body> {
+        self.bodies.get_mut(body_key)
+    }
+
+    /// Iterates over all bodies. The order is the same across steps unless bodies are added or removed.
     pub fn bodies(&self) -> impl ExactSizeIterator<Item = &Body> {
         self.bodies.values()
     }
 
+    /// Iterates over all bodies for modifying them, in the same order as [Simulation::bodies]. Like with
+    /// [Simulation::body_mut], the quadtree is only updated on the next step.
+    pub fn bodies_mut(&mut self) -> impl ExactSizeIterator<Item = &mut Body> {
+        self.bodies.values_mut()
+    }
+
+    /// Iterates over all bodies together with their keys, in the same order as [Simulation::bodies].
+    pub fn body_items(&self) -> impl ExactSizeIterator<Item = (BodyKey, &Body)> {
+        self.bodies.items()
+    }
+
+    /// Sum of the masses of all bodies.
+    pub fn total_mass(&self) -> SimFloat {
+        // summed in double precision since a million bodies lose a lot of precision in single precision
+        self.bodies.values().map(|body| body.mass as f64).sum::<f64>() as SimFloat
+    }
+
+    /// The minimum and maximum corner of the smallest axis-aligned box containing the positions of all bodies.
+    /// Both are the origin if there are no bodies.
+    pub fn bounding_box(&self) -> (Point2<SimFloat>, Point2<SimFloat>) {
+        if self.bodies.is_empty() {
+            return (Point2::new(0.0, 0.0), Point2::new(0.0, 0.0));
+        }
+
+        self.bodies.values().fold(
+            (Point2::new(SimFloat::MAX, SimFloat::MAX), Point2::new(SimFloat::MIN, SimFloat::MIN)),
+            |(min, max), body| {
+                (
+                    Point2::new(min.x.min(body.position.x), min.y.min(body.position.y)),
+                    Point2::new(max.x.max(body.position.x), max.y.max(body.position.y)),
+                )
+            },
+        )
+    }
+
+    /// Mass-weighted mean position of all bodies, or the origin if there is no mass.
+    pub fn center_of_mass(&self) -> Point2<SimFloat> {
+        let (weighted_x, weighted_y, mass) = self.bodies.values().fold((0.0, 0.0, 0.0), |(x, y, mass), body| {
+            let body_mass = body.mass as f64;
+            (x + body.position.x as f64 * body_mass, y + body.position.y as f64 * body_mass, mass + body_mass)
+        });
+
+        if mass <= 0.0 {
+            return Point2::new(0.0, 0.0);
+        }
+
+        Point2::new((weighted_x / mass) as SimFloat, (weighted_y / mass) as SimFloat)
+    }
+
+    /// Sum of mass times velocity of all bodies. Gravity between the bodies doesn't change it, so a nonzero
+    /// momentum moves the whole system at a constant velocity.
+    pub fn net_momentum(&self) -> Vector2<SimFloat> {
+        let (x, y) = self.bodies.values().fold((0.0, 0.0), |(x, y), body| {
+            let body_mass = body.mass as f64;
+            (x + body.velocity.x as f64 * body_mass, y + body.velocity.y as f64 * body_mass)
+        });
+
+        Vector2::new(x as SimFloat, y as SimFloat)
+    }
+
+    /// Subtracts the velocity of the center of mass from all bodies, so the system as a whole stays in place
+    /// instead of drifting away.
+    pub fn remove_net_drift(&mut self) {
+        let total_mass = self.total_mass();
+        if total_mass <= 0.0 {
+            return;
+        }
+
+        let drift = self.net_momentum() / total_mass;
+        for body in self.bodies.values_mut() {
+            body.velocity -= drift;
+        }
+    }
+
+    /// Number of steps advanced so far. It also tells whether the bodies moved since they were last looked at.
+    pub fn step_count(&self) -> u64 {
+        self.step
+    }
+
+    /// Simulated seconds advanced so far, the sum of the timesteps of all steps.
+    pub fn sim_time(&self) -> SimFloat {
+        self.sim_time
+    }
+
+    /// How long the last step took to compute.
+    pub fn step_duration(&self) -> Duration {
+        self.step_duration
+    }
+
+    /// Timings of the phases of the last step.
+    pub fn step_stats(&self) -> StepStats {
+        self.step_stats
+    }
+
     pub fn quadtree(&self) -> &Quadtree<QuadtreeBody, Pseudobody> {
         &self.quadtree
     }
 
-    fn calculate_body_force(
+    /// The quadtree of the last step packed for the force walk, see [FlatQuadtree].
+    pub fn flat_quadtree(&self) -> &FlatQuadtree<QuadtreeBody, Pseudobody> {
+        &self.flat_quadtree
+    }
+
+    fn body_force(
+        &self,
+        body_key: BodyKey,
+        body: &Body,
+    ) -> Vector2<SimFloat> {
+        match self.force_mode {
+            ForceMode::BarnesHut => self.calculate_body_force(body_key, body),
+            ForceMode::Exact => self.calculate_body_force_exact(body_key, body),
+        }
+    }
+
+    /// Calculates the acceleration of a body due to all other bodies, approximated with the quadtree of the
+    /// last step. The body doesn't need to be part of the simulation, but it is only excluded by its key.
+    pub fn calculate_body_force(
         &self,
+        body_key: BodyKey,
         body: &Body,
     ) -> Vector2<SimFloat> {
+        let mut acceleration = Vector2::new(0.0, 0.0);
+        let mut attract = |position: Point2<SimFloat>, mass: SimFloat| {
+            acceleration += self.attraction(body.position, position, mass);
+        };
 
         // start at root and resolve children until we are below the threshold
-        // let
-        todo!()
+        self.flat_quadtree.traverse(|node| {
+            if node.is_leaf() {
+                for element in self.flat_quadtree.leaf_elements(node) {
+                    if element.body_key != body_key {
+                        attract(element.position, element.mass);
+                    }
+                }
+
+                return ContinueTraverse::Stop;
+            }
+
+            let distance = (node.data.position - body.position).magnitude();
+            if should_approximate(node.width, distance, self.pseudobody_threshold) {
+                attract(node.data.position, node.data.mass);
+                ContinueTraverse::Stop
+            } else {
+                ContinueTraverse::Continue
+            }
+        });
+
+        acceleration
+    }
+
+    /// Like [Simulation::calculate_body_force], but sums the attraction of all other bodies exactly.
+    pub fn calculate_body_force_exact(
+        &self,
+        body_key: BodyKey,
+        body: &Body,
+    ) -> Vector2<SimFloat> {
+        self.bodies
+            .items()
+            .filter(|(other_key, _)| *other_key != body_key)
+            .map(|(_, other)| self.attraction(body.position, other.position, other.active_mass()))
+            .sum()
+    }
+
+    // the acceleration of a body pushed away from all bodies overlapping with it. the force only ever pushes,
+    // damping doesn't pull separating bodies back together
+    fn contact_acceleration(
+        &self,
+        body_key: BodyKey,
+        body: &Body,
+        contact_force: ContactForce,
+        max_radius: SimFloat,
+    ) -> Vector2<SimFloat> {
+        let mut acceleration = Vector2::new(0.0, 0.0);
+        if body.tracer || body.mass <= 0.0 {
+            return acceleration;
+        }
+
+        self.quadtree.for_each_within(body.position, body.radius + max_radius, |_, element| {
+            let Some(other) = self.bodies.get(element.body_key).filter(|other| !other.tracer) else {
+                return;
+            };
+
+            let direction = body.position - other.position;
+            let distance = direction.magnitude();
+            let overlap = body.radius + other.radius - distance;
+            // bodies at the same position have no direction to be pushed in
+            if element.body_key == body_key || overlap <= 0.0 || distance <= 0.0 {
+                return;
+            }
+
+            let normal = direction / distance;
+            let other_velocity = if other.fixed { Vector2::new(0.0, 0.0) } else { other.velocity };
+            let approach_speed = (other_velocity - body.velocity).dot(normal);
+            let force = contact_force.stiffness * overlap + contact_force.damping * approach_speed;
+            acceleration += normal * (force.max(0.0) / body.mass);
+        });
+
+        acceleration
+    }
+
+    // the acceleration towards a mass at `position` of a body at `origin`
+    fn attraction(
+        &self,
+        origin: Point2<SimFloat>,
+        position: Point2<SimFloat>,
+        mass: SimFloat,
+    ) -> Vector2<SimFloat> {
+        let direction = position - origin;
+
+        if let Some(force_kernel) = &self.force_kernel {
+            let distance = direction.magnitude();
+            return if distance > 0.0 {
+                direction * (force_kernel.acceleration(distance, mass) / distance)
+            } else {
+                Vector2::new(0.0, 0.0)
+            };
+        }
+
+        let distance_squared = direction.magnitude2() + self.softening * self.softening;
+        if distance_squared > 0.0 {
+            let distance_cubed = distance_squared * distance_squared.sqrt();
+            direction * (GRAVITATIONAL_CONSTANT * mass / distance_cubed)
+        } else {
+            Vector2::new(0.0, 0.0)
+        }
+    }
+}
+
+// moves a coordinate crossing a wall at +-extent back inside and turns its velocity away from the wall
+fn reflect(
+    position: &mut SimFloat,
+    velocity: &mut SimFloat,
+    extent: SimFloat,
+) {
+    if position.abs() > extent {
+        *position = position.clamp(-extent, extent);
+        *velocity = -velocity.abs() * position.signum();
+    }
+}
+
+// wraps a coordinate into [-extent, extent)
+fn wrap(
+    position: SimFloat,
+    extent: SimFloat,
+) -> SimFloat {
+    (position + extent).rem_euclid(2.0 * extent) - extent
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+
+    use super::presets::{
+        MassDistribution,
+        gaussian_blob,
+    };
+    use super::*;
+
+    fn blob(n: usize) -> Simulation {
+        Simulation::new(gaussian_blob(n, MassDistribution::Equal, 1).into_iter(), 0.5)
+    }
+
+    #[test]
+    fn advance_n_matches_repeated_advance() {
+        let mut batched = blob(200);
+        let mut stepped = blob(200);
+
+        batched.advance_n(10, 0.01);
+        for _ in 0..10 {
+            stepped.advance(0.01);
+        }
+
+        assert_eq!(batched.step_count(), stepped.step_count());
+        for ((_, a), (_, b)) in batched.body_items().zip(stepped.body_items()) {
+            assert!((a.position - b.position).magnitude() < 1e-6);
+            assert!((a.velocity - b.velocity).magnitude() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn timestep_levels_are_clamped() {
+        assert_eq!(BlockTimesteps::new(32).max_level, MAX_TIMESTEP_LEVEL);
+
+        let block_timesteps = BlockTimesteps {
+            max_level: 32,
+            accuracy: DEFAULT_TIMESTEP_ACCURACY,
+        };
+        assert_eq!(block_timesteps.level(1.0, 1e30, DEFAULT_SOFTENING), MAX_TIMESTEP_LEVEL);
+    }
+
+    #[test]
+    fn removing_bodies_forgets_their_timestep_level() {
+        let mut simulation = blob(50).with_block_timesteps(BlockTimesteps::new(4));
+        simulation.advance(0.01);
+
+        let body_keys = simulation.body_items().map(|(body_key, _)| body_key).collect::<Vec<_>>();
+        assert!(body_keys.iter().all(|&body_key| simulation.timestep_levels.get(body_key).is_some()));
+
+        assert_eq!(simulation.remove_bodies_where(|_, _| true), body_keys.len());
+        assert!(body_keys.iter().all(|&body_key| simulation.timestep_levels.get(body_key).is_none()));
+    }
+
+    #[test]
+    fn iteration_order_is_stable_across_steps() {
+        let mut simulation = blob(200);
+        let keys = simulation.body_items().map(|(body_key, _)| body_key).collect::<Vec<_>>();
+
+        for _ in 0..5 {
+            simulation.advance(0.01);
+            assert!(simulation.body_items().map(|(body_key, _)| body_key).eq(keys.iter().copied()));
+        }
+    }
+
+    // difference between the accelerations calculated with Barnes-Hut and exact forces, relative to the
+    // exact ones. single bodies can be far off if the forces on them almost cancel, so errors are summed
+    fn barnes_hut_error(threshold: SimFloat) -> SimFloat {
+        let bodies = gaussian_blob(300, MassDistribution::Equal, 2);
+        let mut barnes_hut = Simulation::new(bodies.clone().into_iter(), threshold);
+        let mut exact = Simulation::new(bodies.into_iter(), threshold).with_force_mode(ForceMode::Exact);
+
+        barnes_hut.rebuild_tree();
+        exact.rebuild_tree();
+
+        let (error, magnitude) = barnes_hut
+            .accelerations(|_| true)
+            .into_iter()
+            .zip(exact.accelerations(|_| true))
+            .fold((0.0, 0.0), |(error, magnitude), ((_, approximated), (_, exact))| {
+                (error + (approximated - exact).magnitude2(), magnitude + exact.magnitude2())
+            });
+
+        (error / magnitude).sqrt()
+    }
+
+    #[test]
+    fn barnes_hut_matches_exact_forces() {
+        // without approximating any node, only the summation order differs
+        assert!(barnes_hut_error(0.0) < 1e-5);
+        assert!(barnes_hut_error(0.5) < 0.01);
+        assert!(barnes_hut_error(1.0) < 0.05);
     }
 }
@@ -0,0 +1,96 @@
+use std::fs::File;
+use std::io::{
+    self,
+    BufWriter,
+    Write,
+};
+use std::path::PathBuf;
+
+use super::Simulation;
+use crate::utility::index_map::MapKey;
+
+// the step and time columns tell the steps apart when they are all appended to a single file
+pub const CSV_HEADER: &str = "step,time,body,x,y,vx,vy,mass";
+
+impl Simulation {
+    /// Writes one CSV row per body with the columns of [CSV_HEADER], without the header itself.
+    pub fn write_csv_row(
+        &self,
+        writer: &mut impl Write,
+    ) -> io::Result<()> {
+        for (body_key, body) in self.body_items() {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{}",
+                self.step_count(),
+                self.sim_time(),
+                body_key.to_index(),
+                body.position.x,
+                body.position.y,
+                body.velocity.x,
+                body.velocity.y,
+                body.mass
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Where [CsvExporter] writes the bodies of every step.
+#[derive(Debug, Clone)]
+pub enum CsvTarget {
+    /// Appends all steps to a single file with one header.
+    File(PathBuf),
+    /// Writes a separate file for every step into the directory, named after the step.
+    Directory(PathBuf),
+}
+
+/// Streams the state of a simulation to CSV after every step, so no history is kept in memory.
+pub struct CsvExporter {
+    output: CsvOutput,
+}
+
+enum CsvOutput {
+    File(BufWriter<File>),
+    Directory(PathBuf),
+}
+
+impl CsvExporter {
+    pub fn new(target: CsvTarget) -> io::Result<Self> {
+        let output = match target {
+            CsvTarget::File(path) => {
+                let mut writer = BufWriter::new(File::create(path)?);
+                writeln!(writer, "{}", CSV_HEADER)?;
+                CsvOutput::File(writer)
+            }
+            CsvTarget::Directory(path) => {
+                std::fs::create_dir_all(&path)?;
+                CsvOutput::Directory(path)
+            }
+        };
+
+        Ok(Self { output })
+    }
+
+    pub fn write_step(
+        &mut self,
+        simulation: &Simulation,
+    ) -> io::Result<()> {
+        match &mut self.output {
+            CsvOutput::File(writer) => {
+                simulation.write_csv_row(writer)?;
+                writer.flush()?;
+            }
+            CsvOutput::Directory(path) => {
+                let file = File::create(path.join(format!("step-{:06}.csv", simulation.step_count())))?;
+                let mut writer = BufWriter::new(file);
+                writeln!(writer, "{}", CSV_HEADER)?;
+                simulation.write_csv_row(&mut writer)?;
+                writer.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+}
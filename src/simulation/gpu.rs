@@ -0,0 +1,269 @@
+use std::sync::mpsc;
+
+use bytemuck::{
+    Pod,
+    Zeroable,
+};
+use cgmath::{
+    Point2,
+    Vector2,
+};
+use thiserror::Error;
+use wgpu::util::{
+    BufferInitDescriptor,
+    DeviceExt,
+};
+use wgpu::{
+    BindGroupDescriptor,
+    BindGroupEntry,
+    BindGroupLayout,
+    BufferAsyncError,
+    BufferDescriptor,
+    BufferUsages,
+    ComputePassDescriptor,
+    ComputePipeline,
+    Device,
+    MapMode,
+    PollType,
+    Queue,
+};
+
+use super::{
+    BodyKey,
+    GRAVITATIONAL_CONSTANT,
+    SimFloat,
+    Simulation,
+};
+use crate::pipeline::Pipeline;
+use crate::utility::index_map::MapKey;
+
+// has to match the workgroup size of force_shader.wgsl
+const WORKGROUP_SIZE: u32 = 64;
+
+#[derive(Debug, Error)]
+pub enum GpuForceError {
+    #[error("Couldn't read back accelerations: {0}")]
+    Readback(#[from] BufferAsyncError),
+    #[error("Couldn't wait for the force calculation: {0}")]
+    Poll(#[from] wgpu::PollError),
+}
+
+// the layouts of the structs in force_shader.wgsl
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct ForceParams {
+    theta: f32,
+    softening_squared: f32,
+    gravitational_constant: f32,
+    node_count: u32,
+    body_count: u32,
+    threads_per_row: u32,
+    // uniforms are sized in multiples of 16 bytes
+    _padding: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct GpuNode {
+    position: [f32; 2],
+    mass: f32,
+    width: f32,
+    next: u32,
+    first_element: u32,
+    element_count: u32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct GpuElement {
+    position: [f32; 2],
+    mass: f32,
+    body: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct GpuBody {
+    position: [f32; 2],
+    body: u32,
+    _padding: u32,
+}
+
+/// Calculates Barnes-Hut forces in a compute shader instead of on the CPU, see [Simulation::set_gpu_forces].
+/// The quadtree is still built on the CPU and uploaded every step, and the accelerations are read back
+/// before the bodies are moved.
+#[derive(Debug, Clone)]
+pub struct GpuForces {
+    device: Device,
+    queue: Queue,
+    bind_group_layout: BindGroupLayout,
+    pipeline: ComputePipeline,
+}
+
+impl GpuForces {
+    /// Shares the device of the pipeline. Returns [None] if the device doesn't support compute shaders, like
+    /// with the WebGL2 limits.
+    pub fn new(pipeline: &Pipeline) -> Option<Self> {
+        Some(Self {
+            device: pipeline.device.clone(),
+            queue: pipeline.queue.clone(),
+            bind_group_layout: pipeline.force_bind_group_layout.clone(),
+            pipeline: pipeline.force_pipeline.clone()?,
+        })
+    }
+
+    /// Calculates the accelerations of bodies at the given positions due to the bodies of the simulation,
+    /// approximated with its quadtree like [Simulation::calculate_body_force]. Blocks until the GPU is done.
+    pub fn accelerations(
+        &self,
+        simulation: &Simulation,
+        bodies: &[(BodyKey, Point2<SimFloat>)],
+    ) -> Result<Vec<Vector2<SimFloat>>, GpuForceError> {
+        if bodies.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let flat_quadtree = simulation.flat_quadtree();
+        let mut nodes = flat_quadtree
+            .nodes()
+            .iter()
+            .map(|node| GpuNode {
+                position: node.data.position().into(),
+                mass: node.data.mass(),
+                width: node.width,
+                next: node.next,
+                first_element: node.first_element,
+                element_count: node.element_count,
+                _padding: 0,
+            })
+            .collect::<Vec<_>>();
+        let mut elements = flat_quadtree
+            .elements()
+            .iter()
+            .map(|element| GpuElement {
+                position: element.position.into(),
+                mass: element.mass,
+                body: element.body_key.to_index() as u32,
+            })
+            .collect::<Vec<_>>();
+
+        let workgroups = (bodies.len() as u32).div_ceil(WORKGROUP_SIZE);
+        let workgroups_per_row = workgroups.min(self.device.limits().max_compute_workgroups_per_dimension);
+        let params = ForceParams {
+            theta: simulation.pseudobody_threshold,
+            softening_squared: simulation.softening * simulation.softening,
+            gravitational_constant: GRAVITATIONAL_CONSTANT,
+            node_count: nodes.len() as u32,
+            body_count: bodies.len() as u32,
+            threads_per_row: workgroups_per_row * WORKGROUP_SIZE,
+            _padding: [0; 2],
+        };
+
+        // bindings can't be empty, the counts in the parameters keep the shader from reading the padding
+        if nodes.is_empty() {
+            nodes.push(GpuNode::zeroed());
+        }
+        if elements.is_empty() {
+            elements.push(GpuElement::zeroed());
+        }
+
+        let bodies = bodies
+            .iter()
+            .map(|(body_key, position)| GpuBody {
+                position: (*position).into(),
+                body: body_key.to_index() as u32,
+                _padding: 0,
+            })
+            .collect::<Vec<_>>();
+
+        let storage_buffer = |label, contents| {
+            self.device.create_buffer_init(&BufferInitDescriptor {
+                label: Some(label),
+                contents,
+                usage: BufferUsages::STORAGE,
+            })
+        };
+        let params_buffer = self.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Force Params Buffer"),
+            contents: bytemuck::bytes_of(&params),
+            usage: BufferUsages::UNIFORM,
+        });
+        let node_buffer = storage_buffer("Force Node Buffer", bytemuck::cast_slice(&nodes));
+        let element_buffer = storage_buffer("Force Element Buffer", bytemuck::cast_slice(&elements));
+        let body_buffer = storage_buffer("Force Body Buffer", bytemuck::cast_slice(&bodies));
+
+        let size = (bodies.len() * size_of::<[f32; 2]>()) as u64;
+        let acceleration_buffer = self.device.create_buffer(&BufferDescriptor {
+            label: Some("Force Acceleration Buffer"),
+            size,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = self.device.create_buffer(&BufferDescriptor {
+            label: Some("Force Readback Buffer"),
+            size,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = self.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Force Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: node_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: element_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: body_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: acceleration_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("Force Pass"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(workgroups_per_row, workgroups.div_ceil(workgroups_per_row), 1);
+        }
+        encoder.copy_buffer_to_buffer(&acceleration_buffer, 0, &readback_buffer, 0, size);
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let (sender, receiver) = mpsc::channel();
+        let slice = readback_buffer.slice(..);
+        slice.map_async(MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+
+        self.device.poll(PollType::Wait)?;
+        // the callback has run once polling returns
+        receiver.recv().unwrap_or(Err(BufferAsyncError))?;
+
+        let accelerations = bytemuck::cast_slice::<u8, [f32; 2]>(&slice.get_mapped_range())
+            .iter()
+            .map(|&acceleration| acceleration.into())
+            .collect();
+        readback_buffer.unmap();
+
+        Ok(accelerations)
+    }
+}
@@ -0,0 +1,77 @@
+use std::fmt::Debug;
+
+use super::{
+    GRAVITATIONAL_CONSTANT,
+    SimFloat,
+};
+
+/// The shape of the gravitational force, see [Simulation::set_force_kernel](super::Simulation::set_force_kernel).
+/// Kernels are shared by all threads calculating forces.
+pub trait ForceKernel: Debug + Send + Sync {
+    /// The magnitude of the acceleration towards a mass at the given distance, which is greater than 0.
+    fn acceleration(
+        &self,
+        distance: SimFloat,
+        mass: SimFloat,
+    ) -> SimFloat;
+}
+
+/// The unsoftened force, which diverges for close encounters.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Newtonian;
+
+impl ForceKernel for Newtonian {
+    fn acceleration(
+        &self,
+        distance: SimFloat,
+        mass: SimFloat,
+    ) -> SimFloat {
+        GRAVITATIONAL_CONSTANT * mass / (distance * distance)
+    }
+}
+
+/// Replaces distances with sqrt(r^2 + softening^2), like the softening of [Simulation](super::Simulation)
+/// without a kernel. The force is weakened at all distances, noticeably up to a few softening lengths.
+#[derive(Debug, Clone, Copy)]
+pub struct Plummer {
+    pub softening: SimFloat,
+}
+
+impl ForceKernel for Plummer {
+    fn acceleration(
+        &self,
+        distance: SimFloat,
+        mass: SimFloat,
+    ) -> SimFloat {
+        let distance_squared = distance * distance + self.softening * self.softening;
+        GRAVITATIONAL_CONSTANT * mass * distance / (distance_squared * distance_squared.sqrt())
+    }
+}
+
+/// The force between two spheres whose mass is spread with the cubic spline kernel of SPH, which is exactly
+/// Newtonian beyond the radius `softening` and falls to 0 smoothly within it.
+#[derive(Debug, Clone, Copy)]
+pub struct Spline {
+    pub softening: SimFloat,
+}
+
+impl ForceKernel for Spline {
+    fn acceleration(
+        &self,
+        distance: SimFloat,
+        mass: SimFloat,
+    ) -> SimFloat {
+        let u = distance / self.softening;
+        if u >= 1.0 {
+            return GRAVITATIONAL_CONSTANT * mass / (distance * distance);
+        }
+
+        // the polynomials of Springel et al. 2001, which meet at u = 0.5 and match the newtonian force at 1
+        let factor = if u < 0.5 {
+            32.0 / 3.0 + u * u * (32.0 * u - 38.4)
+        } else {
+            64.0 / 3.0 - 48.0 * u + 38.4 * u * u - 32.0 / 3.0 * u * u * u - 1.0 / (15.0 * u * u * u)
+        };
+        GRAVITATIONAL_CONSTANT * mass * distance * factor / self.softening.powi(3)
+    }
+}
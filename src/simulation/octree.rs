@@ -0,0 +1,461 @@
+// The three-dimensional counterpart of the quadtree, for simulating bodies in space instead of on a plane.
+// It is built by inserting elements one by one, the bulk build of the quadtree has no equivalent yet.
+
+use std::fmt::Debug;
+
+use cgmath::Point3;
+use smallvec::{
+    SmallVec,
+    smallvec,
+};
+use thiserror::Error;
+
+use crate::new_map_key_32;
+use crate::utility::index_map::MapKey;
+use crate::utility::index_map::PrimaryMap;
+
+use super::SimFloat;
+use super::quadtree::{
+    ContinueTraverse,
+    QuadtreeStats,
+};
+
+const MAX_DEPTH: u32 = 64;
+
+// elements closer than this to a node's center (on all axes) are not split any further
+const DEFAULT_CENTER_EPSILON: SimFloat = 1e-6;
+
+#[derive(Debug, Error)]
+pub enum OctreeError {
+    #[error("Can't insert element with position {position:?} into octree with extent {extent}.")]
+    OutOfBounds {
+        position: Point3<SimFloat>,
+        extent: SimFloat,
+    },
+}
+
+pub trait Positioned3 {
+    fn position(&self) -> Point3<SimFloat>;
+}
+
+// like the quadrants, named after the directions on the x/y plane, with up and down along z
+#[repr(usize)]
+#[derive(Eq, PartialEq, Clone, Copy)]
+enum Octant {
+    NeUp = 0b000,
+    NwUp = 0b001,
+    SeUp = 0b010,
+    SwUp = 0b011,
+    NeDown = 0b100,
+    NwDown = 0b101,
+    SeDown = 0b110,
+    SwDown = 0b111,
+}
+
+impl TryFrom<u32> for Octant {
+    type Error = ();
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0b000 => Self::NeUp,
+            0b001 => Self::NwUp,
+            0b010 => Self::SeUp,
+            0b011 => Self::SwUp,
+            0b100 => Self::NeDown,
+            0b101 => Self::NwDown,
+            0b110 => Self::SeDown,
+            0b111 => Self::SwDown,
+            _ => Err(())?,
+        })
+    }
+}
+
+impl Octant {
+    fn from_comparison(
+        node_position: Point3<SimFloat>,
+        element_position: Point3<SimFloat>,
+    ) -> Self {
+        let cmp_x = u32::from(element_position.x < node_position.x);
+        let cmp_y = u32::from(element_position.y < node_position.y);
+        let cmp_z = u32::from(element_position.z < node_position.z);
+
+        Self::try_from(cmp_x | (cmp_y << 1) | (cmp_z << 2)).unwrap()
+    }
+
+    fn apply_offset(
+        &self,
+        position: Point3<SimFloat>,
+        extent: SimFloat,
+    ) -> Point3<SimFloat> {
+        let half_extent = 0.5 * extent;
+        // a set bit means the octant lies on the negative side of that axis
+        let offset = |bit: usize| if *self as usize & bit != 0 { -half_extent } else { half_extent };
+
+        Point3::new(position.x + offset(0b001), position.y + offset(0b010), position.z + offset(0b100))
+    }
+}
+
+new_map_key_32! { pub struct NodeKey; "NODE"; }
+new_map_key_32! { pub struct ElementKey; "ELEM"; }
+
+// the elements stored in a leaf, usually at most the leaf capacity
+pub type LeafElements = SmallVec<[ElementKey; 4]>;
+
+#[derive(Debug, Clone)]
+pub enum OctreeChild {
+    Node(NodeKey),
+    Elements(LeafElements),
+}
+
+#[derive(Debug, Clone)]
+pub struct OctreeNode<U>
+where
+    U: Default + Debug + Copy + Clone,
+{
+    pub child_key: OctreeChild,
+    pub position: Point3<SimFloat>,
+    pub extent: SimFloat,
+    pub data: U,
+}
+
+#[derive(Debug)]
+pub struct Octree<T, U>
+where
+    T: Positioned3 + Debug,
+    U: Default + Debug + Copy + Clone,
+{
+    // the center and half side length of the root cube
+    center: Point3<SimFloat>,
+    extent: SimFloat,
+    nodes: PrimaryMap<NodeKey, Option<OctreeNode<U>>>,
+    elements: PrimaryMap<ElementKey, T>,
+    // number of elements a leaf may hold before it is split
+    leaf_capacity: usize,
+    center_epsilon: SimFloat,
+}
+
+impl<T, U> Octree<T, U>
+where
+    T: Positioned3 + Debug,
+    U: Default + Debug + Copy + Clone,
+{
+    pub fn new(extent: SimFloat) -> Self {
+        Self::with_leaf_capacity(extent, 1)
+    }
+
+    /// Creates a tree whose leaves hold up to `leaf_capacity` elements before being split.
+    pub fn with_leaf_capacity(
+        extent: SimFloat,
+        leaf_capacity: usize,
+    ) -> Self {
+        assert!(leaf_capacity > 0, "Leaves must be able to hold at least one element");
+
+        let mut slf = Self {
+            center: Point3::new(0.0, 0.0, 0.0),
+            extent,
+            nodes: Default::default(),
+            elements: Default::default(),
+            leaf_capacity,
+            center_epsilon: DEFAULT_CENTER_EPSILON,
+        };
+
+        slf.nodes.insert(None);
+        slf
+    }
+
+    /// Removes all nodes and elements, keeping the storage.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.elements.clear();
+
+        self.nodes.insert(None);
+    }
+
+    /// Clears the tree and changes the region it covers to the cube around `center` with half side length
+    /// `extent`.
+    pub fn reset(
+        &mut self,
+        center: Point3<SimFloat>,
+        extent: SimFloat,
+    ) {
+        self.clear();
+        self.center = center;
+        self.extent = extent;
+    }
+
+    pub fn center(&self) -> Point3<SimFloat> {
+        self.center
+    }
+
+    pub fn extent(&self) -> SimFloat {
+        self.extent
+    }
+
+    pub fn leaf_capacity(&self) -> usize {
+        self.leaf_capacity
+    }
+
+    /// Sets the distance to a node's center below which elements are considered inseparable, like
+    /// [Quadtree::set_center_epsilon](super::quadtree::Quadtree::set_center_epsilon).
+    pub fn set_center_epsilon(
+        &mut self,
+        center_epsilon: SimFloat,
+    ) {
+        self.center_epsilon = center_epsilon;
+    }
+
+    fn is_near_center(
+        &self,
+        center: Point3<SimFloat>,
+        element_key: ElementKey,
+    ) -> bool {
+        let offset = self.elements[element_key].position() - center;
+        offset.x.abs() <= self.center_epsilon
+            && offset.y.abs() <= self.center_epsilon
+            && offset.z.abs() <= self.center_epsilon
+    }
+
+    pub fn insert(
+        &mut self,
+        element: T,
+    ) -> Result<(), OctreeError> {
+        let offset = element.position() - self.center;
+        if offset.x.abs() > self.extent || offset.y.abs() > self.extent || offset.z.abs() > self.extent {
+            return Err(OctreeError::OutOfBounds {
+                position: element.position(),
+                extent: self.extent,
+            });
+        }
+
+        let element_key = self.elements.insert(element);
+
+        // find existing leaf octant the element belongs to
+        let mut leaf_node_key = self.nodes.keys().next().expect("A root must exist");
+        let mut position = self.center;
+        let mut extent = self.extent;
+        let mut depth = 0;
+
+        while let Some(OctreeNode { child_key: OctreeChild::Node(children), .. }) = self.nodes[leaf_node_key] {
+            let octant = Octant::from_comparison(position, self.elements[element_key].position());
+            leaf_node_key = self.nodes.key_at(children.to_index() + octant as usize).unwrap();
+
+            position = octant.apply_offset(position, extent);
+            extent *= 0.5;
+            depth += 1;
+        }
+
+        match &mut self.nodes[leaf_node_key] {
+            None => {
+                self.nodes[leaf_node_key] = Some(OctreeNode {
+                    child_key: OctreeChild::Elements(smallvec![element_key]),
+                    position,
+                    extent,
+                    data: U::default(),
+                });
+            }
+            Some(existing) => {
+                let OctreeChild::Elements(elements) = &mut existing.child_key else {
+                    panic!("We checked for this above");
+                };
+
+                elements.push(element_key);
+                if elements.len() > self.leaf_capacity {
+                    self.split_leaf(leaf_node_key, depth);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // distributes the elements of an overflowing leaf into eight new children, recursing into children
+    // which overflow themselves
+    fn split_leaf(
+        &mut self,
+        leaf_node_key: NodeKey,
+        depth: u32,
+    ) {
+        let leaf = self.nodes[leaf_node_key].as_ref().expect("Only existing leaves can be split");
+        let OctreeChild::Elements(elements) = &leaf.child_key else {
+            panic!("Only leaves can be split");
+        };
+
+        let position = leaf.position;
+        let extent = leaf.extent;
+
+        if depth >= MAX_DEPTH || elements.iter().all(|&element_key| self.is_near_center(position, element_key)) {
+            return;
+        }
+
+        let children_key = self.nodes.next_key();
+        for _ in 0..8 {
+            self.nodes.insert(None);
+        }
+
+        let OctreeChild::Elements(elements) = std::mem::replace(
+            &mut self.nodes[leaf_node_key].as_mut().unwrap().child_key,
+            OctreeChild::Node(children_key),
+        ) else {
+            panic!("We checked for this above");
+        };
+
+        for element_key in elements {
+            let octant = Octant::from_comparison(position, self.elements[element_key].position());
+            let child_key = self.nodes.key_at(children_key.to_index() + octant as usize).unwrap();
+
+            match &mut self.nodes[child_key] {
+                None => {
+                    self.nodes[child_key] = Some(OctreeNode {
+                        child_key: OctreeChild::Elements(smallvec![element_key]),
+                        position: octant.apply_offset(position, extent),
+                        extent: 0.5 * extent,
+                        data: Default::default(),
+                    });
+                }
+                Some(child) => {
+                    let OctreeChild::Elements(child_elements) = &mut child.child_key else {
+                        panic!("New children are leaves");
+                    };
+                    child_elements.push(element_key);
+                }
+            }
+        }
+
+        for child_index in 0..8 {
+            let child_key = self.nodes.key_at(children_key.to_index() + child_index).unwrap();
+            let overflowing = matches!(
+                &self.nodes[child_key],
+                Some(OctreeNode { child_key: OctreeChild::Elements(child_elements), .. })
+                    if child_elements.len() > self.leaf_capacity
+            );
+
+            if overflowing {
+                self.split_leaf(child_key, depth + 1);
+            }
+        }
+    }
+
+    pub fn nodes(&self) -> &PrimaryMap<NodeKey, Option<OctreeNode<U>>> {
+        &self.nodes
+    }
+
+    pub fn element(
+        &self,
+        element_key: ElementKey,
+    ) -> &T {
+        &self.elements[element_key]
+    }
+
+    /// Returns the element if the key belongs to this tree and it hasn't been cleared since.
+    pub fn get(
+        &self,
+        element_key: ElementKey,
+    ) -> Option<&T> {
+        self.elements.get(element_key)
+    }
+
+    /// Iterates over all elements in the order they were inserted.
+    pub fn elements(&self) -> impl ExactSizeIterator<Item = (ElementKey, &T)> {
+        self.elements.items()
+    }
+
+    /// Number of elements in the tree.
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    /// Visits all non-empty nodes depth-first, starting at the root with depth 0. The children of a node
+    /// are skipped if the visitor returns [ContinueTraverse::Stop] for it.
+    pub fn traverse<F>(
+        &self,
+        mut visitor: F,
+    ) where
+        F: FnMut(&OctreeNode<U>, u32) -> ContinueTraverse,
+    {
+        let root_key = self.nodes.keys().next().expect("A root must exist");
+        self.traverse_node(root_key, 0, &mut visitor);
+    }
+
+    fn traverse_node<F>(
+        &self,
+        node_key: NodeKey,
+        depth: u32,
+        visitor: &mut F,
+    ) where
+        F: FnMut(&OctreeNode<U>, u32) -> ContinueTraverse,
+    {
+        let Some(node) = &self.nodes[node_key] else {
+            return;
+        };
+
+        if let ContinueTraverse::Stop = visitor(node, depth) {
+            return;
+        }
+
+        if let OctreeChild::Node(children_key) = node.child_key {
+            for child_index in 0..8 {
+                let child_key = self.nodes.key_at(children_key.to_index() + child_index).unwrap();
+                self.traverse_node(child_key, depth + 1, visitor);
+            }
+        }
+    }
+
+    /// Walks the tree to find how deep and balanced it is, see [Quadtree::stats](super::quadtree::Quadtree::stats).
+    pub fn stats(&self) -> QuadtreeStats {
+        let mut stats = QuadtreeStats::default();
+        let mut leaf_depth_sum = 0;
+
+        self.traverse(|node, depth| {
+            stats.node_count += 1;
+            stats.max_depth = stats.max_depth.max(depth);
+
+            if let OctreeChild::Elements(elements) = &node.child_key {
+                stats.leaf_count += 1;
+                stats.element_count += elements.len();
+                leaf_depth_sum += u64::from(depth);
+            }
+
+            ContinueTraverse::Continue
+        });
+
+        if stats.leaf_count > 0 {
+            stats.average_leaf_depth = leaf_depth_sum as f32 / stats.leaf_count as f32;
+        }
+
+        stats
+    }
+
+    /// Calculates the data of every node bottom-up, like
+    /// [Quadtree::aggregate](super::quadtree::Quadtree::aggregate).
+    pub fn aggregate<F, G>(
+        &mut self,
+        mut fold_element: F,
+        mut fold_child: G,
+    ) where
+        F: FnMut(U, &T) -> U,
+        G: FnMut(U, U) -> U,
+    {
+        // children are always inserted after their parent, so iterating in reverse visits them first
+        for index in (0..self.nodes.len()).rev() {
+            let node_key = self.nodes.key_at(index).unwrap();
+            let data = match &self.nodes[node_key] {
+                None => continue,
+                Some(OctreeNode { child_key: OctreeChild::Elements(elements), .. }) => elements
+                    .iter()
+                    .fold(U::default(), |data, &element_key| fold_element(data, &self.elements[element_key])),
+                Some(OctreeNode { child_key: OctreeChild::Node(children_key), .. }) => (0..8)
+                    .filter_map(|child_index| {
+                        let child_key = self.nodes.key_at(children_key.to_index() + child_index).unwrap();
+                        self.nodes[child_key].as_ref().map(|child| child.data)
+                    })
+                    .fold(U::default(), &mut fold_child),
+            };
+
+            self.nodes[node_key].as_mut().unwrap().data = data;
+        }
+    }
+}
@@ -0,0 +1,332 @@
+// Initial conditions for common test setups. All presets are deterministic for a given seed.
+
+use std::f32::consts::TAU;
+use std::str::FromStr;
+
+use cgmath::{
+    InnerSpace,
+    Point2,
+    Vector2,
+};
+use rand::rngs::StdRng;
+use rand::{
+    Rng,
+    SeedableRng,
+};
+use rand_distr::{
+    Distribution,
+    Normal,
+};
+use thiserror::Error;
+
+use super::{
+    Body,
+    GRAVITATIONAL_CONSTANT,
+    SimFloat,
+};
+
+// scale radius of the plummer sphere and radius of the disk when generated through [Preset]
+const PRESET_RADIUS: SimFloat = 0.5;
+
+const BODY_MASS: SimFloat = 1.0;
+const BODY_RADIUS: SimFloat = 0.005;
+
+// mass of the star relative to the total mass of the bodies orbiting it when generated through [Preset], so
+// their attraction among each other barely disturbs their orbits
+const PRESET_STAR_MASS_RATIO: SimFloat = 100.0;
+
+// bodies orbiting a star keep this fraction of the disk radius away from it, where orbits get too fast to
+// integrate with the usual timesteps
+const STAR_INNER_RADIUS: SimFloat = 0.1;
+const STAR_RADIUS: SimFloat = 4.0 * BODY_RADIUS;
+
+// the plummer distribution has infinite extent, so radii are cut off where this fraction of the total
+// mass is enclosed
+const PLUMMER_MASS_CUTOFF: SimFloat = 0.99;
+
+/// How the masses of generated bodies are distributed. The radii of the bodies grow with their mass.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MassDistribution {
+    /// Every body has a mass of 1.
+    #[default]
+    Equal,
+    /// Masses between `min` and `max` with a probability density proportional to `m^-exponent`, like an
+    /// initial mass function. An exponent of 2.35 gives the Salpeter mass function.
+    PowerLaw { min: SimFloat, max: SimFloat, exponent: SimFloat },
+}
+
+impl MassDistribution {
+    pub fn sample(
+        &self,
+        rng: &mut impl Rng,
+    ) -> SimFloat {
+        match *self {
+            MassDistribution::Equal => BODY_MASS,
+            MassDistribution::PowerLaw { min, max, exponent } => {
+                // inverts the cumulative distribution, in double precision since the powers get extreme
+                let (min, max, exponent) = (f64::from(min), f64::from(max), f64::from(exponent));
+                let u = rng.random::<f64>();
+                let mass = if exponent == 1.0 {
+                    min * (max / min).powf(u)
+                } else {
+                    let power = 1.0 - exponent;
+                    (min.powf(power) + u * (max.powf(power) - min.powf(power))).powf(1.0 / power)
+                };
+                mass as SimFloat
+            }
+        }
+    }
+
+    /// The expected mass of a body, used to estimate the total mass when choosing velocities.
+    pub fn mean(&self) -> SimFloat {
+        match *self {
+            MassDistribution::Equal => BODY_MASS,
+            MassDistribution::PowerLaw { min, max, exponent } => {
+                let (min, max, exponent) = (f64::from(min), f64::from(max), f64::from(exponent));
+                // integral of m^(1 - exponent) divided by that of m^-exponent, both of which turn into
+                // logarithms at one of the exponents
+                let integral = |power: f64| {
+                    if power == 0.0 {
+                        (max / min).ln()
+                    } else {
+                        (max.powf(power) - min.powf(power)) / power
+                    }
+                };
+                (integral(2.0 - exponent) / integral(1.0 - exponent)) as SimFloat
+            }
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Invalid mass distribution {0:?}, expected equal or power-law:<min>:<max>:<exponent>.")]
+pub struct InvalidMassDistributionError(String);
+
+impl FromStr for MassDistribution {
+    type Err = InvalidMassDistributionError;
+
+    // parses e.g. "equal" or "power-law:0.1:10:2.35"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "equal" {
+            return Ok(MassDistribution::Equal);
+        }
+
+        let error = || InvalidMassDistributionError(s.to_owned());
+        let parameters = s.strip_prefix("power-law:").ok_or_else(error)?;
+        let parameters = parameters
+            .split(':')
+            .map(|parameter| parameter.parse::<SimFloat>().map_err(|_| error()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match parameters[..] {
+            [min, max, exponent] if min > 0.0 && max >= min && exponent.is_finite() => {
+                Ok(MassDistribution::PowerLaw { min, max, exponent })
+            }
+            _ => Err(error()),
+        }
+    }
+}
+
+fn random_direction(rng: &mut StdRng) -> Vector2<SimFloat> {
+    let angle = rng.random_range(0.0..TAU);
+    Vector2::new(angle.cos(), angle.sin())
+}
+
+// velocity of a circular orbit around the origin, counterclockwise, at the given position and with the
+// given mass enclosed by the orbit
+fn circular_velocity(
+    position: Point2<SimFloat>,
+    enclosed_mass: SimFloat,
+) -> Vector2<SimFloat> {
+    let offset = position - Point2::new(0.0, 0.0);
+    let distance = offset.magnitude();
+    if distance <= 0.0 {
+        return Vector2::new(0.0, 0.0);
+    }
+
+    let speed = (GRAVITATIONAL_CONSTANT * enclosed_mass / distance).sqrt();
+    Vector2::new(-offset.y, offset.x) / distance * speed
+}
+
+/// Two heavy bodies on a circular orbit around their common center of mass at the origin. The seed
+/// determines the orientation of the orbit.
+pub fn two_body_orbit(seed: u64) -> Vec<Body> {
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let masses = [1e6, 5e5];
+    let separation = 0.5;
+    let total_mass = masses[0] + masses[1];
+
+    // relative speed of a circular orbit, split between the bodies in inverse proportion to their mass
+    let direction = random_direction(&mut rng);
+    let perpendicular = Vector2::new(-direction.y, direction.x);
+    let relative_speed = (GRAVITATIONAL_CONSTANT * total_mass / separation).sqrt();
+
+    let origin = Point2::new(0.0, 0.0);
+    vec![
+        Body::at(origin + direction * separation * masses[1] / total_mass)
+            .velocity(perpendicular * relative_speed * masses[1] / total_mass)
+            .mass(masses[0])
+            .radius(2.0 * BODY_RADIUS)
+            .build(),
+        Body::at(origin - direction * separation * masses[0] / total_mass)
+            .velocity(-perpendicular * relative_speed * masses[0] / total_mass)
+            .mass(masses[1])
+            .radius(2.0 * BODY_RADIUS)
+            .build(),
+    ]
+}
+
+/// `n` bodies following the projected density of a Plummer sphere with scale radius `radius`. Velocities
+/// are random with the local velocity dispersion, so the sphere is supported by its random motion and
+/// doesn't rotate.
+pub fn plummer_sphere(
+    n: usize,
+    radius: SimFloat,
+    masses: MassDistribution,
+    seed: u64,
+) -> Vec<Body> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let total_mass = n as SimFloat * masses.mean();
+
+    (0..n)
+        .map(|_| {
+            // the projected mass within r is M * r^2 / (r^2 + a^2), which can be inverted directly
+            let fraction = rng.random_range(0.0..PLUMMER_MASS_CUTOFF);
+            let distance = radius * (fraction / (1.0 - fraction)).sqrt();
+            let position = Point2::new(0.0, 0.0) + random_direction(&mut rng) * distance;
+
+            // one-dimensional velocity dispersion of the plummer model
+            let dispersion_squared =
+                GRAVITATIONAL_CONSTANT * total_mass / (6.0 * (distance * distance + radius * radius).sqrt());
+            let velocity_dist = Normal::new(0.0, dispersion_squared.sqrt()).unwrap();
+            let velocity = Vector2::new(velocity_dist.sample(&mut rng), velocity_dist.sample(&mut rng));
+
+            Body::at(position).velocity(velocity).mass(masses.sample(&mut rng)).build()
+        })
+        .collect()
+}
+
+/// `n` bodies distributed uniformly over a disk with the given radius, rotating counterclockwise on
+/// circular orbits around the mass enclosed by them.
+pub fn uniform_disk(
+    n: usize,
+    radius: SimFloat,
+    masses: MassDistribution,
+    seed: u64,
+) -> Vec<Body> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let total_mass = n as SimFloat * masses.mean();
+
+    (0..n)
+        .map(|_| {
+            // the square root makes the area density uniform
+            let distance = radius * rng.random::<SimFloat>().sqrt();
+            let position = Point2::new(0.0, 0.0) + random_direction(&mut rng) * distance;
+
+            let enclosed_mass = total_mass * (distance / radius).powi(2);
+            let velocity = circular_velocity(position, enclosed_mass);
+
+            Body::at(position).velocity(velocity).mass(masses.sample(&mut rng)).build()
+        })
+        .collect()
+}
+
+/// A heavy star at rest at the origin and `n` bodies orbiting it counterclockwise on circular orbits, spread
+/// uniformly over a ring between a tenth of `disk_radius` and `disk_radius`. Only the star's mass is taken
+/// into account for the orbits, so they are close to Keplerian as long as the star is much heavier than the
+/// other bodies combined.
+pub fn central_star(
+    n: usize,
+    star_mass: SimFloat,
+    disk_radius: SimFloat,
+    seed: u64,
+) -> Vec<Body> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    // uniform in area between both radii
+    let inner_radius = STAR_INNER_RADIUS * disk_radius;
+    let squared_distances = inner_radius * inner_radius..=disk_radius * disk_radius;
+
+    let star = Body::at(Point2::new(0.0, 0.0)).mass(star_mass).radius(STAR_RADIUS).build();
+    let bodies = (0..n).map(|_| {
+        let distance = rng.random_range(squared_distances.clone()).sqrt();
+        let position = Point2::new(0.0, 0.0) + random_direction(&mut rng) * distance;
+
+        Body::at(position).velocity(circular_velocity(position, star_mass)).mass(BODY_MASS).build()
+    });
+
+    std::iter::once(star).chain(bodies).collect()
+}
+
+/// `n` bodies normally distributed around the origin with small random velocities.
+pub fn gaussian_blob(
+    n: usize,
+    masses: MassDistribution,
+    seed: u64,
+) -> Vec<Body> {
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let position_dist = Normal::new(0.0, 0.5).unwrap();
+    let velocity_dist = Normal::new(0.0, 0.1).unwrap();
+
+    (0..n)
+        .map(|_| {
+            let position = Point2::new(position_dist.sample(&mut rng), position_dist.sample(&mut rng));
+            let velocity = Vector2::new(velocity_dist.sample(&mut rng), velocity_dist.sample(&mut rng));
+
+            Body::at(position).velocity(velocity).mass(masses.sample(&mut rng)).build()
+        })
+        .collect()
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown preset {0:?}, expected one of blob, two-body, plummer, disk or star.")]
+pub struct UnknownPresetError(String);
+
+/// Selects one of the presets by name, e.g. from the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Preset {
+    #[default]
+    Blob,
+    TwoBody,
+    Plummer,
+    Disk,
+    CentralStar,
+}
+
+impl Preset {
+    /// Generates the bodies of this preset. The two-body orbit ignores `n` and the mass distribution, the
+    /// bodies orbiting the central star always have the same mass.
+    pub fn generate(
+        self,
+        n: usize,
+        masses: MassDistribution,
+        seed: u64,
+    ) -> Vec<Body> {
+        match self {
+            Preset::Blob => gaussian_blob(n, masses, seed),
+            Preset::TwoBody => two_body_orbit(seed),
+            Preset::Plummer => plummer_sphere(n, PRESET_RADIUS, masses, seed),
+            Preset::Disk => uniform_disk(n, PRESET_RADIUS, masses, seed),
+            Preset::CentralStar => {
+                let star_mass = PRESET_STAR_MASS_RATIO * n as SimFloat * BODY_MASS;
+                central_star(n, star_mass, PRESET_RADIUS, seed)
+            }
+        }
+    }
+}
+
+impl FromStr for Preset {
+    type Err = UnknownPresetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blob" => Ok(Preset::Blob),
+            "two-body" => Ok(Preset::TwoBody),
+            "plummer" => Ok(Preset::Plummer),
+            "disk" => Ok(Preset::Disk),
+            "star" => Ok(Preset::CentralStar),
+            _ => Err(UnknownPresetError(s.to_owned())),
+        }
+    }
+}
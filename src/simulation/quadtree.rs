@@ -1,29 +1,48 @@
-use std::any::Any;
 use std::fmt::Debug;
-use std::iter;
-use std::num::{
-    NonZeroU8,
-    NonZeroU32,
-};
+use std::ops::Range;
 
 use cgmath::{
-    Array,
+    InnerSpace,
     Point2,
     Vector2,
 };
+use smallvec::{
+    SmallVec,
+    smallvec,
+};
+use thiserror::Error;
 
-use crate::{new_map_key, new_map_key_16, new_map_key_32};
+use crate::new_map_key_32;
 use crate::utility::index_map::PrimaryMap;
 use crate::utility::index_map::MapKey;
+use crate::utility::index_map::SecondaryMap;
 
 use super::SimFloat;
 
 const MAX_DEPTH: u32 = 64;
 
+// bits per axis of the morton codes used by build_from, i.e. the depth up to which they decide quadrants
+const MORTON_BITS: u32 = 32;
+
+// elements closer than this to a node's center (on both axes) are not split any further
+const DEFAULT_CENTER_EPSILON: SimFloat = 1e-6;
+
+#[derive(Debug, Error)]
+pub enum QuadtreeError {
+    #[error("Can't insert element with position {position:?} into quadtree with extents {extents:?}.")]
+    OutOfBounds {
+        position: Point2<SimFloat>,
+        extents: Vector2<SimFloat>,
+    },
+    #[error("The quadtree has no node at index {0}, the children of a twig are missing.")]
+    MissingNode(usize),
+}
+
 pub trait Positioned {
     fn position(&self) -> Point2<SimFloat>;
 }
 
+/// Returned by traversal visitors to decide whether the children of the visited node are visited.
 #[derive(Debug)]
 pub enum ContinueTraverse {
     Continue,
@@ -74,55 +93,170 @@ impl Quadrant {
         let cmp_x = u32::from(element_position.x < node_position.x);
         let cmp_y = u32::from(element_position.y < node_position.y);
 
-        Self::try_from((cmp_x << 0) | (cmp_y << 1)).unwrap()
+        Self::try_from(cmp_x | (cmp_y << 1)).unwrap()
     }
 
     fn apply_offset(
         &self,
         position: Point2<SimFloat>,
-        extent: SimFloat,
+        extent: Vector2<SimFloat>,
     ) -> Point2<SimFloat> {
         let half_extent = 0.5 * extent;
 
         match self {
-            Quadrant::NW => Point2::new(position.x - half_extent, position.y + half_extent),
-            Quadrant::SW => Point2::new(position.x - half_extent, position.y - half_extent),
-            Quadrant::NE => Point2::new(position.x + half_extent, position.y + half_extent),
-            Quadrant::SE => Point2::new(position.x + half_extent, position.y - half_extent),
+            Quadrant::NW => Point2::new(position.x - half_extent.x, position.y + half_extent.y),
+            Quadrant::SW => Point2::new(position.x - half_extent.x, position.y - half_extent.y),
+            Quadrant::NE => Point2::new(position.x + half_extent.x, position.y + half_extent.y),
+            Quadrant::SE => Point2::new(position.x + half_extent.x, position.y - half_extent.y),
         }
     }
 }
 
 new_map_key_32! { pub struct NodeKey; "NODE"; }
-new_map_key_32! { pub struct ElementKey; "NODE"; }
+new_map_key_32! { pub struct ElementKey; "ELEM"; }
+
+// the elements stored in a leaf, usually at most the leaf capacity
+pub type LeafElements = SmallVec<[ElementKey; 4]>;
 
-#[derive(Debug, Copy, Clone)]
-pub(super) enum QuadtreeChild {
+#[derive(Debug, Clone)]
+pub enum QuadtreeChild {
     Node(NodeKey),
-    Element(ElementKey),
+    Elements(LeafElements),
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub struct QuadtreeNode<U>
 where
     U: Default + Debug + Copy + Clone,
 {
     pub child_key: QuadtreeChild,
     pub position: Point2<SimFloat>,
-    pub extent: SimFloat,
+    // half the width and height of the node, which are only equal if the root is a square
+    pub extent: Vector2<SimFloat>,
     pub data: U,
 }
 
-#[derive(Debug)]
+impl<U> QuadtreeNode<U>
+where
+    U: Default + Debug + Copy + Clone,
+{
+    /// The length of the longer side, which is what the Barnes-Hut criterion compares to the distance.
+    pub fn width(&self) -> SimFloat {
+        2.0 * self.extent.x.max(self.extent.y)
+    }
+
+    pub fn contains(
+        &self,
+        point: Point2<SimFloat>,
+    ) -> bool {
+        let offset = point - self.position;
+        offset.x.abs() <= self.extent.x && offset.y.abs() <= self.extent.y
+    }
+}
+
+/// A node of a [FlatQuadtree]. The nodes are stored in depth-first order, so the first child of a twig
+/// directly follows it and `next` is the index past its subtree.
+#[derive(Debug, Clone, Copy)]
+pub struct FlatNode<U> {
+    pub data: U,
+    // the longer side of the node, see QuadtreeNode::width
+    pub width: SimFloat,
+    pub next: u32,
+    // range of the leaf's elements in FlatQuadtree::elements, empty for twigs
+    pub first_element: u32,
+    pub element_count: u32,
+}
+
+impl<U> FlatNode<U> {
+    pub fn is_leaf(&self) -> bool {
+        self.element_count > 0
+    }
+}
+
+/// The non-empty nodes of a [Quadtree] packed into one array, with copies of the elements in the order of
+/// their leaves, see [Quadtree::flatten_into]. Walking it only reads forward through two arrays instead of
+/// looking up every node and element by key, which makes it faster for walks repeated many times per tree
+/// like the force calculation.
+#[derive(Debug, Clone)]
+pub struct FlatQuadtree<T, U> {
+    nodes: Vec<FlatNode<U>>,
+    elements: Vec<T>,
+}
+
+impl<T, U> FlatQuadtree<T, U> {
+    pub fn nodes(&self) -> &[FlatNode<U>] {
+        &self.nodes
+    }
+
+    pub fn elements(&self) -> &[T] {
+        &self.elements
+    }
+
+    /// The elements of a leaf, or none for a twig.
+    pub fn leaf_elements(
+        &self,
+        node: &FlatNode<U>,
+    ) -> &[T] {
+        let first_element = node.first_element as usize;
+        &self.elements[first_element..first_element + node.element_count as usize]
+    }
+
+    /// Visits the nodes depth-first like [Quadtree::traverse], skipping the children of a node if the
+    /// visitor returns [ContinueTraverse::Stop] for it.
+    pub fn traverse<F>(
+        &self,
+        mut visitor: F,
+    ) where
+        F: FnMut(&FlatNode<U>) -> ContinueTraverse,
+    {
+        let mut index = 0;
+        while let Some(node) = self.nodes.get(index) {
+            index = match visitor(node) {
+                ContinueTraverse::Continue => index + 1,
+                ContinueTraverse::Stop => node.next as usize,
+            };
+        }
+    }
+}
+
+impl<T, U> Default for FlatQuadtree<T, U> {
+    fn default() -> Self {
+        Self {
+            nodes: Vec::new(),
+            elements: Vec::new(),
+        }
+    }
+}
+
+/// Shape of a [Quadtree], see [Quadtree::stats]. Empty nodes aren't counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct QuadtreeStats {
+    pub max_depth: u32,
+    pub average_leaf_depth: f32,
+    pub node_count: usize,
+    pub leaf_count: usize,
+    pub element_count: usize,
+}
+
+#[derive(Debug, Clone)]
 pub struct Quadtree<T, U>
 where
     T: Positioned + Debug,
     U: Default + Debug + Copy + Clone,
 {
-    // the size of the root quadrants
-    extent: SimFloat,
+    // the center and half width and height of the root
+    center: Point2<SimFloat>,
+    extents: Vector2<SimFloat>,
     nodes: PrimaryMap<NodeKey, Option<QuadtreeNode<U>>>,
     elements: PrimaryMap<ElementKey, T>,
+    // the leaf every element is stored in, so moving or removing an element doesn't search for it
+    element_leaves: SecondaryMap<ElementKey, NodeKey>,
+    // number of elements a leaf may hold before it is split
+    leaf_capacity: usize,
+    center_epsilon: SimFloat,
+    // kept between calls to build_from so rebuilding doesn't allocate
+    build_buffer: Vec<(u64, T)>,
+    morton_codes: Vec<u64>,
 }
 
 impl<T, U> Quadtree<T, U>
@@ -131,57 +265,218 @@ where
     U: Default + Debug + Copy + Clone,
 {
     pub fn new(extent: SimFloat) -> Self {
+        Self::with_leaf_capacity(extent, 1)
+    }
+
+    /// Creates a tree whose leaves hold up to `leaf_capacity` elements before being split.
+    pub fn with_leaf_capacity(
+        extent: SimFloat,
+        leaf_capacity: usize,
+    ) -> Self {
+        Self::with_extents(Vector2::new(extent, extent), leaf_capacity)
+    }
+
+    /// Creates a tree covering the rectangle with half width `extents.x` and half height `extents.y` around
+    /// the origin. Every node is split in the middle of both axes, so its children keep its aspect ratio.
+    pub fn with_extents(
+        extents: Vector2<SimFloat>,
+        leaf_capacity: usize,
+    ) -> Self {
+        assert!(leaf_capacity > 0, "Leaves must be able to hold at least one element");
+
         let mut slf = Self {
-            extent,
+            center: Point2::new(0.0, 0.0),
+            extents,
             nodes: Default::default(),
             elements: Default::default(),
+            // every stored element is in a leaf, so the default is never used
+            element_leaves: SecondaryMap::with_default(NodeKey::try_from_index(0).unwrap()),
+            leaf_capacity,
+            center_epsilon: DEFAULT_CENTER_EPSILON,
+            build_buffer: Vec::new(),
+            morton_codes: Vec::new(),
         };
 
         slf.nodes.insert(None);
         slf
     }
 
+    /// Removes all nodes and elements. The storage is kept, so rebuilding a tree of similar size doesn't
+    /// allocate.
     pub fn clear(&mut self) {
-        self.nodes = Default::default();
-        self.elements = Default::default();
+        self.nodes.clear();
+        self.elements.clear();
+        self.element_leaves.clear();
 
         self.nodes.insert(None);
     }
 
+    /// Clears the tree and changes the region it covers to the square around `center` with half side
+    /// length `extent`.
+    pub fn reset(
+        &mut self,
+        center: Point2<SimFloat>,
+        extent: SimFloat,
+    ) {
+        self.reset_with_extents(center, Vector2::new(extent, extent));
+    }
+
+    /// Clears the tree and changes the region it covers to the rectangle around `center` with half width
+    /// `extents.x` and half height `extents.y`.
+    pub fn reset_with_extents(
+        &mut self,
+        center: Point2<SimFloat>,
+        extents: Vector2<SimFloat>,
+    ) {
+        self.clear();
+        self.center = center;
+        self.extents = extents;
+    }
+
+    pub fn center(&self) -> Point2<SimFloat> {
+        self.center
+    }
+
+    /// The larger of the root's half width and height.
     pub fn extent(&self) -> SimFloat {
-        self.extent
+        self.extents.x.max(self.extents.y)
+    }
+
+    pub fn extents(&self) -> Vector2<SimFloat> {
+        self.extents
+    }
+
+    pub fn leaf_capacity(&self) -> usize {
+        self.leaf_capacity
+    }
+
+    /// Sets the distance to a node's center below which elements are considered inseparable. A leaf
+    /// whose elements all lie this close to its center is not split, even if it exceeds its capacity.
+    pub fn set_center_epsilon(
+        &mut self,
+        center_epsilon: SimFloat,
+    ) {
+        self.center_epsilon = center_epsilon;
+    }
+
+    fn is_near_center(
+        &self,
+        center: Point2<SimFloat>,
+        element_key: ElementKey,
+    ) -> bool {
+        let position = self.elements[element_key].position();
+        (position.x - center.x).abs() <= self.center_epsilon && (position.y - center.y).abs() <= self.center_epsilon
     }
 
+    /// Adds an element to the leaf its position lies in, splitting the leaf if it overflows.
     pub fn insert(
         &mut self,
         element: T,
-    ) -> Result<(), String> {
-        if element.position().x.abs() > self.extent || element.position().y.abs() > self.extent {
-            panic!("Can't insert element with position {:?} into self with extent {}", element.position(), self.extent);
-        }
+    ) -> Result<ElementKey, QuadtreeError> {
+        self.check_bounds(element.position())?;
 
-        // insert new element
         let element_key = self.elements.insert(element);
+        if let Err(e) = self.place(element_key) {
+            self.elements.remove(element_key);
+            return Err(e);
+        }
+
+        Ok(element_key)
+    }
+
+    /// Replaces an element and returns the previous one. If the new element lies outside of the leaf the
+    /// previous one was in, it is moved to the leaf it belongs to, and nodes that are left with no more
+    /// elements than a leaf can hold are merged, so the tree looks about the same as if it had been built
+    /// with the new element. This is much cheaper than rebuilding the tree when few elements change their
+    /// leaf. The tree is left unchanged if the new element lies outside of the tree. If the children of a
+    /// twig on the way to the new leaf are missing, the element is removed.
+    pub fn replace(
+        &mut self,
+        element_key: ElementKey,
+        element: T,
+    ) -> Result<T, QuadtreeError> {
+        self.check_bounds(element.position())?;
+
+        let position = element.position();
+        let previous = std::mem::replace(&mut self.elements[element_key], element);
 
+        let leaf_key = self.element_leaves[element_key];
+        let leaf = self.nodes[leaf_key].as_ref().expect("Elements are stored in existing leaves");
+        if !leaf.contains(position) {
+            self.detach(element_key);
+            // an element outside of every leaf would be skipped by all walks, so it is dropped instead
+            if let Err(e) = self.place(element_key) {
+                self.elements.remove(element_key);
+                return Err(e);
+            }
+        }
+
+        Ok(previous)
+    }
+
+    /// Removes an element and merges nodes that are left with no more elements than a leaf can hold. Returns
+    /// [None] if the element has already been removed.
+    pub fn remove(
+        &mut self,
+        element_key: ElementKey,
+    ) -> Option<T> {
+        self.elements.get(element_key)?;
+        self.detach(element_key);
+        self.elements.remove(element_key)
+    }
+
+    fn check_bounds(
+        &self,
+        position: Point2<SimFloat>,
+    ) -> Result<(), QuadtreeError> {
+        if !self.root_contains(position) {
+            return Err(QuadtreeError::OutOfBounds {
+                position,
+                extents: self.extents,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn root_contains(
+        &self,
+        position: Point2<SimFloat>,
+    ) -> bool {
+        let offset = position - self.center;
+        offset.x.abs() <= self.extents.x && offset.y.abs() <= self.extents.y
+    }
+
+    // adds a stored element that isn't in any leaf yet to the leaf its position lies in
+    fn place(
+        &mut self,
+        element_key: ElementKey,
+    ) -> Result<(), QuadtreeError> {
         // find existing leaf quadrant the element belongs to
         let mut leaf_node_key = self.nodes.keys().next().expect("A root must exist");
-        let mut position = Point2::new(0.0, 0.0);
-        let mut extent = self.extent;
+        let mut position = self.center;
+        let mut extent = self.extents;
+        let mut depth = 0;
 
         while let Some(QuadtreeNode { child_key: QuadtreeChild::Node(children), .. }) = self.nodes[leaf_node_key] {
             let quadrant = Quadrant::from_comparison(position, self.elements[element_key].position());
             let child_index = quadrant as usize;
-            leaf_node_key = NodeKey::try_from_index(children.to_index() + child_index).unwrap();
+            leaf_node_key = self
+                .child_key(children, child_index)
+                .ok_or(QuadtreeError::MissingNode(children.to_index() + child_index))?;
 
             position = quadrant.apply_offset(position, extent);
             extent *= 0.5;
+            depth += 1;
         }
 
-        match self.nodes[leaf_node_key] {
+        self.element_leaves.insert(element_key, leaf_node_key);
+
+        match &mut self.nodes[leaf_node_key] {
             None => {
                 // empty leaf => insert directly
                 let new_leaf = QuadtreeNode {
-                    child_key: QuadtreeChild::Element(element_key),
+                    child_key: QuadtreeChild::Elements(smallvec![element_key]),
                     position,
                     extent,
                     data: U::default(),
@@ -189,80 +484,887 @@ where
                 self.nodes[leaf_node_key] = Some(new_leaf);
             }
             Some(existing) => {
-                // non-empty leaf => split until quadrants are different
-                let QuadtreeChild::Element(existing_element_key) = existing.child_key else {
+                // non-empty leaf => add to bucket and split if it overflows
+                let QuadtreeChild::Elements(elements) = &mut existing.child_key else {
                     panic!("We checked for this above");
                 };
 
-                // convert leaf to empty twig
-                let children_index = self.nodes.next_key();
-                for _ in 0..4 {
-                    self.nodes.insert(None);
+                elements.push(element_key);
+                if elements.len() > self.leaf_capacity {
+                    self.split_leaf(leaf_node_key, depth);
                 }
+            }
+        }
 
-                self.nodes[leaf_node_key].as_mut().unwrap().child_key = QuadtreeChild::Node(children_index);
+        Ok(())
+    }
 
-                loop {
-                    let q_0 = Quadrant::from_comparison(position, self.elements[existing_element_key].position());
-                    let q_1 = Quadrant::from_comparison(position, self.elements[element_key].position());
+    // takes an element out of its leaf, which stays in the elements, and merges the nodes above it that
+    // don't need to be split anymore
+    fn detach(
+        &mut self,
+        element_key: ElementKey,
+    ) {
+        let leaf_key = self.element_leaves.remove(element_key).expect("Stored elements are in a leaf");
+        let Some(QuadtreeNode { child_key: QuadtreeChild::Elements(elements), position, .. }) =
+            &mut self.nodes[leaf_key]
+        else {
+            panic!("Elements are stored in leaves");
+        };
 
-                    if q_0 == q_1 {
-                        position = q_0.apply_offset(position, extent);
-                        extent *= 0.5;
+        let leaf_position = *position;
+        let index = elements.iter().position(|&other| other == element_key).expect("The leaf holds the element");
+        elements.swap_remove(index);
+        if elements.is_empty() {
+            self.nodes[leaf_key] = None;
+        }
 
-                        // same quadrants => split further by creating new twig node at the child's position
-                        let QuadtreeChild::Node(children_key) = self.nodes[leaf_node_key].unwrap().child_key else {
-                            panic!("We converted the parent to a twig");
-                        };
+        // the twigs above the leaf are found by descending towards its center, which lies strictly inside of
+        // each of them
+        let mut path: SmallVec<[NodeKey; 32]> = SmallVec::new();
+        let mut node_key = self.nodes.keys().next().expect("A root must exist");
+        while node_key != leaf_key {
+            let Some(QuadtreeNode { child_key: QuadtreeChild::Node(children_key), position, .. }) =
+                &self.nodes[node_key]
+            else {
+                panic!("Only twigs lie above a leaf");
+            };
 
-                        let child_key = NodeKey::try_from_index(children_key.to_index() + q_0 as usize).unwrap();
-                        let new_children_key = self.nodes.next_key();
-                        for _ in 0..4 {
-                            self.nodes.insert(None);
-                        }
+            path.push(node_key);
+            let quadrant = Quadrant::from_comparison(*position, leaf_position);
+            // a missing child ends the path early, which only leaves the twigs below unmerged
+            let Some(child_key) = self.child_key(*children_key, quadrant as usize) else {
+                break;
+            };
+            node_key = child_key;
+        }
 
-                        self.nodes[child_key] = Some(QuadtreeNode {
-                            child_key: QuadtreeChild::Node(new_children_key), position, extent, data: Default::default(),
-                        });
-
-                        leaf_node_key = child_key;
-                    } else {
-                        // different quadrants => insert elements and finish
-                        let position_0 = q_0.apply_offset(position, extent);
-                        let position_1 = q_1.apply_offset(position, extent);
-                        extent *= 0.5;
-
-                        let QuadtreeChild::Node(children_key) = self.nodes[leaf_node_key].unwrap().child_key else {
-                            panic!("We converted the parent to a twig");
-                        };
-
-                        let child_key_0 = NodeKey::try_from_index(children_key.to_index() + q_0 as usize).unwrap();
-                        let child_key_1 = NodeKey::try_from_index(children_key.to_index() + q_1 as usize).unwrap();
-
-                        self.nodes[child_key_0] = Some(QuadtreeNode {
-                            child_key: QuadtreeChild::Element(existing_element_key),
-                            position: position_0,
-                            extent,
-                            data: Default::default(),
-                        });
-
-                        self.nodes[child_key_1] = Some(QuadtreeNode {
-                            child_key: QuadtreeChild::Element(element_key),
-                            position: position_1,
-                            extent,
-                            data: Default::default(),
-                        });
-
-                        break;
-                    }
+        // a twig can only be merged if all of its children could
+        for &twig_key in path.iter().rev() {
+            if !self.merge_children(twig_key) {
+                break;
+            }
+        }
+    }
+
+    // turns a twig whose children are leaves holding no more than a leaf's capacity into a leaf, or into an
+    // empty node if they are all empty. the children's slots are left empty, nodes are never removed
+    fn merge_children(
+        &mut self,
+        twig_key: NodeKey,
+    ) -> bool {
+        let Some(QuadtreeNode { child_key: QuadtreeChild::Node(children_key), .. }) = &self.nodes[twig_key] else {
+            return false;
+        };
+
+        let child_keys = self.child_keys(*children_key).collect::<SmallVec<[NodeKey; 4]>>();
+
+        let mut elements = LeafElements::new();
+        for &child_key in &child_keys {
+            match &self.nodes[child_key] {
+                None => {}
+                Some(QuadtreeNode { child_key: QuadtreeChild::Elements(child_elements), .. }) => {
+                    elements.extend_from_slice(child_elements);
                 }
+                Some(QuadtreeNode { child_key: QuadtreeChild::Node(_), .. }) => return false,
+            }
+
+            if elements.len() > self.leaf_capacity {
+                return false;
             }
         }
 
-        Ok(())
+        for child_key in child_keys {
+            self.nodes[child_key] = None;
+        }
+
+        for &element_key in &elements {
+            self.element_leaves.insert(element_key, twig_key);
+        }
+
+        if elements.is_empty() {
+            self.nodes[twig_key] = None;
+        } else {
+            self.nodes[twig_key].as_mut().unwrap().child_key = QuadtreeChild::Elements(elements);
+        }
+
+        true
+    }
+
+    /// Clears the tree and inserts all elements at once, which is much faster than inserting them one by one.
+    /// The elements are sorted by the Morton code of their position, so the elements of every node are
+    /// next to each other and the tree is built in a single sweep over them. Returns the elements lying
+    /// outside of the tree, which are left out.
+    pub fn build_from<I>(
+        &mut self,
+        elements: I,
+    ) -> Vec<T>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        self.clear();
+
+        let mut out_of_bounds = Vec::new();
+        let mut build_buffer = std::mem::take(&mut self.build_buffer);
+        for element in elements {
+            if !self.root_contains(element.position()) {
+                out_of_bounds.push(element);
+            } else {
+                build_buffer.push((self.morton_code(element.position()), element));
+            }
+        }
+
+        build_buffer.sort_unstable_by_key(|(code, _)| *code);
+
+        // element keys are handed out in order, so the elements of a node have consecutive keys
+        self.morton_codes.clear();
+        for (code, element) in build_buffer.drain(..) {
+            self.morton_codes.push(code);
+            self.elements.insert(element);
+        }
+
+        self.build_buffer = build_buffer;
+
+        let root_key = self.nodes.keys().next().expect("A root must exist");
+        self.build_node(root_key, 0..self.elements.len(), self.center, self.extents, 0);
+
+        out_of_bounds
+    }
+
+    // interleaves the bits of the position's quantized coordinates, starting with the most significant ones.
+    // the bits are inverted to match the quadrant order, so every two bits are the quadrant on one level
+    fn morton_code(
+        &self,
+        position: Point2<SimFloat>,
+    ) -> u64 {
+        // the axes are scaled separately, since the quadrants of a rectangular root are split in half on each
+        let quantize = |value: SimFloat, center: SimFloat, extent: SimFloat| {
+            let scale = (1u64 << MORTON_BITS) as f64 / (2.0 * extent as f64);
+            let quantized = ((value as f64 - center as f64 + extent as f64) * scale).clamp(0.0, u32::MAX as f64);
+            !(quantized as u32)
+        };
+
+        let x = quantize(position.x, self.center.x, self.extents.x);
+        let y = quantize(position.y, self.center.y, self.extents.y);
+        spread_bits(x) | (spread_bits(y) << 1)
+    }
+
+    // turns the empty node into a leaf or twig holding the elements in `range`, which all lie in the node
+    fn build_node(
+        &mut self,
+        node_key: NodeKey,
+        range: Range<usize>,
+        position: Point2<SimFloat>,
+        extent: Vector2<SimFloat>,
+        depth: u32,
+    ) {
+        if range.is_empty() {
+            return;
+        }
+
+        let element_keys = range.clone().map(|index| self.elements.key_at(index).unwrap());
+        let inseparable = depth >= MAX_DEPTH
+            || element_keys.clone().all(|element_key| self.is_near_center(position, element_key));
+
+        // elements beyond the precision of the codes are split the same way as when inserting them
+        if range.len() <= self.leaf_capacity || inseparable || depth >= MORTON_BITS {
+            for element_key in element_keys.clone() {
+                self.element_leaves.insert(element_key, node_key);
+            }
+
+            self.nodes[node_key] = Some(QuadtreeNode {
+                child_key: QuadtreeChild::Elements(element_keys.collect()),
+                position,
+                extent,
+                data: U::default(),
+            });
+
+            if range.len() > self.leaf_capacity && !inseparable {
+                self.split_leaf(node_key, depth);
+            }
+
+            return;
+        }
+
+        let children = self.insert_children();
+        self.nodes[node_key] = Some(QuadtreeNode {
+            child_key: QuadtreeChild::Node(children[0]),
+            position,
+            extent,
+            data: U::default(),
+        });
+
+        // the codes of the range are sorted, so the elements of every quadrant follow each other
+        let shift = 2 * (MORTON_BITS - 1 - depth);
+        let mut start = range.start;
+        for child_index in 0..4 {
+            let end = start
+                + self.morton_codes[start..range.end].partition_point(|code| (code >> shift) & 0b11 <= child_index);
+
+            let quadrant = Quadrant::try_from(child_index as u32).unwrap();
+            let child_position = quadrant.apply_offset(position, extent);
+            self.build_node(children[child_index as usize], start..end, child_position, 0.5 * extent, depth + 1);
+
+            start = end;
+        }
+    }
+
+    // distributes the elements of an overflowing leaf into four new children, recursing into children
+    // which overflow themselves
+    fn split_leaf(
+        &mut self,
+        leaf_node_key: NodeKey,
+        depth: u32,
+    ) {
+        let leaf = self.nodes[leaf_node_key].as_ref().expect("Only existing leaves can be split");
+        let QuadtreeChild::Elements(elements) = &leaf.child_key else {
+            panic!("Only leaves can be split");
+        };
+
+        let position = leaf.position;
+        let extent = leaf.extent;
+
+        // elements that all sit on the center won't be separated by splitting, and we can't go deeper
+        // than MAX_DEPTH => keep the bucket even though it is over capacity
+        if depth >= MAX_DEPTH || elements.iter().all(|&element_key| self.is_near_center(position, element_key)) {
+            return;
+        }
+
+        // convert leaf to empty twig
+        let children = self.insert_children();
+        let QuadtreeChild::Elements(elements) = std::mem::replace(
+            &mut self.nodes[leaf_node_key].as_mut().unwrap().child_key,
+            QuadtreeChild::Node(children[0]),
+        ) else {
+            panic!("We checked for this above");
+        };
+
+        for element_key in elements {
+            let quadrant = Quadrant::from_comparison(position, self.elements[element_key].position());
+            let child_key = children[quadrant as usize];
+            self.element_leaves.insert(element_key, child_key);
+
+            match &mut self.nodes[child_key] {
+                None => {
+                    self.nodes[child_key] = Some(QuadtreeNode {
+                        child_key: QuadtreeChild::Elements(smallvec![element_key]),
+                        position: quadrant.apply_offset(position, extent),
+                        extent: 0.5 * extent,
+                        data: Default::default(),
+                    });
+                }
+                Some(child) => {
+                    let QuadtreeChild::Elements(child_elements) = &mut child.child_key else {
+                        panic!("New children are leaves");
+                    };
+                    child_elements.push(element_key);
+                }
+            }
+        }
+
+        for child_key in children {
+            let overflowing = matches!(
+                &self.nodes[child_key],
+                Some(QuadtreeNode { child_key: QuadtreeChild::Elements(child_elements), .. })
+                    if child_elements.len() > self.leaf_capacity
+            );
+
+            if overflowing {
+                self.split_leaf(child_key, depth + 1);
+            }
+        }
+    }
+
+    // adds four empty nodes for the children of a twig. they follow each other, so the twig only stores the
+    // first key
+    fn insert_children(&mut self) -> [NodeKey; 4] {
+        std::array::from_fn(|_| self.nodes.insert(None))
+    }
+
+    /// The key of a twig's child, where `children_key` is the key stored in its [QuadtreeChild::Node] and
+    /// `child_index` is below 4. Returns [None] if the child doesn't exist, which only happens if the twig
+    /// doesn't belong to this tree.
+    pub fn child_key(
+        &self,
+        children_key: NodeKey,
+        child_index: usize,
+    ) -> Option<NodeKey> {
+        if child_index >= 4 {
+            return None;
+        }
+
+        self.nodes.key_at(children_key.to_index() + child_index)
+    }
+
+    /// The keys of a twig's children that exist, see [Quadtree::child_key].
+    pub fn child_keys(
+        &self,
+        children_key: NodeKey,
+    ) -> impl Iterator<Item = NodeKey> + '_ {
+        (0..4).filter_map(move |child_index| self.child_key(children_key, child_index))
     }
 
     pub fn nodes(&self) -> &PrimaryMap<NodeKey, Option<QuadtreeNode<U>>> {
         &self.nodes
     }
+
+    pub fn element(
+        &self,
+        element_key: ElementKey,
+    ) -> &T {
+        &self.elements[element_key]
+    }
+
+    /// Returns the element if the key belongs to this tree and it hasn't been cleared since.
+    pub fn get(
+        &self,
+        element_key: ElementKey,
+    ) -> Option<&T> {
+        self.elements.get(element_key)
+    }
+
+    /// Iterates over all elements in the order they were stored, which is sorted by position after
+    /// [Quadtree::build_from].
+    pub fn elements(&self) -> impl ExactSizeIterator<Item = (ElementKey, &T)> {
+        self.elements.items()
+    }
+
+    /// Iterates over the center, half the width and height, and the elements of every leaf in no particular
+    /// order. The leaves hold all elements without overlapping, but don't cover the whole region since empty
+    /// quadrants have no node, so an empty tree has no leaves.
+    pub fn leaves(&self) -> impl Iterator<Item = (Point2<SimFloat>, Vector2<SimFloat>, &[ElementKey])> {
+        self.nodes.values().flatten().filter_map(|node| match &node.child_key {
+            QuadtreeChild::Elements(element_keys) => Some((node.position, node.extent, element_keys.as_slice())),
+            QuadtreeChild::Node(_) => None,
+        })
+    }
+
+    /// Number of elements in the tree.
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    /// Visits all non-empty nodes depth-first, starting at the root with depth 0. The children of a node
+    /// are skipped if the visitor returns [ContinueTraverse::Stop] for it.
+    pub fn traverse<F>(
+        &self,
+        mut visitor: F,
+    ) where
+        F: FnMut(&QuadtreeNode<U>, u32) -> ContinueTraverse,
+    {
+        let root_key = self.nodes.keys().next().expect("A root must exist");
+        self.traverse_node(root_key, 0, &mut visitor);
+    }
+
+    fn traverse_node<F>(
+        &self,
+        node_key: NodeKey,
+        depth: u32,
+        visitor: &mut F,
+    ) where
+        F: FnMut(&QuadtreeNode<U>, u32) -> ContinueTraverse,
+    {
+        let Some(node) = &self.nodes[node_key] else {
+            return;
+        };
+
+        if let ContinueTraverse::Stop = visitor(node, depth) {
+            return;
+        }
+
+        if let QuadtreeChild::Node(children_key) = node.child_key {
+            for child_key in self.child_keys(children_key) {
+                self.traverse_node(child_key, depth + 1, visitor);
+            }
+        }
+    }
+
+    /// Packs the tree into `flat`, reusing its allocations. The flat tree is a copy, it doesn't change along
+    /// with this one.
+    pub fn flatten_into(
+        &self,
+        flat: &mut FlatQuadtree<T, U>,
+    ) where
+        T: Clone,
+    {
+        flat.nodes.clear();
+        flat.elements.clear();
+
+        let root_key = self.nodes.keys().next().expect("A root must exist");
+        self.flatten_node(root_key, flat);
+    }
+
+    /// Like [Quadtree::flatten_into], but into a new flat tree.
+    pub fn flatten(&self) -> FlatQuadtree<T, U>
+    where
+        T: Clone,
+    {
+        let mut flat = FlatQuadtree::default();
+        self.flatten_into(&mut flat);
+        flat
+    }
+
+    fn flatten_node(
+        &self,
+        node_key: NodeKey,
+        flat: &mut FlatQuadtree<T, U>,
+    ) where
+        T: Clone,
+    {
+        let Some(node) = &self.nodes[node_key] else {
+            return;
+        };
+
+        let index = flat.nodes.len();
+        flat.nodes.push(FlatNode {
+            data: node.data,
+            width: node.width(),
+            next: 0,
+            first_element: flat.elements.len() as u32,
+            element_count: 0,
+        });
+
+        match &node.child_key {
+            QuadtreeChild::Elements(elements) => {
+                flat.nodes[index].element_count = elements.len() as u32;
+                flat.elements.extend(elements.iter().map(|&element_key| self.elements[element_key].clone()));
+            }
+            QuadtreeChild::Node(children_key) => {
+                for child_key in self.child_keys(*children_key) {
+                    self.flatten_node(child_key, flat);
+                }
+            }
+        }
+
+        flat.nodes[index].next = flat.nodes.len() as u32;
+    }
+
+    /// Returns the element closest to `point`, or `None` if the tree is empty. See [Quadtree::k_nearest].
+    pub fn nearest(
+        &self,
+        point: Point2<SimFloat>,
+    ) -> Option<ElementKey> {
+        self.k_nearest(point, 1).first().copied()
+    }
+
+    /// Returns the `k` elements closest to `point`, the closest first, or all elements if there are fewer.
+    /// Children are searched closest first, and nodes that are farther away than the `k`th closest element
+    /// found so far are skipped.
+    pub fn k_nearest(
+        &self,
+        point: Point2<SimFloat>,
+        k: usize,
+    ) -> Vec<ElementKey> {
+        // sorted by squared distance and never longer than k
+        let mut nearest = Vec::with_capacity(k + 1);
+        if k > 0 {
+            let root_key = self.nodes.keys().next().expect("A root must exist");
+            self.k_nearest_node(root_key, point, k, &mut nearest);
+        }
+
+        nearest.into_iter().map(|(_, element_key)| element_key).collect()
+    }
+
+    fn k_nearest_node(
+        &self,
+        node_key: NodeKey,
+        point: Point2<SimFloat>,
+        k: usize,
+        nearest: &mut Vec<(SimFloat, ElementKey)>,
+    ) {
+        let Some(node) = &self.nodes[node_key] else {
+            return;
+        };
+
+        if nearest.len() == k && node_distance2(node, point) > nearest[k - 1].0 {
+            return;
+        }
+
+        match &node.child_key {
+            QuadtreeChild::Elements(element_keys) => {
+                for &element_key in element_keys {
+                    let distance2 = (self.elements[element_key].position() - point).magnitude2();
+                    if nearest.len() < k || distance2 < nearest[k - 1].0 {
+                        let index = nearest.partition_point(|(other, _)| *other <= distance2);
+                        nearest.insert(index, (distance2, element_key));
+                        nearest.truncate(k);
+                    }
+                }
+            }
+            QuadtreeChild::Node(children_key) => {
+                let mut children: SmallVec<[(SimFloat, NodeKey); 4]> = self
+                    .child_keys(*children_key)
+                    .filter_map(|child_key| {
+                        self.nodes[child_key].as_ref().map(|child| (node_distance2(child, point), child_key))
+                    })
+                    .collect();
+                children.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+
+                for (_, child_key) in children {
+                    self.k_nearest_node(child_key, point, k, nearest);
+                }
+            }
+        }
+    }
+
+    /// Number of elements at most `radius` away from `point`. Nodes farther away than that are skipped.
+    pub fn count_within(
+        &self,
+        point: Point2<SimFloat>,
+        radius: SimFloat,
+    ) -> usize {
+        let mut count = 0;
+        self.for_each_within(point, radius, |_, _| count += 1);
+        count
+    }
+
+    /// Calls `f` with every element at most `radius` away from `point`, like [Quadtree::count_within].
+    pub fn for_each_within<F>(
+        &self,
+        point: Point2<SimFloat>,
+        radius: SimFloat,
+        mut f: F,
+    ) where
+        F: FnMut(ElementKey, &T),
+    {
+        let radius2 = radius * radius;
+
+        self.traverse(|node, _| {
+            if node_distance2(node, point) > radius2 {
+                return ContinueTraverse::Stop;
+            }
+
+            if let QuadtreeChild::Elements(element_keys) = &node.child_key {
+                for &element_key in element_keys {
+                    let element = &self.elements[element_key];
+                    if (element.position() - point).magnitude2() <= radius2 {
+                        f(element_key, element);
+                    }
+                }
+            }
+
+            ContinueTraverse::Continue
+        });
+    }
+
+    /// Walks the tree to find how deep and balanced it is. A large maximum depth compared to the average
+    /// leaf depth means the elements are clustered in a small part of the tree's region.
+    pub fn stats(&self) -> QuadtreeStats {
+        let mut stats = QuadtreeStats::default();
+        let mut leaf_depth_sum = 0;
+
+        self.traverse(|node, depth| {
+            stats.node_count += 1;
+            stats.max_depth = stats.max_depth.max(depth);
+
+            if let QuadtreeChild::Elements(elements) = &node.child_key {
+                stats.leaf_count += 1;
+                stats.element_count += elements.len();
+                leaf_depth_sum += u64::from(depth);
+            }
+
+            ContinueTraverse::Continue
+        });
+
+        if stats.leaf_count > 0 {
+            stats.average_leaf_depth = leaf_depth_sum as f32 / stats.leaf_count as f32;
+        }
+
+        stats
+    }
+
+    /// Calculates the data of every node bottom-up. The data of a leaf is its elements folded with
+    /// `fold_element`, the data of a twig is its children's data folded with `fold_child`. Both folds
+    /// start at `U::default()`.
+    pub fn aggregate<F, G>(
+        &mut self,
+        mut fold_element: F,
+        mut fold_child: G,
+    ) where
+        F: FnMut(U, &T) -> U,
+        G: FnMut(U, U) -> U,
+    {
+        // children are always inserted after their parent, so iterating in reverse visits them first
+        for index in (0..self.nodes.len()).rev() {
+            let Some(node_key) = self.nodes.key_at(index) else {
+                continue;
+            };
+            let data = match &self.nodes[node_key] {
+                None => continue,
+                Some(QuadtreeNode { child_key: QuadtreeChild::Elements(elements), .. }) => elements
+                    .iter()
+                    .fold(U::default(), |data, &element_key| fold_element(data, &self.elements[element_key])),
+                Some(QuadtreeNode { child_key: QuadtreeChild::Node(children_key), .. }) => self
+                    .child_keys(*children_key)
+                    .filter_map(|child_key| self.nodes[child_key].as_ref().map(|child| child.data))
+                    .fold(U::default(), &mut fold_child),
+            };
+
+            self.nodes[node_key].as_mut().unwrap().data = data;
+        }
+    }
+}
+
+// squared distance from a point to the closest point of a node's rectangle, 0 if the point is inside
+fn node_distance2<U>(
+    node: &QuadtreeNode<U>,
+    point: Point2<SimFloat>,
+) -> SimFloat
+where
+    U: Default + Debug + Copy + Clone,
+{
+    let dx = ((point.x - node.position.x).abs() - node.extent.x).max(0.0);
+    let dy = ((point.y - node.position.y).abs() - node.extent.y).max(0.0);
+    dx * dx + dy * dy
+}
+
+// moves the bits of value apart, so a zero bit follows every one of them
+fn spread_bits(value: u32) -> u64 {
+    let mut bits = u64::from(value);
+    bits = (bits | (bits << 16)) & 0x0000_FFFF_0000_FFFF;
+    bits = (bits | (bits << 8)) & 0x00FF_00FF_00FF_00FF;
+    bits = (bits | (bits << 4)) & 0x0F0F_0F0F_0F0F_0F0F;
+    bits = (bits | (bits << 2)) & 0x3333_3333_3333_3333;
+    (bits | (bits << 1)) & 0x5555_5555_5555_5555
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rand::rngs::StdRng;
+    use rand::{
+        Rng,
+        SeedableRng,
+    };
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct Point(Point2<SimFloat>);
+
+    impl Positioned for Point {
+        fn position(&self) -> Point2<SimFloat> {
+            self.0
+        }
+    }
+
+    fn points(coordinates: &[(SimFloat, SimFloat)]) -> impl Iterator<Item = Point> + '_ {
+        coordinates.iter().map(|&(x, y)| Point(Point2::new(x, y)))
+    }
+
+    // every element together with the leaf it is stored in
+    fn leaf_elements(tree: &Quadtree<Point, ()>) -> Vec<(ElementKey, Point2<SimFloat>, Vector2<SimFloat>)> {
+        tree.leaves()
+            .flat_map(|(position, extent, element_keys)| element_keys.iter().map(move |&key| (key, position, extent)))
+            .collect()
+    }
+
+    const KNOWN_POINTS: [(SimFloat, SimFloat); 8] = [
+        (-0.75, -0.75),
+        (0.25, 0.5),
+        (0.5, 0.25),
+        (0.3, 0.3),
+        (0.31, 0.3),
+        (-0.1, 0.9),
+        (0.9, -0.9),
+        (-0.99, 0.01),
+    ];
+
+    fn random_points(
+        n: usize,
+        seed: u64,
+    ) -> Vec<(SimFloat, SimFloat)> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..n).map(|_| (rng.random_range(-1.0..1.0), rng.random_range(-1.0..1.0))).collect()
+    }
+
+    fn inserted(coordinates: &[(SimFloat, SimFloat)]) -> Quadtree<Point, ()> {
+        let mut tree = Quadtree::new(1.0);
+        for point in points(coordinates) {
+            tree.insert(point).unwrap();
+        }
+        tree
+    }
+
+    fn built(coordinates: &[(SimFloat, SimFloat)]) -> Quadtree<Point, ()> {
+        let mut tree = Quadtree::new(1.0);
+        assert!(tree.build_from(points(coordinates)).is_empty());
+        tree
+    }
+
+    // the leaf found by descending into the quadrant the position lies in
+    fn find_leaf(
+        tree: &Quadtree<Point, ()>,
+        position: Point2<SimFloat>,
+    ) -> Option<NodeKey> {
+        let mut node_key = tree.nodes().keys().next().unwrap();
+        while let Some(QuadtreeNode { child_key: QuadtreeChild::Node(children_key), position: center, .. }) =
+            &tree.nodes()[node_key]
+        {
+            node_key = tree.child_key(*children_key, Quadrant::from_comparison(*center, position) as usize)?;
+        }
+        Some(node_key)
+    }
+
+    fn assert_invariants(tree: &Quadtree<Point, ()>) {
+        let mut children_keys = HashSet::new();
+        let mut stored = HashSet::new();
+
+        for (node_key, node) in tree.nodes().items() {
+            let Some(node) = node else {
+                continue;
+            };
+
+            match &node.child_key {
+                QuadtreeChild::Node(children_key) => {
+                    assert!(children_keys.insert(*children_key), "twigs must not share children");
+
+                    // the four children directly follow each other, starting at the stored key
+                    let children = (0..4)
+                        .map(|child_index| {
+                            let child_key = tree.child_key(*children_key, child_index).unwrap();
+                            assert_eq!(child_key.to_index(), children_key.to_index() + child_index);
+                            (child_index, child_key)
+                        })
+                        .filter_map(|(child_index, child_key)| Some((child_index, tree.nodes()[child_key].as_ref()?)))
+                        .collect::<Vec<_>>();
+
+                    for &(child_index, child) in &children {
+                        let quadrant = Quadrant::try_from(child_index as u32).unwrap();
+                        assert_eq!(child.position, quadrant.apply_offset(node.position, node.extent));
+                        assert_eq!(child.extent, 0.5 * node.extent);
+                    }
+
+                    for (i, &(_, a)) in children.iter().enumerate() {
+                        for &(_, b) in &children[i + 1..] {
+                            let offset = a.position - b.position;
+                            assert!(
+                                offset.x.abs() >= a.extent.x + b.extent.x || offset.y.abs() >= a.extent.y + b.extent.y,
+                                "child quadrants must not overlap"
+                            );
+                        }
+                    }
+                }
+                QuadtreeChild::Elements(element_keys) => {
+                    assert!(!element_keys.is_empty());
+                    for &element_key in element_keys {
+                        assert!(node.contains(tree.element(element_key).position()));
+                        assert_eq!(tree.element_leaves[element_key], node_key);
+                        assert!(stored.insert(element_key), "elements must be stored in a single leaf");
+                    }
+                }
+            }
+        }
+
+        assert_eq!(stored.len(), tree.len());
+    }
+
+    #[test]
+    fn insert_keeps_the_invariants() {
+        let tree = inserted(&KNOWN_POINTS);
+        assert_invariants(&tree);
+
+        // all points are apart, so every leaf holds a single element
+        assert!(tree.leaves().all(|(_, _, element_keys)| element_keys.len() == 1));
+        assert_eq!(tree.leaves().count(), KNOWN_POINTS.len());
+    }
+
+    #[test]
+    fn build_from_keeps_the_invariants() {
+        let tree = built(&KNOWN_POINTS);
+        assert_invariants(&tree);
+
+        assert!(tree.leaves().all(|(_, _, element_keys)| element_keys.len() == 1));
+        assert_eq!(tree.leaves().count(), KNOWN_POINTS.len());
+    }
+
+    #[test]
+    fn keys_from_before_a_rebuild_are_not_found() {
+        let mut tree = built(&KNOWN_POINTS);
+        let (old_key, _) = tree.elements().next().unwrap();
+
+        assert!(tree.build_from(points(&KNOWN_POINTS)).is_empty());
+        assert!(tree.get(old_key).is_none());
+        assert_invariants(&tree);
+        assert!(tree.elements().all(|(element_key, _)| tree.get(element_key).is_some()));
+    }
+
+    #[test]
+    fn random_elements_are_found_by_descending() {
+        for seed in 0..8 {
+            let coordinates = random_points(1000, seed);
+
+            for tree in [inserted(&coordinates), built(&coordinates)] {
+                assert_invariants(&tree);
+                for (element_key, element) in tree.elements() {
+                    let leaf_key = find_leaf(&tree, element.position()).unwrap();
+                    let Some(QuadtreeNode { child_key: QuadtreeChild::Elements(element_keys), .. }) =
+                        &tree.nodes()[leaf_key]
+                    else {
+                        panic!("Descending must end at a leaf");
+                    };
+                    assert!(element_keys.contains(&element_key));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn keys_are_tagged_by_kind() {
+        assert_eq!(NodeKey::try_from_index(3).unwrap().to_string(), "NODE3");
+        assert_eq!(ElementKey::try_from_index(3).unwrap().to_string(), "ELEM3");
+    }
+
+    #[test]
+    fn keys_round_trip_through_their_index() {
+        for index in [0, 1, 1000, u32::MAX as usize - 1] {
+            assert_eq!(NodeKey::try_from_index(index).unwrap().to_index(), index);
+            assert_eq!(ElementKey::try_from_index(index).unwrap().to_index(), index);
+        }
+
+        // the keys store the index plus one, which doesn't fit for the largest index
+        assert!(NodeKey::try_from_index(u32::MAX as usize).is_err());
+        assert!(ElementKey::try_from_index(u32::MAX as usize).is_err());
+    }
+
+    #[test]
+    fn elements_at_the_center_are_kept() {
+        let origin = [(0.0, 0.0); 5];
+
+        let mut inserted = Quadtree::<Point, ()>::new(1.0);
+        let keys = points(&origin).map(|point| inserted.insert(point).unwrap()).collect::<Vec<_>>();
+
+        let mut built = Quadtree::<Point, ()>::new(1.0);
+        built.build_from(points(&origin));
+
+        for tree in [&inserted, &built] {
+            assert_eq!(tree.len(), origin.len());
+            assert_eq!(leaf_elements(tree).len(), origin.len());
+            for &key in &keys {
+                assert_eq!(tree.get(key).unwrap().0, Point2::new(0.0, 0.0));
+            }
+        }
+    }
+
+    #[test]
+    fn splitting_stops_at_max_depth() {
+        // far closer than f32::EPSILON, but still separable until the extent underflows
+        let close = [(1e-30, 1e-30), (2e-30, 2e-30)];
+
+        let mut tree = Quadtree::<Point, ()>::new(1.0);
+        tree.set_center_epsilon(0.0);
+        for point in points(&close) {
+            tree.insert(point).unwrap();
+        }
+
+        let stats = tree.stats();
+        assert_eq!(stats.max_depth, MAX_DEPTH);
+        assert_eq!(stats.leaf_count, 1);
+        assert_eq!(stats.element_count, close.len());
+    }
 }
@@ -0,0 +1,70 @@
+use std::fs::File;
+use std::io::{
+    BufReader,
+    BufWriter,
+};
+use std::path::Path;
+
+use thiserror::Error;
+
+use super::quadtree::Quadtree;
+use super::{
+    Body,
+    SimFloat,
+    Simulation,
+};
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("Couldn't access snapshot file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Couldn't encode snapshot: {0}")]
+    Encode(#[from] bincode::error::EncodeError),
+    #[error("Couldn't decode snapshot: {0}")]
+    Decode(#[from] bincode::error::DecodeError),
+}
+
+/// Everything needed to restore a [Simulation]. The quadtree isn't stored since it is rebuilt every step.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct SimulationSnapshot {
+    pub bodies: Vec<Body>,
+    pub pseudobody_threshold: SimFloat,
+    pub softening: SimFloat,
+    pub extent: SimFloat,
+}
+
+impl Simulation {
+    /// Captures the current bodies and parameters. Bodies are stored in key order, so restoring the
+    /// snapshot hands out the same keys again as long as no bodies have been removed.
+    pub fn snapshot(&self) -> SimulationSnapshot {
+        SimulationSnapshot {
+            bodies: self.bodies.values().cloned().collect(),
+            pseudobody_threshold: self.pseudobody_threshold,
+            softening: self.softening,
+            extent: self.quadtree.extent(),
+        }
+    }
+
+    pub fn from_snapshot(snapshot: SimulationSnapshot) -> Self {
+        let mut simulation =
+            Self::new(snapshot.bodies.into_iter(), snapshot.pseudobody_threshold).with_softening(snapshot.softening);
+        simulation.quadtree = Quadtree::new(snapshot.extent);
+        simulation.flat_quadtree = Default::default();
+        simulation
+    }
+
+    pub fn save(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<(), SnapshotError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        bincode::serde::encode_into_std_write(self.snapshot(), &mut writer, bincode::config::standard())?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let snapshot = bincode::serde::decode_from_std_read(&mut reader, bincode::config::standard())?;
+        Ok(Self::from_snapshot(snapshot))
+    }
+}
@@ -0,0 +1,31 @@
+// fullscreen passes used for drawing trails and screenshots: fading the persistent trail texture and
+// copying an offscreen texture to the surface
+
+@group(0) @binding(0)
+var source_texture: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+// a single triangle covering the whole screen
+@vertex
+fn vs_fullscreen(
+    @builtin(vertex_index) index: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+    let position = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(position * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+// the blend constant scales what has been drawn before, including its alpha
+@fragment
+fn fs_fade(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(0.0, 0.0, 0.0, 0.0);
+}
+
+@fragment
+fn fs_blit(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureLoad(source_texture, vec2<i32>(in.clip_position.xy), 0);
+}
@@ -1 +1,49 @@
+use crate::simulation::SimFloat;
+
 pub mod index_map;
+pub mod rolling_average;
+pub mod triple_buffer;
+
+/// The Barnes-Hut opening criterion: whether a node whose square has side length `node_width` may be
+/// replaced by its pseudobody, which is `dist_to_com` away from the body the force is calculated for.
+/// This is the case if `node_width / dist_to_com` is strictly below the opening angle `theta`. Nodes
+/// at zero distance are never approximated, since the body might lie inside of them.
+pub fn should_approximate(
+    node_width: SimFloat,
+    dist_to_com: SimFloat,
+    theta: SimFloat,
+) -> bool {
+    if dist_to_com <= 0.0 {
+        return false;
+    }
+
+    // same as node_width / dist_to_com < theta without the division
+    node_width < theta * dist_to_com
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn approximates_far_nodes() {
+        assert!(should_approximate(1.0, 10.0, 0.5));
+    }
+
+    #[test]
+    fn recurses_into_near_nodes() {
+        assert!(!should_approximate(1.0, 1.5, 0.5));
+    }
+
+    #[test]
+    fn recurses_at_exactly_the_opening_angle() {
+        assert!(!should_approximate(1.0, 2.0, 0.5));
+        assert!(should_approximate(1.0, 2.0 + 1e-6, 0.5));
+    }
+
+    #[test]
+    fn never_approximates_at_zero_distance() {
+        assert!(!should_approximate(0.0, 0.0, 0.5));
+        assert!(!should_approximate(1.0, 0.0, SimFloat::INFINITY));
+    }
+}
@@ -6,7 +6,6 @@ use std::marker::PhantomData;
 use std::ops::{
     Index,
     IndexMut,
-    Range,
 };
 use std::slice::Iter;
 use std::vec::IntoIter;
@@ -23,10 +22,22 @@ pub enum IndexMapError {
 /// It is highly discouraged to manually implement this trait.
 #[allow(private_bounds)]
 pub trait MapKey: Copy + PartialEq + Eq + PartialOrd + Ord + Hash {
-    /// Tries to create a new key from the provided index. If Index is the maximum value, this will result
-    /// in an error.
-    fn try_from_index(index: usize) -> Result<Self, IndexMapError>;
+    /// Tries to create a new key from the provided index and generation. If Index is the maximum value, this
+    /// will result in an error.
+    fn try_from_parts(
+        index: usize,
+        generation: u32,
+    ) -> Result<Self, IndexMapError>;
     fn to_index(&self) -> usize;
+    /// How often the slot of the key has been reused. Keys of the same slot but a different generation
+    /// don't access each other's values.
+    fn generation(&self) -> u32;
+
+    /// Tries to create a new key of the first generation from the provided index. The key isn't checked
+    /// against any map, see [PrimaryMap::key_at] for a key that is known to exist.
+    fn try_from_index(index: usize) -> Result<Self, IndexMapError> {
+        Self::try_from_parts(index, 0)
+    }
 }
 
 #[macro_export(local_inner_macros)]
@@ -38,8 +49,10 @@ macro_rules! new_map_key {
         $(#[$outer])*
         #[derive(Debug, Eq, PartialEq, PartialOrd, Ord, Copy, Clone, Hash)]
         #[derive(serde::Serialize, serde::Deserialize)]
-        #[repr(transparent)]
-        $vis struct $name(std::num::NonZero<usize>);
+        $vis struct $name {
+            index: std::num::NonZero<usize>,
+            generation: u32,
+        }
 
         map_key_display!($name, $display_prefix);
         map_key_trait!($name, usize::try_from);
@@ -52,8 +65,10 @@ macro_rules! new_map_key {
     ( $(#[$outer:meta])* $vis:vis struct $name:ident; $display_prefix:literal;) => {
         $(#[$outer])*
         #[derive(Debug, Eq, PartialEq, PartialOrd, Ord, Copy, Clone, Hash)]
-        #[repr(transparent)]
-        $vis struct $name(std::num::NonZero<usize>);
+        $vis struct $name {
+            index: std::num::NonZero<usize>,
+            generation: u32,
+        }
 
         map_key_display!($name, $display_prefix);
         map_key_trait!($name, usize::try_from);
@@ -63,15 +78,17 @@ macro_rules! new_map_key {
 #[macro_export(local_inner_macros)]
 /// Creates a new key type for indexing [PrimaryMap] and [SecondaryMap].
 /// The keys are guaranteed to be non-zero, facilitating optimizations when using e.g. [Option]
-/// The keys generated by this macro have a size of 16 bit.
+/// The keys generated by this macro have an 8 bit index and a 32 bit generation, 64 bit with padding.
 #[cfg(feature = "serde")]
 macro_rules! new_map_key_8 {
     ( $(#[$outer:meta])* $vis:vis struct $name:ident; $display_prefix:literal;) => {
         $(#[$outer])*
         #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash)]
         #[derive(serde::Serialize, serde::Deserialize)]
-        #[repr(transparent)]
-        $vis struct $name(std::num::NonZero<u8>);
+        $vis struct $name {
+            index: std::num::NonZero<u8>,
+            generation: u32,
+        }
 
         map_key_display!($name, $display_prefix);
         map_key_trait!($name, u8::try_from);
@@ -83,8 +100,10 @@ macro_rules! new_map_key_8 {
     ( $(#[$outer:meta])* $vis:vis struct $name:ident; $display_prefix:literal;) => {
         $(#[$outer])*
         #[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
-        #[repr(transparent)]
-        $vis struct $name(std::num::NonZero<u8>);
+        $vis struct $name {
+            index: std::num::NonZero<u8>,
+            generation: u32,
+        }
 
         map_key_display!($name, $display_prefix);
         map_key_trait!($name, u8::try_from);
@@ -94,15 +113,17 @@ macro_rules! new_map_key_8 {
 #[macro_export(local_inner_macros)]
 /// Creates a new key type for indexing [PrimaryMap] and [SecondaryMap].
 /// The keys are guaranteed to be non-zero, facilitating optimizations when using e.g. [Option]
-/// The keys generated by this macro have a size of 16 bit.
+/// The keys generated by this macro have a 16 bit index and a 32 bit generation, 64 bit with padding.
 #[cfg(feature = "serde")]
 macro_rules! new_map_key_16 {
     ( $(#[$outer:meta])* $vis:vis struct $name:ident; $display_prefix:literal;) => {
         $(#[$outer])*
         #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash)]
         #[derive(serde::Serialize, serde::Deserialize)]
-        #[repr(transparent)]
-        $vis struct $name(std::num::NonZero<u16>);
+        $vis struct $name {
+            index: std::num::NonZero<u16>,
+            generation: u32,
+        }
 
         map_key_display!($name, $display_prefix);
         map_key_trait!($name, u16::try_from);
@@ -115,22 +136,46 @@ macro_rules! new_map_key_16 {
     ( $(#[$outer:meta])* $vis:vis struct $name:ident; $display_prefix:literal;) => {
         $(#[$outer])*
         #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash)]
-        #[repr(transparent)]
-        $vis struct $name(std::num::NonZero<u16>);
+        $vis struct $name {
+            index: std::num::NonZero<u16>,
+            generation: u32,
+        }
 
         map_key_display!($name, $display_prefix);
         map_key_trait!($name, u16::try_from);
     };
 }
 
+#[macro_export(local_inner_macros)]
+/// Creates a new key type for indexing [PrimaryMap] and [SecondaryMap].
+/// The keys are guaranteed to be non-zero, facilitating optimizations when using e.g. [Option]
+/// The keys generated by this macro have a 32 bit index and a 32 bit generation, 64 bit in total.
+#[cfg(feature = "serde")]
+macro_rules! new_map_key_32 {
+    ( $(#[$outer:meta])* $vis:vis struct $name:ident; $display_prefix:literal;) => {
+        $(#[$outer])*
+        #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash)]
+        #[derive(serde::Serialize, serde::Deserialize)]
+        $vis struct $name {
+            index: std::num::NonZero<u32>,
+            generation: u32,
+        }
+
+        map_key_display!($name, $display_prefix);
+        map_key_trait!($name, u32::try_from);
+    };
+}
+
 #[macro_export(local_inner_macros)]
 #[cfg(not(feature = "serde"))]
 macro_rules! new_map_key_32 {
     ( $(#[$outer:meta])* $vis:vis struct $name:ident; $display_prefix:literal;) => {
         $(#[$outer])*
         #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash)]
-        #[repr(transparent)]
-        $vis struct $name(std::num::NonZero<u32>);
+        $vis struct $name {
+            index: std::num::NonZero<u32>,
+            generation: u32,
+        }
 
         map_key_display!($name, $display_prefix);
         map_key_trait!($name, u32::try_from);
@@ -145,7 +190,7 @@ macro_rules! map_key_display {
                 &self,
                 f: &mut std::fmt::Formatter<'_>,
             ) -> std::fmt::Result {
-                std::write!(f, "{}{}", $display_prefix, self.0.get() - 1)
+                std::write!(f, "{}{}", $display_prefix, self.index.get() - 1)
             }
         }
     };
@@ -154,43 +199,119 @@ macro_rules! map_key_display {
 macro_rules! map_key_trait {
     ($name:ident, $backing_type_conversion:path) => {
         impl MapKey for $name {
-            fn try_from_index(index: usize) -> Result<Self, crate::utility::index_map::IndexMapError> {
+            fn try_from_parts(
+                index: usize,
+                generation: u32,
+            ) -> Result<Self, $crate::utility::index_map::IndexMapError> {
                 std::num::NonZero::new(
                     $backing_type_conversion(index + 1)
-                        .map_err(|e| crate::utility::index_map::IndexMapError::InvalidIndex(index))?,
+                        .map_err(|_| $crate::utility::index_map::IndexMapError::InvalidIndex(index))?,
                 )
-                .ok_or(crate::utility::index_map::IndexMapError::InvalidIndex(index))
-                .map(Self)
+                .ok_or($crate::utility::index_map::IndexMapError::InvalidIndex(index))
+                .map(|index| Self { index, generation })
             }
             fn to_index(&self) -> usize {
-                // This is safe, since self.0 is never zero
-                unsafe { usize::try_from(self.0.get().unchecked_sub(1)).unwrap() }
+                // This is safe, since self.index is never zero
+                unsafe { usize::try_from(self.index.get().unchecked_sub(1)).unwrap() }
+            }
+            fn generation(&self) -> u32 {
+                self.generation
             }
         }
     };
 }
 
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// Storage of a single value in a [PrimaryMap] or [SecondaryMap], only exposed through the types of the
+/// [PrimaryMap]'s iterators.
+pub struct Slot<V> {
+    generation: u32,
+    value: Option<V>,
+}
+
+/// Map which hands out a new key for every inserted value. Slots of removed values are reused by later
+/// insertions with a new generation, so keys of removed values never access the values inserted after them.
+/// All iterators visit the entries in ascending index order, so the iteration order is stable as long as
+/// no entries are added or removed.
 #[derive(Debug)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PrimaryMap<K: MapKey, V> {
-    data: Vec<V>,
+    slots: Vec<Slot<V>>,
+    // indices of empty slots, the last one is reused first
+    free: Vec<usize>,
+    len: usize,
     phantom_data: PhantomData<K>,
 }
 
 impl<K: MapKey, V> PrimaryMap<K, V> {
     pub fn with_capacity(capacity: usize) -> Self {
         Self {
-            data: Vec::with_capacity(capacity),
+            slots: Vec::with_capacity(capacity),
+            free: Vec::new(),
+            len: 0,
             phantom_data: Default::default(),
         }
     }
 
     pub fn shrink_to_fit(&mut self) {
-        self.data.shrink_to_fit();
+        self.slots.shrink_to_fit();
+        self.free.shrink_to_fit();
+    }
+
+    /// Removes all values while keeping the allocated capacity. Keys start at the first index again, but the
+    /// slots keep counting their generations, so keys handed out before don't access the new values.
+    pub fn clear(&mut self) {
+        self.free.clear();
+        // in reverse, so the lowest index is reused first like in an empty map
+        for (index, slot) in self.slots.iter_mut().enumerate().rev() {
+            if slot.value.take().is_some() {
+                slot.generation = slot.generation.wrapping_add(1);
+            }
+            self.free.push(index);
+        }
+        self.len = 0;
+    }
+
+    /// Removes all values for which `keep` returns false and returns how many were removed. Keys of the
+    /// remaining values stay valid.
+    pub fn retain(
+        &mut self,
+        mut keep: impl FnMut(K, &V) -> bool,
+    ) -> usize {
+        let len = self.len;
+        for index in 0..self.slots.len() {
+            let slot = &self.slots[index];
+            let Some(value) = &slot.value else {
+                continue;
+            };
+
+            let key = K::try_from_parts(index, slot.generation).expect("Enumerating must produce a valid index");
+            if !keep(key, value) {
+                self.remove(key);
+            }
+        }
+
+        len - self.len
     }
+
     /// Gets the next key that will be used when inserting a value.
     pub fn next_key(&self) -> K {
-        K::try_from_index(self.data.len()).expect("Index out of range")
+        match self.free.last() {
+            Some(&index) => K::try_from_parts(index, self.slots[index].generation).expect("Index out of range"),
+            None => K::try_from_index(self.slots.len()).expect("Index out of range"),
+        }
+    }
+
+    /// The key of the value at the index with its current generation, or [None] if the index is past the end
+    /// of the map or its value has been removed.
+    pub fn key_at(
+        &self,
+        index: usize,
+    ) -> Option<K> {
+        let slot = self.slots.get(index)?;
+        slot.value.as_ref()?;
+        K::try_from_parts(index, slot.generation).ok()
     }
 
     pub fn insert(
@@ -198,84 +319,175 @@ impl<K: MapKey, V> PrimaryMap<K, V> {
         value: V,
     ) -> K {
         let key = self.next_key();
-        self.data.push(value);
+        match self.free.pop() {
+            Some(index) => self.slots[index].value = Some(value),
+            None => self.slots.push(Slot {
+                generation: 0,
+                value: Some(value),
+            }),
+        }
+
+        self.len += 1;
         key
     }
 
+    /// Removes the value of the key and returns it, or returns [None] if the key has already been removed.
+    /// The slot is reused by a later insertion with a key of the next generation.
+    pub fn remove(
+        &mut self,
+        key: K,
+    ) -> Option<V> {
+        let index = key.to_index();
+        let slot = self.slots.get_mut(index).filter(|slot| slot.generation == key.generation())?;
+        let value = slot.value.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+
+        self.free.push(index);
+        self.len -= 1;
+        Some(value)
+    }
+
+    pub fn contains_key(
+        &self,
+        key: K,
+    ) -> bool {
+        self.get(key).is_some()
+    }
+
     pub fn get(
         &self,
         key: K,
     ) -> Option<&V> {
-        self.data.get(key.to_index())
+        self.slots
+            .get(key.to_index())
+            .filter(|slot| slot.generation == key.generation())
+            .and_then(|slot| slot.value.as_ref())
     }
 
     pub fn get_mut(
         &mut self,
         key: K,
     ) -> Option<&mut V> {
-        self.data.get_mut(key.to_index())
+        self.slots
+            .get_mut(key.to_index())
+            .filter(|slot| slot.generation == key.generation())
+            .and_then(|slot| slot.value.as_mut())
     }
 
     pub fn len(&self) -> usize {
-        self.data.len()
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
     }
 
     pub fn first(&self) -> Option<&V> {
-        self.data.first()
+        self.values().next()
     }
 
     pub fn last(&self) -> Option<&V> {
-        self.data.last()
+        self.values().next_back()
     }
 
-    pub fn keys(&self) -> impl ExactSizeIterator<Item = K> + DoubleEndedIterator + use<K, V> {
-        (0..self.data.len())
-            .map(MapKey::try_from_index)
-            .map(Result::unwrap)
+    pub fn keys(&self) -> impl ExactSizeIterator<Item = K> + DoubleEndedIterator {
+        self.items().map(|(k, _)| k)
     }
 
     pub fn values(&self) -> impl ExactSizeIterator<Item = &V> + DoubleEndedIterator {
-        self.data.iter()
+        Occupied::new(self.slots.iter().filter_map(|slot| slot.value.as_ref()), self.len)
     }
 
     pub fn values_mut(&mut self) -> impl ExactSizeIterator<Item = &mut V> + DoubleEndedIterator {
-        self.data.iter_mut()
+        Occupied::new(self.slots.iter_mut().filter_map(|slot| slot.value.as_mut()), self.len)
     }
 
     pub fn items(&self) -> impl ExactSizeIterator<Item = (K, &V)> + DoubleEndedIterator {
-        self.data
-            .iter()
-            .enumerate()
-            .map(|(k, v)| (MapKey::try_from_index(k).expect("Enumarating must produce a valid index"), v))
+        Occupied::new(self.slots.iter().enumerate().filter_map(slot_item), self.len)
+    }
+
+    #[cfg(feature = "parallel")]
+    pub fn par_items(&self) -> impl rayon::iter::ParallelIterator<Item = (K, &V)>
+    where
+        K: Send,
+        V: Sync,
+    {
+        use rayon::prelude::*;
+
+        self.slots.par_iter().enumerate().filter_map(slot_item)
     }
 
     pub fn items_mut(&mut self) -> impl Iterator<Item = (K, &mut V)> {
-        self.data
-            .iter_mut()
-            .enumerate()
-            .map(|(k, v)| (MapKey::try_from_index(k).expect("Enumarating must produce a valid index"), v))
+        self.slots.iter_mut().enumerate().filter_map(|(index, slot)| {
+            let key = K::try_from_parts(index, slot.generation).expect("Enumarating must produce a valid index");
+            slot.value.as_mut().map(|value| (key, value))
+        })
     }
 
     pub fn into_items(self) -> impl Iterator<Item = (K, V)> {
-        self.data
-            .into_iter()
-            .enumerate()
-            .map(|(k, v)| (MapKey::try_from_index(k).expect("Enumerating must produce a valid index"), v))
+        self.into_iter()
+    }
+}
+
+fn slot_item<K: MapKey, V>((index, slot): (usize, &Slot<V>)) -> Option<(K, &V)> {
+    let key = K::try_from_parts(index, slot.generation).expect("Enumarating must produce a valid index");
+    slot.value.as_ref().map(|value| (key, value))
+}
+
+fn owned_slot_item<K: MapKey, V>((index, slot): (usize, Slot<V>)) -> Option<(K, V)> {
+    let key = K::try_from_parts(index, slot.generation).expect("Enumerating must produce a valid index");
+    slot.value.map(|value| (key, value))
+}
+
+// skips empty slots while still knowing how many values are left, since the map counts them
+struct Occupied<I> {
+    iter: I,
+    remaining: usize,
+}
+
+impl<I> Occupied<I> {
+    fn new(
+        iter: I,
+        remaining: usize,
+    ) -> Self {
+        Self { iter, remaining }
+    }
+}
+
+impl<I: Iterator> Iterator for Occupied<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.iter.next()?;
+        self.remaining -= 1;
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
     }
 }
 
+impl<I: DoubleEndedIterator> DoubleEndedIterator for Occupied<I> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let item = self.iter.next_back()?;
+        self.remaining -= 1;
+        Some(item)
+    }
+}
+
+impl<I: Iterator> ExactSizeIterator for Occupied<I> {}
+
 impl<K, V> IntoIterator for PrimaryMap<K, V>
 where
     K: MapKey,
 {
     type Item = (K, V);
-    type IntoIter = std::iter::Map<std::iter::Enumerate<IntoIter<V>>, fn((usize, V)) -> (K, V)>;
+    type IntoIter =
+        std::iter::FilterMap<std::iter::Enumerate<IntoIter<Slot<V>>>, fn((usize, Slot<V>)) -> Option<(K, V)>>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.data
-            .into_iter()
-            .enumerate()
-            .map(|(k, v)| (MapKey::try_from_index(k).expect("Enumerating must produce a valid index"), v))
+        self.slots.into_iter().enumerate().filter_map(owned_slot_item)
     }
 }
 
@@ -285,29 +497,26 @@ where
 {
     type Item = (K, &'a V);
     // TODO: Replace type with ATIT once https://github.com/rust-lang/rust/issues/63063 is stable
-    type IntoIter = std::iter::Map<std::iter::Enumerate<Iter<'a, V>>, fn((usize, &'a V)) -> (K, &'a V)>;
+    type IntoIter =
+        std::iter::FilterMap<std::iter::Enumerate<Iter<'a, Slot<V>>>, fn((usize, &'a Slot<V>)) -> Option<(K, &'a V)>>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.data
-            .iter()
-            .enumerate()
-            .map(|(k, v)| (MapKey::try_from_index(k).expect("Enumerating must produce a valid index"), v))
+        self.slots.iter().enumerate().filter_map(slot_item)
     }
 }
 
 impl<K: MapKey, V> Default for PrimaryMap<K, V> {
     fn default() -> Self {
-        Self {
-            data: Default::default(),
-            phantom_data: Default::default(),
-        }
+        Self::with_capacity(0)
     }
 }
 
 impl<K: MapKey, V: Clone> Clone for PrimaryMap<K, V> {
     fn clone(&self) -> Self {
         Self {
-            data: self.data.clone(),
+            slots: self.slots.clone(),
+            free: self.free.clone(),
+            len: self.len,
             phantom_data: Default::default(),
         }
     }
@@ -316,12 +525,12 @@ impl<K: MapKey, V: Clone> Clone for PrimaryMap<K, V> {
 impl<K: MapKey, V> Index<K> for PrimaryMap<K, V> {
     type Output = V;
 
+    /// Panics if the value of the key has been removed.
     fn index(
         &self,
         key: K,
     ) -> &Self::Output {
-        // index - 1 is fine, since index is guaranteed to be non-zero
-        &self.data[key.to_index()]
+        self.get(key).expect("Key must not have been removed")
     }
 }
 
@@ -330,33 +539,7 @@ impl<K: MapKey, V> IndexMut<K> for PrimaryMap<K, V> {
         &mut self,
         key: K,
     ) -> &mut Self::Output {
-        &mut self.data[key.to_index()]
-    }
-}
-
-impl<K: MapKey, V> Index<Range<K>> for PrimaryMap<K, V> {
-    type Output = [V];
-
-    fn index(
-        &self,
-        key: Range<K>,
-    ) -> &Self::Output {
-        // index - 1 is fine, since index is guaranteed to be non-zero
-        let index_start = key.start.to_index();
-        let index_end = key.end.to_index();
-        &self.data[index_start..index_end]
-    }
-}
-
-impl<K: MapKey, V> IndexMut<Range<K>> for PrimaryMap<K, V> {
-    fn index_mut(
-        &mut self,
-        key: Range<K>,
-    ) -> &mut Self::Output {
-        // index - 1 is fine, since index is guaranteed to be non-zero
-        let index_start = key.start.to_index();
-        let index_end = key.end.to_index();
-        &mut self.data[index_start..index_end]
+        self.get_mut(key).expect("Key must not have been removed")
     }
 }
 
@@ -374,16 +557,28 @@ impl<K: MapKey, V> FromIterator<(K, V)> for PrimaryMap<K, V> {
         }
 
         Self {
-            data: collected.into_iter().map(|(_, v)| v).collect_vec(),
+            len: collected.len(),
+            slots: collected
+                .into_iter()
+                .map(|(k, v)| Slot {
+                    generation: k.generation(),
+                    value: Some(v),
+                })
+                .collect_vec(),
+            free: Vec::new(),
             phantom_data: Default::default(),
         }
     }
 }
 
+/// Associates values with keys handed out by a [PrimaryMap] without owning the keys. Not every key needs a
+/// value, the storage only grows as far as the largest key that has one.
+/// Indexing with a key that has no value returns the default value instead of panicking. Values are stored
+/// with the generation of their key, so keys of a reused slot don't access the values of the previous keys.
 #[derive(Debug)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SecondaryMap<K: MapKey, V> {
-    data: Vec<V>,
+    data: Vec<Slot<V>>,
     default: V,
     phantom_data: PhantomData<K>,
 }
@@ -426,7 +621,7 @@ where
         &self,
         index: K,
     ) -> &Self::Output {
-        self.data.get(index.to_index()).unwrap_or(&self.default)
+        self.get(index).unwrap_or(&self.default)
     }
 }
 
@@ -435,17 +630,17 @@ where
     K: MapKey,
     V: Default,
 {
-    /// Access to the secondary map causes the underlying vector to reallocate to fit the provided index.
-    /// The secondary map will be at most as large as the original map, if only valid keys are used.
+    /// Inserts the default value if the key has no value yet.
     fn index_mut(
         &mut self,
-        index: K,
+        key: K,
     ) -> &mut Self::Output {
-        let index = index.to_index();
-        if index >= self.data.len() {
-            self.data.resize_with(index + 1, Default::default);
+        let slot = self.slot_mut(key);
+        if slot.generation != key.generation() {
+            slot.generation = key.generation();
+            slot.value = None;
         }
-        &mut self.data[index]
+        slot.value.get_or_insert_with(Default::default)
     }
 }
 
@@ -453,18 +648,103 @@ impl<K, V> SecondaryMap<K, V>
 where
     K: MapKey,
 {
+    /// Creates an empty map that returns `default` when indexed with a key that has no value, for values
+    /// that don't implement [Default].
+    pub fn with_default(default: V) -> Self {
+        Self {
+            data: Vec::new(),
+            default,
+            phantom_data: Default::default(),
+        }
+    }
+
+    pub fn contains_key(
+        &self,
+        key: K,
+    ) -> bool {
+        self.get(key).is_some()
+    }
+
     pub fn get(
         &self,
-        index: &K,
+        key: K,
     ) -> Option<&V> {
-        self.data.get(index.to_index())
+        self.data
+            .get(key.to_index())
+            .filter(|slot| slot.generation == key.generation())
+            .and_then(|slot| slot.value.as_ref())
     }
 
-    pub fn items(&self) -> impl Iterator<Item = (K, &V)> {
+    pub fn get_mut(
+        &mut self,
+        key: K,
+    ) -> Option<&mut V> {
         self.data
-            .iter()
-            .enumerate()
-            .map(|(k, v)| (MapKey::try_from_index(k).expect("Enumarating must produce a valid index"), v))
+            .get_mut(key.to_index())
+            .filter(|slot| slot.generation == key.generation())
+            .and_then(|slot| slot.value.as_mut())
+    }
+
+    /// Sets the value of the key and returns the previous one. A value of an older key of the same slot is
+    /// dropped instead of returned.
+    pub fn insert(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> Option<V> {
+        let slot = self.slot_mut(key);
+        let previous = slot.value.replace(value).filter(|_| slot.generation == key.generation());
+        slot.generation = key.generation();
+        previous
+    }
+
+    /// Removes the value of the key and returns it. Trailing empty slots are freed.
+    pub fn remove(
+        &mut self,
+        key: K,
+    ) -> Option<V> {
+        let value = self
+            .data
+            .get_mut(key.to_index())
+            .filter(|slot| slot.generation == key.generation())?
+            .value
+            .take();
+        while self.data.last().is_some_and(|slot| slot.value.is_none()) {
+            self.data.pop();
+        }
+
+        value
+    }
+
+    // the slot of the key's index with any generation, growing the storage if it doesn't exist yet
+    fn slot_mut(
+        &mut self,
+        key: K,
+    ) -> &mut Slot<V> {
+        let index = key.to_index();
+        if index >= self.data.len() {
+            self.data.resize_with(index + 1, || Slot {
+                generation: 0,
+                value: None,
+            });
+        }
+        &mut self.data[index]
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+
+    /// Iterates the keys that have a value in ascending order.
+    pub fn items(&self) -> impl Iterator<Item = (K, &V)> {
+        self.data.iter().enumerate().filter_map(slot_item)
+    }
+
+    pub fn items_mut(&mut self) -> impl Iterator<Item = (K, &mut V)> {
+        self.data.iter_mut().enumerate().filter_map(|(index, slot)| {
+            let key = K::try_from_parts(index, slot.generation).expect("Enumarating must produce a valid index");
+            slot.value.as_mut().map(|value| (key, value))
+        })
     }
 }
 
@@ -519,3 +799,106 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    new_map_key! { struct TestKey; "TEST"; }
+    new_map_key_16! { struct TestKey16; "TEST"; }
+    new_map_key_32! { struct TestKey32; "TEST"; }
+
+    #[test]
+    fn keys_have_the_documented_size() {
+        assert_eq!(std::mem::size_of::<TestKey16>(), 8);
+        assert_eq!(std::mem::size_of::<TestKey32>(), 8);
+        // the index is non-zero, so options of keys don't need any extra space
+        assert_eq!(std::mem::size_of::<Option<TestKey32>>(), 8);
+    }
+
+    #[test]
+    fn reinserting_reuses_the_slot_with_a_new_generation() {
+        let mut map = PrimaryMap::<TestKey, &str>::default();
+        let first = map.insert("first");
+        let second = map.insert("second");
+
+        assert_eq!(map.remove(first), Some("first"));
+        assert_eq!(map.remove(first), None);
+        assert_eq!(map.len(), 1);
+
+        let reinserted = map.insert("reinserted");
+        assert_eq!(reinserted.to_index(), first.to_index());
+        assert_ne!(reinserted, first);
+        assert_eq!(map[reinserted], "reinserted");
+        assert_eq!(map[second], "second");
+        assert!(map.items().map(|(key, _)| key).eq([reinserted, second]));
+    }
+
+    #[test]
+    fn stale_primary_keys_return_none() {
+        let mut map = PrimaryMap::<TestKey, &str>::default();
+        let stale = map.insert("removed");
+        map.remove(stale);
+        map.insert("reinserted");
+
+        assert_eq!(map.get(stale), None);
+        assert_eq!(map.get_mut(stale), None);
+        assert!(!map.contains_key(stale));
+        assert_eq!(map.remove(stale), None);
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn stale_secondary_keys_return_none() {
+        let mut primary = PrimaryMap::<TestKey, ()>::default();
+        let mut secondary = SecondaryMap::<TestKey, u32>::default();
+
+        let stale = primary.insert(());
+        secondary.insert(stale, 1);
+        primary.remove(stale);
+        let reinserted = primary.insert(());
+
+        assert_eq!(secondary.get(reinserted), None);
+        assert_eq!(secondary[reinserted], 0);
+        assert_eq!(secondary.remove(reinserted), None);
+
+        // a value of the new key replaces the stale one without returning it
+        assert_eq!(secondary.insert(reinserted, 2), None);
+        assert_eq!(secondary.get(stale), None);
+        assert_eq!(secondary.remove(stale), None);
+        assert_eq!(secondary[reinserted], 2);
+        assert!(secondary.items().eq([(reinserted, &2)]));
+    }
+
+    #[test]
+    fn secondary_index_mut_replaces_stale_values() {
+        let mut primary = PrimaryMap::<TestKey, ()>::default();
+        let mut secondary = SecondaryMap::<TestKey, u32>::default();
+
+        let stale = primary.insert(());
+        secondary[stale] += 5;
+        primary.remove(stale);
+        let reinserted = primary.insert(());
+
+        secondary[reinserted] += 1;
+        assert_eq!(secondary[reinserted], 1);
+        assert_eq!(secondary.get(stale), None);
+    }
+
+    #[test]
+    fn clearing_keeps_old_keys_from_accessing_new_values() {
+        let mut map = PrimaryMap::<TestKey, &str>::default();
+        let old = [map.insert("first"), map.insert("second")];
+        map.clear();
+        assert!(map.is_empty());
+
+        let new = [map.insert("third"), map.insert("fourth")];
+        for (old, new) in old.into_iter().zip(new) {
+            // the indices start at the front again, only the generation tells the keys apart
+            assert_eq!(old.to_index(), new.to_index());
+            assert_eq!(map.get(old), None);
+        }
+        assert_eq!(map[new[0]], "third");
+        assert!(map.keys().eq(new));
+    }
+}
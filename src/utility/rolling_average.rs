@@ -0,0 +1,76 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Average over the most recent samples of a duration, e.g. a frame time.
+#[derive(Debug, Clone)]
+pub struct RollingAverage {
+    samples: VecDeque<Duration>,
+    window: usize,
+    sum: Duration,
+}
+
+impl RollingAverage {
+    /// Creates an average over the last `window` samples. The window must not be empty.
+    pub fn new(window: usize) -> Self {
+        assert!(window > 0, "Window must contain at least one sample");
+
+        Self {
+            samples: VecDeque::with_capacity(window),
+            window,
+            sum: Duration::ZERO,
+        }
+    }
+
+    /// Adds a sample, evicting the oldest one if the window is full.
+    pub fn push(
+        &mut self,
+        sample: Duration,
+    ) {
+        if self.samples.len() == self.window {
+            let oldest = self.samples.pop_front().expect("Window is full");
+            self.sum -= oldest;
+        }
+
+        self.samples.push_back(sample);
+        self.sum += sample;
+    }
+
+    /// The average of all samples in the window or zero if there are none.
+    pub fn average(&self) -> Duration {
+        if self.samples.is_empty() {
+            return Duration::ZERO;
+        }
+
+        self.sum / u32::try_from(self.samples.len()).unwrap()
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn averages_the_samples_in_the_window() {
+        let mut average = RollingAverage::new(3);
+        assert_eq!(average.average(), Duration::ZERO);
+
+        average.push(Duration::from_millis(10));
+        average.push(Duration::from_millis(20));
+        assert_eq!(average.average(), Duration::from_millis(15));
+
+        // the oldest samples are evicted once the window is full
+        average.push(Duration::from_millis(30));
+        average.push(Duration::from_millis(40));
+        average.push(Duration::from_millis(50));
+        assert_eq!(average.len(), 3);
+        assert_eq!(average.average(), Duration::from_millis(40));
+    }
+}
@@ -0,0 +1,119 @@
+use std::cell::UnsafeCell;
+use std::sync::Arc;
+use std::sync::atomic::{
+    AtomicUsize,
+    Ordering,
+};
+
+// set in the shared index while its slot holds a value the reader hasn't taken yet
+const FRESH: usize = 0b100;
+const INDEX_MASK: usize = 0b011;
+
+// the three slots are owned by the writer, the reader and `back` respectively. only the slot index stored in
+// `back` moves between the sides, neither side ever touches the slot owned by the other one
+struct Shared<T> {
+    slots: [UnsafeCell<T>; 3],
+    back: AtomicUsize,
+}
+
+// the slots are only accessed through the handle owning them, see above
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+/// Hands values from one writer thread to one reader thread without either side ever waiting for the other.
+/// The writer fills its own slot and swaps it with the shared one, the reader swaps its slot with the shared
+/// one if that holds a newer value. Both swaps are a single atomic operation, so there are no locks.
+pub fn triple_buffer<T: Default>() -> (TripleBufferWriter<T>, TripleBufferReader<T>) {
+    let shared = Arc::new(Shared {
+        slots: Default::default(),
+        back: AtomicUsize::new(2),
+    });
+
+    let writer = TripleBufferWriter {
+        shared: shared.clone(),
+        index: 0,
+    };
+    let reader = TripleBufferReader { shared, index: 1 };
+    (writer, reader)
+}
+
+pub struct TripleBufferWriter<T> {
+    shared: Arc<Shared<T>>,
+    index: usize,
+}
+
+impl<T> TripleBufferWriter<T> {
+    /// Fills the writer's slot and publishes it. The slot still holds a value published before, so `fill`
+    /// has to overwrite all of it, but can reuse its allocations.
+    pub fn publish(
+        &mut self,
+        fill: impl FnOnce(&mut T),
+    ) {
+        // safe since the writer owns its slot
+        fill(unsafe { &mut *self.shared.slots[self.index].get() });
+        let back = self.shared.back.swap(self.index | FRESH, Ordering::AcqRel);
+        self.index = back & INDEX_MASK;
+    }
+}
+
+pub struct TripleBufferReader<T> {
+    shared: Arc<Shared<T>>,
+    index: usize,
+}
+
+impl<T> TripleBufferReader<T> {
+    /// The most recently published value, or the default value if none has been published yet.
+    pub fn read(&mut self) -> &T {
+        if self.shared.back.load(Ordering::Relaxed) & FRESH != 0 {
+            let back = self.shared.back.swap(self.index, Ordering::AcqRel);
+            self.index = back & INDEX_MASK;
+        }
+
+        // safe since the reader owns its slot
+        unsafe { &*self.shared.slots[self.index].get() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_latest_value() {
+        let (mut writer, mut reader) = triple_buffer::<u32>();
+        assert_eq!(*reader.read(), 0);
+
+        writer.publish(|value| *value = 1);
+        writer.publish(|value| *value = 2);
+        assert_eq!(*reader.read(), 2);
+        assert_eq!(*reader.read(), 2);
+
+        writer.publish(|value| *value = 3);
+        assert_eq!(*reader.read(), 3);
+    }
+
+    #[test]
+    fn reads_never_go_back() {
+        let (mut writer, mut reader) = triple_buffer::<Vec<u32>>();
+
+        let writer_thread = std::thread::spawn(move || {
+            for i in 1..=10_000 {
+                writer.publish(|value| {
+                    value.clear();
+                    value.extend([i; 16]);
+                });
+            }
+        });
+
+        let mut previous = 0;
+        while previous < 10_000 {
+            let value = reader.read();
+            // a torn value would mix two publishes
+            let latest = value.first().copied().unwrap_or(0);
+            assert!(value.iter().all(|&i| i == latest));
+            assert!(latest >= previous);
+            previous = latest;
+        }
+
+        writer_thread.join().unwrap();
+    }
+}